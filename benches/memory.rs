@@ -0,0 +1,67 @@
+use rustboy::cartridge::Cartridge;
+use rustboy::virtual_memory::{MemoryMappedPeripheral, VirtualMemory};
+use rustboy::watchpoint::{Access, ValueCondition, Watchpoint};
+use std::hint::black_box;
+use std::time::Instant;
+
+const ITERATIONS: usize = 200;
+
+fn bench(name: &str, virtual_memory: &mut VirtualMemory, addresses: &[u16]) {
+    let start = Instant::now();
+
+    for _ in 0..ITERATIONS {
+        for &address in addresses {
+            let data = virtual_memory.read(black_box(address));
+            if (0xc000..0xe000).contains(&address) {
+                virtual_memory.write(address, data.wrapping_add(1));
+            }
+        }
+    }
+
+    let accesses = ITERATIONS * addresses.len();
+    let elapsed = start.elapsed();
+    println!(
+        "{:<16} {:>8.2} ns/access ({} accesses in {:?})",
+        name,
+        elapsed.as_nanos() as f64 / accesses as f64,
+        accesses,
+        elapsed
+    );
+}
+
+fn main() {
+    let rom = std::fs::read("roms/cpu_instrs.gb").expect("roms/cpu_instrs.gb");
+    let mut virtual_memory = VirtualMemory::new(Cartridge::load(&rom));
+
+    let rom_reads = (0x0000..0x8000).collect::<Vec<u16>>();
+    let wram_accesses = (0xc000..0xe000).collect::<Vec<u16>>();
+    let hram_reads = (0xff80..0xffff).cycle().take(0x8000).collect::<Vec<u16>>();
+    // A typical instruction stream: mostly ROM fetches with some WRAM and HRAM traffic.
+    let mixed = (0..0x8000u16)
+        .map(|i| match i % 8 {
+            0..=4 => 0x0150 + i % 0x3e00,
+            5 | 6 => 0xc000 + i % 0x2000,
+            _ => 0xff80 + i % 0x7f,
+        })
+        .collect::<Vec<u16>>();
+
+    let run = |virtual_memory: &mut VirtualMemory, suffix: &str| {
+        bench(&format!("rom{}", suffix), virtual_memory, &rom_reads);
+        bench(&format!("wram{}", suffix), virtual_memory, &wram_accesses);
+        bench(&format!("hram{}", suffix), virtual_memory, &hram_reads);
+        bench(&format!("mixed{}", suffix), virtual_memory, &mixed);
+    };
+    run(&mut virtual_memory, "");
+
+    // Watchpoints over the whole map take every page off the page table, so the same accesses
+    // go through the address decoding. They never match, to leave the hit handling out.
+    let never = ValueCondition::Masked {
+        mask: 0x00,
+        value: 0x01,
+    };
+    for access in [Access::Read, Access::Write] {
+        virtual_memory
+            .add_watchpoint(Watchpoint::new(access, 0x0000..=0xffff).with_condition(never));
+    }
+    run(&mut virtual_memory, " (handler)");
+}
@@ -0,0 +1,658 @@
+use crate::model::Model;
+use crate::virtual_memory::MemoryMappedPeripheral;
+use alloc::vec::Vec;
+
+const DUTY_PATTERNS: [u8; 4] = [0b0000_0001, 0b1000_0001, 0b1000_0111, 0b0111_1110];
+const NOISE_DIVISORS: [i32; 8] = [8, 16, 32, 48, 64, 80, 96, 112];
+
+#[derive(Default)]
+struct Length {
+    counter: u16,
+    enabled: bool,
+}
+
+impl Length {
+    fn load(&mut self, max: u16, value: u8) {
+        self.counter = max - value as u16;
+    }
+
+    fn trigger(&mut self, max: u16) {
+        if self.counter == 0 {
+            self.counter = max;
+        }
+    }
+
+    /// Returns true when the counter expires and the channel has to be disabled.
+    fn step(&mut self) -> bool {
+        if !self.enabled || self.counter == 0 {
+            return false;
+        }
+
+        self.counter -= 1;
+        self.counter == 0
+    }
+}
+
+#[derive(Default)]
+struct Envelope {
+    register: u8,
+    volume: u8,
+    timer: u8,
+}
+
+impl Envelope {
+    fn dac_enabled(&self) -> bool {
+        self.register & 0xf8 != 0
+    }
+
+    fn trigger(&mut self) {
+        self.volume = self.register >> 4;
+        self.timer = self.register & 0x07;
+    }
+
+    fn step(&mut self) {
+        let period = self.register & 0x07;
+        if period == 0 {
+            return;
+        }
+
+        self.timer = self.timer.saturating_sub(1);
+        if self.timer != 0 {
+            return;
+        }
+
+        self.timer = period;
+        if self.register & 0x08 != 0 && self.volume < 15 {
+            self.volume += 1;
+        } else if self.register & 0x08 == 0 && self.volume > 0 {
+            self.volume -= 1;
+        }
+    }
+}
+
+#[derive(Default)]
+struct Sweep {
+    register: u8,
+    enabled: bool,
+    shadow: u16,
+    timer: u8,
+}
+
+impl Sweep {
+    fn period(&self) -> u8 {
+        (self.register >> 4) & 0x07
+    }
+
+    fn shift(&self) -> u8 {
+        self.register & 0x07
+    }
+
+    fn next_frequency(&self) -> u16 {
+        let delta = self.shadow >> self.shift();
+
+        if self.register & 0x08 != 0 {
+            self.shadow - delta
+        } else {
+            self.shadow + delta
+        }
+    }
+
+    fn reload_timer(&mut self) {
+        self.timer = match self.period() {
+            0 => 8,
+            period => period,
+        };
+    }
+}
+
+#[derive(Default)]
+struct Square {
+    enabled: bool,
+    duty: u8,
+    duty_step: u8,
+    length: Length,
+    envelope: Envelope,
+    frequency: u16,
+    timer: i32,
+}
+
+impl Square {
+    const LENGTH: u16 = 64;
+
+    fn period(&self) -> i32 {
+        (2048 - self.frequency as i32) * 4
+    }
+
+    fn trigger(&mut self) {
+        self.enabled = self.envelope.dac_enabled();
+        self.length.trigger(Square::LENGTH);
+        self.envelope.trigger();
+        self.timer = self.period();
+    }
+
+    fn tick(&mut self, cycles: i32) {
+        self.timer -= cycles;
+        while self.timer <= 0 {
+            self.timer += self.period();
+            self.duty_step = (self.duty_step + 1) % 8;
+        }
+    }
+
+    fn output(&self) -> Option<u8> {
+        if !self.envelope.dac_enabled() {
+            return None;
+        }
+
+        let high = DUTY_PATTERNS[self.duty as usize] >> (7 - self.duty_step) & 0x01 != 0;
+        Some(if self.enabled && high {
+            self.envelope.volume
+        } else {
+            0
+        })
+    }
+}
+
+#[derive(Default)]
+struct Wave {
+    enabled: bool,
+    dac_enabled: bool,
+    length: Length,
+    volume_code: u8,
+    frequency: u16,
+    timer: i32,
+    position: u8,
+    ram: [u8; 16],
+}
+
+impl Wave {
+    const LENGTH: u16 = 256;
+
+    fn period(&self) -> i32 {
+        (2048 - self.frequency as i32) * 2
+    }
+
+    fn trigger(&mut self) {
+        self.enabled = self.dac_enabled;
+        self.length.trigger(Wave::LENGTH);
+        self.timer = self.period();
+        self.position = 0;
+    }
+
+    fn tick(&mut self, cycles: i32) {
+        self.timer -= cycles;
+        while self.timer <= 0 {
+            self.timer += self.period();
+            self.position = (self.position + 1) % 32;
+        }
+    }
+
+    fn output(&self) -> Option<u8> {
+        if !self.dac_enabled {
+            return None;
+        }
+
+        let byte = self.ram[self.position as usize / 2];
+        let sample = if self.position.is_multiple_of(2) {
+            byte >> 4
+        } else {
+            byte & 0x0f
+        };
+
+        Some(match (self.enabled, self.volume_code) {
+            (false, _) | (true, 0) => 0,
+            (true, code) => sample >> (code - 1),
+        })
+    }
+}
+
+#[derive(Default)]
+struct Noise {
+    enabled: bool,
+    length: Length,
+    envelope: Envelope,
+    register: u8,
+    timer: i32,
+    lfsr: u16,
+}
+
+impl Noise {
+    const LENGTH: u16 = 64;
+
+    fn period(&self) -> i32 {
+        NOISE_DIVISORS[(self.register & 0x07) as usize] << (self.register >> 4)
+    }
+
+    fn trigger(&mut self) {
+        self.enabled = self.envelope.dac_enabled();
+        self.length.trigger(Noise::LENGTH);
+        self.envelope.trigger();
+        self.timer = self.period();
+        self.lfsr = 0x7fff;
+    }
+
+    fn tick(&mut self, cycles: i32) {
+        // Shifts 14 and 15 stop the clock.
+        if self.register >> 4 >= 14 {
+            return;
+        }
+
+        self.timer -= cycles;
+        while self.timer <= 0 {
+            self.timer += self.period();
+
+            let bit = (self.lfsr ^ (self.lfsr >> 1)) & 0x01;
+            self.lfsr = (self.lfsr >> 1) | (bit << 14);
+            if self.register & 0x08 != 0 {
+                self.lfsr = (self.lfsr & !0x40) | (bit << 6);
+            }
+        }
+    }
+
+    fn output(&self) -> Option<u8> {
+        if !self.envelope.dac_enabled() {
+            return None;
+        }
+
+        Some(if self.enabled && self.lfsr & 0x01 == 0 {
+            self.envelope.volume
+        } else {
+            0
+        })
+    }
+}
+
+/// The four sound channels, mixed and resampled to `sample_rate` as interleaved stereo samples.
+pub struct Apu {
+    model: Model,
+    /// NR10-NR51 as last written, for the readable bits.
+    registers: [u8; 0x16],
+    powered: bool,
+    square1: Square,
+    sweep: Sweep,
+    square2: Square,
+    wave: Wave,
+    noise: Noise,
+    frame_sequencer: u8,
+    frame_sequencer_cycles: u32,
+    sample_rate: u32,
+    sample_clock: u32,
+    /// Charge of the high-pass capacitors of the left and right outputs.
+    capacitors: [f32; 2],
+    capacitor_factor: f32,
+    samples: Vec<i16>,
+}
+
+impl Default for Apu {
+    fn default() -> Self {
+        Apu::new(Model::default(), Apu::DEFAULT_SAMPLE_RATE)
+    }
+}
+
+impl Apu {
+    pub const DEFAULT_SAMPLE_RATE: u32 = 48000;
+    /// M-cycles per second.
+    const CLOCK_RATE: u32 = 1 << 20;
+    /// M-cycles between frame sequencer steps, at 512 Hz.
+    const FRAME_SEQUENCER_PERIOD: u32 = 2048;
+
+    pub fn new(model: Model, sample_rate: u32) -> Self {
+        // Charge kept by the capacitor after one sample, 0.999958 per T-cycle.
+        let cycles_per_sample = Apu::CLOCK_RATE * 4 / sample_rate.max(1);
+        let capacitor_factor = (0..cycles_per_sample).fold(1.0f32, |factor, _| factor * 0.999958);
+
+        Self {
+            model,
+            registers: [0x00; 0x16],
+            powered: false,
+            square1: Square::default(),
+            sweep: Sweep::default(),
+            square2: Square::default(),
+            wave: Wave::default(),
+            noise: Noise::default(),
+            frame_sequencer: 0,
+            frame_sequencer_cycles: 0,
+            sample_rate: sample_rate.max(1),
+            sample_clock: 0,
+            capacitors: [0.0; 2],
+            capacitor_factor,
+            samples: Vec::new(),
+        }
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Appends the registers and the state of the channels and the frame sequencer. The
+    /// resampling and high-pass filter state is left out: it depends on the host sample rate and
+    /// never feeds back into the emulation.
+    pub fn hash_state(&self, state: &mut Vec<u8>) {
+        fn length(state: &mut Vec<u8>, length: &Length) {
+            state.extend_from_slice(&length.counter.to_le_bytes());
+            state.push(length.enabled as u8);
+        }
+
+        fn envelope(state: &mut Vec<u8>, envelope: &Envelope) {
+            state.extend_from_slice(&[envelope.register, envelope.volume, envelope.timer]);
+        }
+
+        state.extend_from_slice(&self.registers);
+        state.push(self.powered as u8);
+
+        for square in [&self.square1, &self.square2] {
+            state.extend_from_slice(&[square.enabled as u8, square.duty, square.duty_step]);
+            length(state, &square.length);
+            envelope(state, &square.envelope);
+            state.extend_from_slice(&square.frequency.to_le_bytes());
+            state.extend_from_slice(&square.timer.to_le_bytes());
+        }
+
+        state.extend_from_slice(&[
+            self.sweep.register,
+            self.sweep.enabled as u8,
+            self.sweep.timer,
+        ]);
+        state.extend_from_slice(&self.sweep.shadow.to_le_bytes());
+
+        let wave = &self.wave;
+        state.extend_from_slice(&[wave.enabled as u8, wave.dac_enabled as u8]);
+        length(state, &wave.length);
+        state.push(wave.volume_code);
+        state.extend_from_slice(&wave.frequency.to_le_bytes());
+        state.extend_from_slice(&wave.timer.to_le_bytes());
+        state.push(wave.position);
+        state.extend_from_slice(&wave.ram);
+
+        let noise = &self.noise;
+        state.push(noise.enabled as u8);
+        length(state, &noise.length);
+        envelope(state, &noise.envelope);
+        state.push(noise.register);
+        state.extend_from_slice(&noise.timer.to_le_bytes());
+        state.extend_from_slice(&noise.lfsr.to_le_bytes());
+
+        state.push(self.frame_sequencer);
+        state.extend_from_slice(&self.frame_sequencer_cycles.to_le_bytes());
+    }
+
+    /// Samples produced since the last call, left and right interleaved.
+    pub fn drain_samples(&mut self) -> Vec<i16> {
+        core::mem::take(&mut self.samples)
+    }
+
+    /// Advances one M-cycle.
+    pub fn tick(&mut self) {
+        if self.powered {
+            self.frame_sequencer_cycles += 1;
+            if self.frame_sequencer_cycles == Apu::FRAME_SEQUENCER_PERIOD {
+                self.frame_sequencer_cycles = 0;
+                self.step_frame_sequencer();
+            }
+
+            self.square1.tick(4);
+            self.square2.tick(4);
+            self.wave.tick(4);
+            self.noise.tick(4);
+        }
+
+        self.sample_clock += self.sample_rate;
+        if self.sample_clock >= Apu::CLOCK_RATE {
+            self.sample_clock -= Apu::CLOCK_RATE;
+            self.push_sample();
+        }
+    }
+
+    fn step_frame_sequencer(&mut self) {
+        if self.frame_sequencer.is_multiple_of(2) {
+            if self.square1.length.step() {
+                self.square1.enabled = false;
+            }
+            if self.square2.length.step() {
+                self.square2.enabled = false;
+            }
+            if self.wave.length.step() {
+                self.wave.enabled = false;
+            }
+            if self.noise.length.step() {
+                self.noise.enabled = false;
+            }
+        }
+
+        if self.frame_sequencer == 2 || self.frame_sequencer == 6 {
+            self.step_sweep();
+        }
+
+        if self.frame_sequencer == 7 {
+            self.square1.envelope.step();
+            self.square2.envelope.step();
+            self.noise.envelope.step();
+        }
+
+        self.frame_sequencer = (self.frame_sequencer + 1) % 8;
+    }
+
+    /// Computes the next sweep frequency, disabling channel 1 if it overflows.
+    fn sweep_overflow_check(&mut self) -> u16 {
+        let frequency = self.sweep.next_frequency();
+        if frequency > 2047 {
+            self.square1.enabled = false;
+        }
+
+        frequency
+    }
+
+    fn step_sweep(&mut self) {
+        self.sweep.timer = self.sweep.timer.saturating_sub(1);
+        if self.sweep.timer != 0 {
+            return;
+        }
+
+        self.sweep.reload_timer();
+        if !self.sweep.enabled || self.sweep.period() == 0 {
+            return;
+        }
+
+        let frequency = self.sweep_overflow_check();
+        if frequency <= 2047 && self.sweep.shift() != 0 {
+            self.sweep.shadow = frequency;
+            self.square1.frequency = frequency;
+            self.sweep_overflow_check();
+        }
+    }
+
+    fn trigger_square1(&mut self) {
+        self.square1.trigger();
+
+        self.sweep.shadow = self.square1.frequency;
+        self.sweep.reload_timer();
+        self.sweep.enabled = self.sweep.period() != 0 || self.sweep.shift() != 0;
+        if self.sweep.shift() != 0 {
+            self.sweep_overflow_check();
+        }
+    }
+
+    fn push_sample(&mut self) {
+        let outputs = [
+            self.square1.output(),
+            self.square2.output(),
+            self.wave.output(),
+            self.noise.output(),
+        ];
+        let panning = self.registers[0x15];
+        let volume = self.registers[0x14];
+
+        for (side, (panning_shift, volume_shift)) in [(4, 4), (0, 0)].into_iter().enumerate() {
+            let mut mixed = 0.0;
+            let mut any_dac = false;
+
+            for (channel, output) in outputs.iter().enumerate() {
+                let Some(output) = output else {
+                    continue;
+                };
+
+                any_dac = true;
+                if panning >> (panning_shift + channel) & 0x01 != 0 {
+                    mixed += *output as f32 / 7.5 - 1.0;
+                }
+            }
+
+            let volume = ((volume >> volume_shift) & 0x07) as f32 + 1.0;
+            let input = mixed / 4.0 * volume / 8.0;
+
+            let output = if any_dac && self.powered {
+                let output = input - self.capacitors[side];
+                self.capacitors[side] = input - output * self.capacitor_factor;
+                output
+            } else {
+                0.0
+            };
+
+            self.samples.push((output * i16::MAX as f32) as i16);
+        }
+    }
+
+    fn power_off(&mut self) {
+        self.registers = [0x00; 0x16];
+        let wave_ram = self.wave.ram;
+        let lengths = [
+            self.square1.length.counter,
+            self.square2.length.counter,
+            self.wave.length.counter,
+            self.noise.length.counter,
+        ];
+
+        self.square1 = Square::default();
+        self.sweep = Sweep::default();
+        self.square2 = Square::default();
+        self.wave = Wave {
+            ram: wave_ram,
+            ..Wave::default()
+        };
+        self.noise = Noise::default();
+        self.powered = false;
+
+        // Only the CGB resets the length counters along with the rest of the APU.
+        if !self.model.is_cgb() {
+            self.square1.length.counter = lengths[0];
+            self.square2.length.counter = lengths[1];
+            self.wave.length.counter = lengths[2];
+            self.noise.length.counter = lengths[3];
+        }
+    }
+
+    /// On the DMG the length counters keep working while the APU is off.
+    fn write_length_powered_off(&mut self, address: u16, data: u8) {
+        match address {
+            0x11 => self.square1.length.load(Square::LENGTH, data & 0x3f),
+            0x16 => self.square2.length.load(Square::LENGTH, data & 0x3f),
+            0x1b => self.wave.length.load(Wave::LENGTH, data),
+            0x20 => self.noise.length.load(Noise::LENGTH, data & 0x3f),
+            _ => {}
+        }
+    }
+
+    fn write_register(&mut self, address: u16, data: u8) {
+        self.registers[address as usize - 0x10] = data;
+
+        match address {
+            0x10 => self.sweep.register = data,
+            0x11 => {
+                self.square1.duty = data >> 6;
+                self.square1.length.load(Square::LENGTH, data & 0x3f);
+            }
+            0x12 => {
+                self.square1.envelope.register = data;
+                self.square1.enabled &= self.square1.envelope.dac_enabled();
+            }
+            0x13 => self.square1.frequency = (self.square1.frequency & 0x700) | data as u16,
+            0x14 => {
+                self.square1.frequency =
+                    (self.square1.frequency & 0xff) | ((data as u16 & 0x07) << 8);
+                self.square1.length.enabled = data & 0x40 != 0;
+                if data & 0x80 != 0 {
+                    self.trigger_square1();
+                }
+            }
+            0x16 => {
+                self.square2.duty = data >> 6;
+                self.square2.length.load(Square::LENGTH, data & 0x3f);
+            }
+            0x17 => {
+                self.square2.envelope.register = data;
+                self.square2.enabled &= self.square2.envelope.dac_enabled();
+            }
+            0x18 => self.square2.frequency = (self.square2.frequency & 0x700) | data as u16,
+            0x19 => {
+                self.square2.frequency =
+                    (self.square2.frequency & 0xff) | ((data as u16 & 0x07) << 8);
+                self.square2.length.enabled = data & 0x40 != 0;
+                if data & 0x80 != 0 {
+                    self.square2.trigger();
+                }
+            }
+            0x1a => {
+                self.wave.dac_enabled = data & 0x80 != 0;
+                self.wave.enabled &= self.wave.dac_enabled;
+            }
+            0x1b => self.wave.length.load(Wave::LENGTH, data),
+            0x1c => self.wave.volume_code = (data >> 5) & 0x03,
+            0x1d => self.wave.frequency = (self.wave.frequency & 0x700) | data as u16,
+            0x1e => {
+                self.wave.frequency = (self.wave.frequency & 0xff) | ((data as u16 & 0x07) << 8);
+                self.wave.length.enabled = data & 0x40 != 0;
+                if data & 0x80 != 0 {
+                    self.wave.trigger();
+                }
+            }
+            0x20 => self.noise.length.load(Noise::LENGTH, data & 0x3f),
+            0x21 => {
+                self.noise.envelope.register = data;
+                self.noise.enabled &= self.noise.envelope.dac_enabled();
+            }
+            0x22 => self.noise.register = data,
+            0x23 => {
+                self.noise.length.enabled = data & 0x40 != 0;
+                if data & 0x80 != 0 {
+                    self.noise.trigger();
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Addresses are the offsets of the registers from 0xFF00: NR10-NR52 and wave RAM.
+impl MemoryMappedPeripheral for Apu {
+    fn write(&mut self, address: u16, data: u8) {
+        match address {
+            0x26 => {
+                if data & 0x80 == 0 {
+                    self.power_off();
+                } else if !self.powered {
+                    self.powered = true;
+                    self.frame_sequencer = 0;
+                    self.frame_sequencer_cycles = 0;
+                }
+            }
+            0x30..=0x3f => self.wave.ram[address as usize - 0x30] = data,
+            0x10..=0x25 if self.powered => self.write_register(address, data),
+            0x10..=0x25 if !self.model.is_cgb() => self.write_length_powered_off(address, data),
+            _ => {}
+        }
+    }
+
+    fn read(&self, address: u16) -> u8 {
+        match address {
+            0x26 => {
+                (self.powered as u8) << 7
+                    | (self.noise.enabled as u8) << 3
+                    | (self.wave.enabled as u8) << 2
+                    | (self.square2.enabled as u8) << 1
+                    | self.square1.enabled as u8
+            }
+            0x30..=0x3f => self.wave.ram[address as usize - 0x30],
+            0x10..=0x25 => self.registers[address as usize - 0x10],
+            _ => 0xff,
+        }
+    }
+}
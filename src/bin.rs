@@ -1,20 +1,398 @@
-use rustboy::cartridge::Cartridge;
-use rustboy::virtual_memory::{MemoryMappedPeripheral, VirtualMemory};
+use rustboy::cartridge::{Cartridge, CartridgeHeader};
+use rustboy::dat::DatFile;
+use rustboy::gameboy::GameBoy;
+use rustboy::gbx::GbxFooter;
+use rustboy::hash::{to_hex, RomHashes};
+use rustboy::model::Model;
+use rustboy::patch::{self, PatchFormat};
+use std::collections::HashSet;
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
 
+const DEFAULT_ROM: &str = "roms/cpu_instrs.gb";
+/// About a minute of emulated time.
+const FRAMES: u32 = 3600;
+
+fn invalid_data(err: impl ToString) -> Error {
+    Error::new(ErrorKind::InvalidData, err.to_string())
+}
+
+/// Reads the ROM and applies the first IPS, UPS or BPS patch found next to it with the same name.
 fn load_cartridge_from_file(path: &str) -> std::io::Result<Vec<u8>> {
-    std::fs::read(path)
+    let rom = std::fs::read(path)?;
+
+    for format in [PatchFormat::Ips, PatchFormat::Ups, PatchFormat::Bps] {
+        let patch_path = Path::new(path).with_extension(format.extension());
+
+        match std::fs::read(&patch_path) {
+            Ok(patch) => {
+                println!("Applying patch {}", patch_path.display());
+                return patch::apply(&rom, &patch).map_err(invalid_data);
+            }
+            Err(error) if error.kind() == ErrorKind::NotFound => {}
+            Err(error) => {
+                return Err(Error::new(
+                    error.kind(),
+                    format!("{}: {}", patch_path.display(), error),
+                ))
+            }
+        }
+    }
+
+    Ok(rom)
 }
 
-fn main() -> std::io::Result<()> {
-    let cartridge = load_cartridge_from_file("roms/cpu_instrs.gb")?;
+fn usage() -> Error {
+    Error::new(
+        ErrorKind::InvalidInput,
+        "usage: rustboy [rom] [dmg0|dmg|mgb|sgb|sgb2|cgb|agb] [boot_rom|skip]\n       \
+        rustboy identify <rom> <dat>\n       \
+        rustboy make-patch <original> <modified> <patch.ips|patch.bps>\n       \
+        rustboy scan <dir> [csv|json]",
+    )
+}
+
+fn run(path: &str, model: Option<&str>, boot: Option<&str>) -> std::io::Result<()> {
+    let cartridge = load_cartridge_from_file(path)?;
     let cartridge = Cartridge::load(&cartridge);
     println!("Cartridge: {}", cartridge);
 
-    let mut virtual_memory = VirtualMemory::new(cartridge);
-    let _joypad = virtual_memory.joypad_ref();
+    let mut builder = GameBoy::builder(cartridge);
+    if let Some(model) = model {
+        builder = builder.model(Model::from_name(model).ok_or_else(usage)?);
+    }
+
+    match boot {
+        Some("skip") => builder = builder.skip_boot(),
+        Some(boot_rom) => builder = builder.boot_rom(std::fs::read(boot_rom)?),
+        None => {}
+    }
+
+    let mut gameboy = builder.build().map_err(invalid_data)?;
+    println!("Model: {}", gameboy.memory().model().name());
+
+    for _ in 0..FRAMES {
+        gameboy.run_frame();
+
+        let serial = gameboy.drain_serial();
+        if !serial.is_empty() {
+            print!("{}", String::from_utf8_lossy(&serial));
+        }
+    }
+    println!();
+
+    Ok(())
+}
+
+/// Splits off the GBX footer, if any, since dumps are hashed and parsed without it.
+fn split_gbx_footer(content: &[u8]) -> (&[u8], Option<GbxFooter>) {
+    match GbxFooter::parse(content) {
+        Some(footer) => (&content[..content.len() - GbxFooter::SIZE], Some(footer)),
+        None => (content, None),
+    }
+}
+
+fn identify(rom_path: &str, dat_path: &str) -> std::io::Result<()> {
+    let content = std::fs::read(rom_path)?;
+    let (content, _) = split_gbx_footer(&content);
+    let dat = std::fs::read_to_string(dat_path)?;
+    let dat = DatFile::parse(&dat).map_err(invalid_data)?;
+    let hashes = RomHashes::compute(content);
+
+    println!("CRC32: {:08x}", hashes.crc32);
+    println!("MD5: {}", to_hex(&hashes.md5));
+    println!("SHA-1: {}", to_hex(&hashes.sha1));
+
+    let Some(identification) = dat.identify(content, &hashes) else {
+        println!("Not found in {}", dat_path);
+        return Ok(());
+    };
+
+    println!("Name: {}", identification.game.name);
+    println!(
+        "Region: {}",
+        identification.game.region().unwrap_or("Unknown")
+    );
+    println!(
+        "Revision: {}",
+        identification.game.revision().unwrap_or("0")
+    );
+    println!("Status: {:?}", identification.rom.status);
+    if identification.bad_dump() {
+        println!("Warning: known bad dump");
+    }
+    if identification.overdump {
+        println!(
+            "Warning: overdump, only the first {} bytes match",
+            identification.rom.size.unwrap_or_default()
+        );
+    }
+
+    Ok(())
+}
+
+fn make_patch(original: &str, modified: &str, output: &str) -> std::io::Result<()> {
+    let original = std::fs::read(original)?;
+    let modified = std::fs::read(modified)?;
+
+    let patch = match Path::new(output).extension().and_then(|ext| ext.to_str()) {
+        Some("ips") => patch::create_ips(&original, &modified).map_err(invalid_data)?,
+        Some("bps") => patch::create_bps(&original, &modified),
+        _ => return Err(usage()),
+    };
+
+    std::fs::write(output, patch)
+}
+
+/// A ROM found by the scan, or a file or directory it couldn't read.
+enum ScanEntry {
+    Rom(PathBuf),
+    Unreadable(PathBuf, Error),
+}
+
+/// Unreadable entries are reported instead of stopping the scan. Directories are only walked
+/// once, so symlinks pointing back up the tree don't loop forever.
+fn collect_roms(dir: &Path, visited: &mut HashSet<PathBuf>, roms: &mut Vec<ScanEntry>) {
+    match dir.canonicalize() {
+        Ok(canonical) => {
+            if !visited.insert(canonical) {
+                return;
+            }
+        }
+        Err(err) => return roms.push(ScanEntry::Unreadable(dir.to_path_buf(), err)),
+    }
+
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) => return roms.push(ScanEntry::Unreadable(dir.to_path_buf(), err)),
+    };
+
+    let mut paths = vec![];
+    for entry in entries {
+        match entry {
+            Ok(entry) => paths.push(entry.path()),
+            Err(err) => roms.push(ScanEntry::Unreadable(dir.to_path_buf(), err)),
+        }
+    }
+    paths.sort();
+
+    for path in paths {
+        if path.is_dir() {
+            collect_roms(&path, visited, roms);
+        } else if path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| {
+                ["gb", "gbc", "gbx"]
+                    .iter()
+                    .any(|rom_ext| ext.eq_ignore_ascii_case(rom_ext))
+            })
+        {
+            roms.push(ScanEntry::Rom(path));
+        }
+    }
+}
+
+/// One row of the scan report. Files that can't be read or parsed only fill the path and error.
+#[derive(Default)]
+struct ScanRow {
+    path: String,
+    title: Option<String>,
+    cartridge_type: Option<String>,
+    rom_size: Option<usize>,
+    ram_size: Option<usize>,
+    cgb: Option<String>,
+    sgb: Option<bool>,
+    licensee: Option<String>,
+    header_checksum_valid: Option<bool>,
+    global_checksum_valid: Option<bool>,
+    crc32: Option<String>,
+    sha1: Option<String>,
+    error: Option<String>,
+}
+
+/// A field of the scan report, typed so that JSON keeps numbers and booleans as such. Missing
+/// values are empty in CSV and null in JSON.
+enum ScanField<'a> {
+    Text(Option<&'a str>),
+    Number(Option<usize>),
+    Bool(Option<bool>),
+}
+
+impl ScanRow {
+    fn new(entry: &ScanEntry) -> Self {
+        let path = match entry {
+            ScanEntry::Rom(path) => path,
+            ScanEntry::Unreadable(path, err) => {
+                return Self {
+                    path: path.display().to_string(),
+                    error: Some(err.to_string()),
+                    ..Self::default()
+                }
+            }
+        };
+        let mut row = Self {
+            path: path.display().to_string(),
+            ..Self::default()
+        };
+
+        let content = match std::fs::read(path) {
+            Ok(content) => content,
+            Err(err) => {
+                row.error = Some(err.to_string());
+                return row;
+            }
+        };
+
+        let (content, gbx_footer) = split_gbx_footer(&content);
+        let hashes = RomHashes::compute(content);
+        row.crc32 = Some(format!("{:08x}", hashes.crc32));
+        row.sha1 = Some(to_hex(&hashes.sha1));
+
+        match CartridgeHeader::parse_with(content, gbx_footer.is_some()) {
+            Ok(header) => {
+                row.title = Some(header.title().to_string());
+                row.cartridge_type = header
+                    .cartridge_type()
+                    .map(|cartridge_type| format!("{:?}", cartridge_type));
+                row.rom_size = header
+                    .rom_size()
+                    .or(gbx_footer.map(|footer| footer.rom_size as usize));
+                row.ram_size = header
+                    .ram_size()
+                    .or(gbx_footer.map(|footer| footer.ram_size as usize));
+                row.cgb = Some(format!("{:?}", header.cgb_support()));
+                row.sgb = Some(header.sgb_support());
+                row.licensee = Some(header.publisher().to_string());
+                row.header_checksum_valid = Some(header.header_checksum_valid());
+                row.global_checksum_valid = Some(header.global_checksum_valid());
+            }
+            Err(err) => row.error = Some(err.to_string()),
+        }
+
+        row
+    }
+
+    fn fields(&self) -> [(&'static str, ScanField<'_>); 13] {
+        [
+            ("path", ScanField::Text(Some(&self.path))),
+            ("title", ScanField::Text(self.title.as_deref())),
+            (
+                "cartridge_type",
+                ScanField::Text(self.cartridge_type.as_deref()),
+            ),
+            ("rom_size", ScanField::Number(self.rom_size)),
+            ("ram_size", ScanField::Number(self.ram_size)),
+            ("cgb", ScanField::Text(self.cgb.as_deref())),
+            ("sgb", ScanField::Bool(self.sgb)),
+            ("licensee", ScanField::Text(self.licensee.as_deref())),
+            (
+                "header_checksum_valid",
+                ScanField::Bool(self.header_checksum_valid),
+            ),
+            (
+                "global_checksum_valid",
+                ScanField::Bool(self.global_checksum_valid),
+            ),
+            ("crc32", ScanField::Text(self.crc32.as_deref())),
+            ("sha1", ScanField::Text(self.sha1.as_deref())),
+            ("error", ScanField::Text(self.error.as_deref())),
+        ]
+    }
+}
 
-    virtual_memory.write(0, 0xaf);
-    let _ = virtual_memory.read(0);
+impl ScanField<'_> {
+    fn csv(&self) -> String {
+        match self {
+            ScanField::Text(text) => csv_field(text.unwrap_or_default()),
+            ScanField::Number(number) => number.map(|n| n.to_string()).unwrap_or_default(),
+            ScanField::Bool(value) => value.map(|b| b.to_string()).unwrap_or_default(),
+        }
+    }
+
+    fn json(&self) -> String {
+        match self {
+            ScanField::Text(Some(text)) => json_string(text),
+            ScanField::Number(Some(number)) => number.to_string(),
+            ScanField::Bool(Some(value)) => value.to_string(),
+            _ => String::from("null"),
+        }
+    }
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+fn json_string(field: &str) -> String {
+    let mut output = String::from('"');
+
+    for c in field.chars() {
+        match c {
+            '"' => output.push_str("\\\""),
+            '\\' => output.push_str("\\\\"),
+            c if (c as u32) < 0x20 => output.push_str(&format!("\\u{:04x}", c as u32)),
+            c => output.push(c),
+        }
+    }
+
+    output.push('"');
+    output
+}
+
+fn scan(dir: &str, format: &str) -> std::io::Result<()> {
+    if !std::fs::metadata(dir)?.is_dir() {
+        return Err(usage());
+    }
+
+    let mut roms = vec![];
+    collect_roms(Path::new(dir), &mut HashSet::new(), &mut roms);
+    let rows = roms.iter().map(ScanRow::new);
+
+    match format {
+        "csv" => {
+            let columns = ScanRow::default().fields().map(|(column, _)| column);
+            println!("{}", columns.join(","));
+            for row in rows {
+                let fields = row.fields().map(|(_, field)| field.csv());
+                println!("{}", fields.join(","));
+            }
+        }
+        "json" => {
+            let rows = rows
+                .map(|row| {
+                    let fields = row.fields().map(|(column, field)| {
+                        format!("{}: {}", json_string(column), field.json())
+                    });
+                    format!("  {{{}}}", fields.join(", "))
+                })
+                .collect::<Vec<_>>();
+            println!("[\n{}\n]", rows.join(",\n"));
+        }
+        _ => return Err(usage()),
+    }
 
     Ok(())
 }
+
+fn main() -> std::io::Result<()> {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    let args = args.iter().map(String::as_str).collect::<Vec<_>>();
+
+    match args.as_slice() {
+        [] => run(DEFAULT_ROM, None, None),
+        ["identify", rom, dat] => identify(rom, dat),
+        ["make-patch", original, modified, output] => make_patch(original, modified, output),
+        ["scan", dir] => scan(dir, "csv"),
+        ["scan", dir, format] => scan(dir, format),
+        ["identify", ..] | ["make-patch", ..] | ["scan", ..] => Err(usage()),
+        [rom] => run(rom, None, None),
+        [rom, model] => run(rom, Some(model), None),
+        [rom, model, boot] => run(rom, Some(model), Some(boot)),
+        _ => Err(usage()),
+    }
+}
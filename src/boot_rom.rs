@@ -0,0 +1,128 @@
+use crate::model::Model;
+use core::fmt::{Display, Formatter};
+
+/// The DMG boot ROM, which is the default on that model. Its size is checked by the type.
+pub const DMG_BOOT_ROM: &[u8; 0x100] = include_bytes!("DMG_ROM.bin");
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BootRomError {
+    InvalidSize {
+        model: Model,
+        expected: usize,
+        actual: usize,
+    },
+    /// The boot ROM doesn't have the SHA-1 of the one a movie was recorded with, or is missing.
+    MovieMismatch,
+}
+
+impl Display for BootRomError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self {
+            BootRomError::InvalidSize {
+                model,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "The {} boot ROM has {} bytes, expected {}",
+                model.name(),
+                actual,
+                expected
+            ),
+            BootRomError::MovieMismatch => {
+                write!(f, "The boot ROM isn't the one the movie was recorded with")
+            }
+        }
+    }
+}
+
+/// The CGB boot ROM is mapped at 0x0000-0x00FF and 0x0200-0x08FF, leaving the cartridge header
+/// visible in between.
+pub fn size(model: Model) -> usize {
+    if model.is_cgb() {
+        0x900
+    } else {
+        0x100
+    }
+}
+
+pub fn validate(model: Model, boot_rom: &[u8]) -> Result<(), BootRomError> {
+    if boot_rom.len() != size(model) {
+        return Err(BootRomError::InvalidSize {
+            model,
+            expected: size(model),
+            actual: boot_rom.len(),
+        });
+    }
+
+    Ok(())
+}
+
+/// I/O registers as the boot ROM leaves them, in the order they have to be written: the APU
+/// is powered on first, and channel 1 is triggered last as it's still playing the boot sound.
+pub(crate) fn post_boot_io(model: Model) -> [(u16, u8); 26] {
+    let nr14 = if model.is_sgb() { 0x3f } else { 0xbf };
+
+    [
+        (0x0000, 0x00),
+        (0x0007, 0xf8),
+        (0x000f, 0xe1),
+        (0x0026, 0x80),
+        (0x0010, 0x80),
+        (0x0011, 0xbf),
+        (0x0012, 0xf3),
+        (0x0013, 0xff),
+        (0x0016, 0x3f),
+        (0x0017, 0x00),
+        (0x0018, 0xff),
+        (0x0019, 0xbf),
+        (0x001a, 0x7f),
+        (0x001b, 0xff),
+        (0x001c, 0x9f),
+        (0x001d, 0xff),
+        (0x001e, 0xbf),
+        (0x0020, 0xff),
+        (0x0021, 0x00),
+        (0x0022, 0x00),
+        (0x0023, 0xbf),
+        (0x0024, 0x77),
+        (0x0025, 0xf3),
+        (0x0040, 0x91),
+        (0x0047, 0xfc),
+        (0x0014, nr14),
+    ]
+}
+
+/// The ® next to the logo, stored in the boot ROM itself.
+const REGISTERED_TILE: [u8; 8] = [0x3c, 0x42, 0xb9, 0xa5, 0xb9, 0xa5, 0x42, 0x3c];
+
+/// Tiles 1-25 at 0x8010 as the DMG boot ROM draws them from the 48 logo bytes of the header:
+/// every bit is doubled in both directions and only the low bit plane is used.
+pub(crate) fn logo_tiles(logo: &[u8]) -> [u8; 25 * 16] {
+    let mut tiles = [0x00; 25 * 16];
+
+    let nibbles = logo.iter().flat_map(|byte| [byte >> 4, byte & 0x0f]);
+    for (index, nibble) in nibbles.enumerate() {
+        let row = (0..4).fold(0u8, |row, bit| {
+            let set = (nibble >> (3 - bit)) & 0x01;
+            row << 2 | set << 1 | set
+        });
+
+        tiles[index * 4] = row;
+        tiles[index * 4 + 2] = row;
+    }
+
+    for (index, &row) in REGISTERED_TILE.iter().enumerate() {
+        tiles[24 * 16 + index * 2] = row;
+    }
+
+    tiles
+}
+
+/// Tile map entries for the logo: `(address, tile)` for the two rows and the ®.
+pub(crate) fn logo_map() -> impl Iterator<Item = (u16, u8)> {
+    let top = (0x01..=0x0c).map(|tile| (0x9903 + tile as u16, tile));
+    let bottom = (0x0d..=0x18).map(|tile| (0x9917 + tile as u16, tile));
+
+    top.chain(bottom).chain([(0x9910, 0x19)])
+}
@@ -1,3 +1,5 @@
+use crate::gbx::GbxFooter;
+use crate::hash::RomHashes;
 use crate::ram::Ram;
 use crate::virtual_memory::MemoryMappedPeripheral;
 use alloc::string::String;
@@ -40,9 +42,25 @@ impl<const S: usize> Rom<S> {
 
         self.actual_bank = bank;
     }
+
+    pub fn banks(&self) -> usize {
+        self.banks
+    }
+
+    pub fn actual_bank(&self) -> usize {
+        self.actual_bank
+    }
+
+    pub(crate) fn bank(&self, bank: usize) -> &[u8] {
+        &self.buffer[bank]
+    }
+
+    pub(crate) fn bank_mut(&mut self, bank: usize) -> &mut [u8] {
+        &mut self.buffer[bank]
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[allow(non_camel_case_types)]
 pub enum NewLicensee {
     None,
@@ -109,9 +127,11 @@ pub enum NewLicensee {
     Konami_YuGiOh,
 }
 
-impl From<&[char]> for NewLicensee {
-    fn from(value: &[char]) -> Self {
-        match value {
+impl TryFrom<&[char]> for NewLicensee {
+    type Error = CartridgeError;
+
+    fn try_from(value: &[char]) -> Result<Self, Self::Error> {
+        Ok(match value {
             ['0', '0'] | ['\0', '\0'] => NewLicensee::None,
             ['0', '1'] => NewLicensee::NintendoRnD1,
             ['0', '8'] => NewLicensee::Capcom,
@@ -174,12 +194,81 @@ impl From<&[char]> for NewLicensee {
             ['9', '9'] => NewLicensee::PackInSoft,
             ['9', 'H'] => NewLicensee::BottomUp,
             ['A', '4'] => NewLicensee::Konami_YuGiOh,
-            _ => panic!("Invalid New Licensee code: {:?}", value),
+            _ => return Err(CartridgeError::InvalidNewLicensee(value.iter().collect())),
+        })
+    }
+}
+
+impl NewLicensee {
+    pub fn name(&self) -> &'static str {
+        match self {
+            NewLicensee::None => "None",
+            NewLicensee::NintendoRnD1 => "Nintendo R&D1",
+            NewLicensee::Capcom => "Capcom",
+            NewLicensee::EletronicArts => "Electronic Arts",
+            NewLicensee::HudsonSoft => "Hudson Soft",
+            NewLicensee::b_ai => "B-AI",
+            NewLicensee::kss => "KSS",
+            NewLicensee::pow => "Planning Office WADA",
+            NewLicensee::PCMComplete => "PCM Complete",
+            NewLicensee::san_x => "San-X",
+            NewLicensee::KemcoJapan => "Kemco",
+            NewLicensee::seta => "SETA Corporation",
+            NewLicensee::Viacom => "Viacom",
+            NewLicensee::Nintendo => "Nintendo",
+            NewLicensee::Bandai => "Bandai",
+            NewLicensee::Ocean_Acclaim => "Ocean Software/Acclaim Entertainment",
+            NewLicensee::Konami => "Konami",
+            NewLicensee::Hector => "HectorSoft",
+            NewLicensee::Taito => "Taito",
+            NewLicensee::Hudson => "Hudson Soft",
+            NewLicensee::Banpresto => "Banpresto",
+            NewLicensee::UbiSoft => "Ubi Soft",
+            NewLicensee::Atlus => "Atlus",
+            NewLicensee::Malibu => "Malibu Interactive",
+            NewLicensee::angel => "Angel",
+            NewLicensee::Bullet_Proof => "Bullet-Proof Software",
+            NewLicensee::irem => "Irem",
+            NewLicensee::Absolute => "Absolute",
+            NewLicensee::Acclaim => "Acclaim Entertainment",
+            NewLicensee::Activision => "Activision",
+            NewLicensee::AmericanSammy => "Sammy USA Corporation",
+            NewLicensee::Konami2 => "Konami",
+            NewLicensee::HiTechEntertainment => "Hi Tech Expressions",
+            NewLicensee::LJN => "LJN",
+            NewLicensee::Matchbox => "Matchbox",
+            NewLicensee::Mattel => "Mattel",
+            NewLicensee::MiltonBradley => "Milton Bradley Company",
+            NewLicensee::Titus => "Titus Interactive",
+            NewLicensee::Virgin => "Virgin Games",
+            NewLicensee::LucasArts => "Lucasfilm Games",
+            NewLicensee::Ocean => "Ocean Software",
+            NewLicensee::EletronicArts2 => "Electronic Arts",
+            NewLicensee::Infogrames => "Infogrames",
+            NewLicensee::Interplay => "Interplay Entertainment",
+            NewLicensee::Broderbund => "Broderbund",
+            NewLicensee::sculptured => "Sculptured Software",
+            NewLicensee::sci => "The Sales Curve Limited",
+            NewLicensee::THQ => "THQ",
+            NewLicensee::Accolade => "Accolade",
+            NewLicensee::misawa => "Misawa Entertainment",
+            NewLicensee::lozc => "lozc",
+            NewLicensee::TokumaShotenIntermedia => "Tokuma Shoten",
+            NewLicensee::TsukudaOriginal => "Tsukuda Original",
+            NewLicensee::Chunsoft => "Chunsoft",
+            NewLicensee::VideoSystem => "Video System",
+            NewLicensee::Ocean_Acclaim2 => "Ocean Software/Acclaim Entertainment",
+            NewLicensee::Varie => "Varie",
+            NewLicensee::Yonezawa_spal => "Yonezawa/s'pal",
+            NewLicensee::Kaneko => "Kaneko",
+            NewLicensee::PackInSoft => "Pack-In-Video",
+            NewLicensee::BottomUp => "Bottom Up",
+            NewLicensee::Konami_YuGiOh => "Konami (Yu-Gi-Oh!)",
         }
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum CartridgeType {
     RomOnly = 0x00,
     Mbc1 = 0x01,
@@ -211,9 +300,11 @@ pub enum CartridgeType {
     HuC1RamBattery = 0xFF,
 }
 
-impl From<u8> for CartridgeType {
-    fn from(value: u8) -> Self {
-        match value {
+impl TryFrom<u8> for CartridgeType {
+    type Error = CartridgeError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        Ok(match value {
             0x00 => CartridgeType::RomOnly,
             0x01 => CartridgeType::Mbc1,
             0x02 => CartridgeType::Mbc1Ram,
@@ -242,28 +333,192 @@ impl From<u8> for CartridgeType {
             0xFD => CartridgeType::BandaiTama5,
             0xFE => CartridgeType::HuC3,
             0xFF => CartridgeType::HuC1RamBattery,
-            _ => panic!("Invalid Cartridge type: {}", value),
+            _ => return Err(CartridgeError::InvalidCartridgeType(value)),
+        })
+    }
+}
+
+impl CartridgeType {
+    pub fn mapper(&self) -> Mapper {
+        use CartridgeType::*;
+
+        match self {
+            RomOnly | RomRam | RomRamBattery => Mapper::None,
+            Mbc1 | Mbc1Ram | Mbc1RamBattery => Mapper::Mbc1,
+            Mbc2 | Mbc2Battery => Mapper::Mbc2,
+            Mmm01 | Mmm01Ram | Mmm01RamBattery => Mapper::Mmm01,
+            Mbc3TimerBattery | Mbc3TimerRamBattery | Mbc3 | Mbc3Ram | Mbc3RamBattery => {
+                Mapper::Mbc3
+            }
+            Mbc5 | Mbc5Ram | Mbc5RamBattery | Mbc5Rumble | Mbc5RumbleRam | Mbc5RumbleRamBattery => {
+                Mapper::Mbc5
+            }
+            Mbc6 => Mapper::Mbc6,
+            Mbc7SensorRumbleRamBattery => Mapper::Mbc7,
+            PocketCamera => Mapper::PocketCamera,
+            BandaiTama5 => Mapper::Tama5,
+            HuC3 => Mapper::HuC3,
+            HuC1RamBattery => Mapper::HuC1,
         }
     }
+
+    pub fn features(&self) -> CartridgeFeatures {
+        use CartridgeType::*;
+
+        CartridgeFeatures {
+            ram: matches!(
+                self,
+                Mbc1Ram
+                    | Mbc1RamBattery
+                    | Mbc2
+                    | Mbc2Battery
+                    | RomRam
+                    | RomRamBattery
+                    | Mmm01Ram
+                    | Mmm01RamBattery
+                    | Mbc3TimerRamBattery
+                    | Mbc3Ram
+                    | Mbc3RamBattery
+                    | Mbc5Ram
+                    | Mbc5RamBattery
+                    | Mbc5RumbleRam
+                    | Mbc5RumbleRamBattery
+                    | Mbc7SensorRumbleRamBattery
+                    | PocketCamera
+                    | HuC3
+                    | HuC1RamBattery
+            ),
+            battery: matches!(
+                self,
+                Mbc1RamBattery
+                    | Mbc2Battery
+                    | RomRamBattery
+                    | Mmm01RamBattery
+                    | Mbc3TimerBattery
+                    | Mbc3TimerRamBattery
+                    | Mbc3RamBattery
+                    | Mbc5RamBattery
+                    | Mbc5RumbleRamBattery
+                    | Mbc7SensorRumbleRamBattery
+                    | PocketCamera
+                    | HuC3
+                    | HuC1RamBattery
+            ),
+            timer: matches!(self, Mbc3TimerBattery | Mbc3TimerRamBattery | HuC3),
+            rumble: matches!(
+                self,
+                Mbc5Rumble | Mbc5RumbleRam | Mbc5RumbleRamBattery | Mbc7SensorRumbleRamBattery
+            ),
+            sensor: matches!(self, Mbc7SensorRumbleRamBattery),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mapper {
+    None,
+    Mbc1,
+    Mbc1Multicart,
+    Mbc2,
+    Mbc3,
+    Mbc5,
+    Mbc6,
+    Mbc7,
+    Mmm01,
+    PocketCamera,
+    Tama5,
+    HuC1,
+    HuC3,
+    /// Mapper without a header code, identified by its GBX id.
+    Other([u8; 4]),
+}
+
+impl Mapper {
+    pub fn from_gbx_id(id: [u8; 4]) -> Self {
+        match &id {
+            b"ROM\0" => Mapper::None,
+            b"MBC1" => Mapper::Mbc1,
+            b"MB1M" => Mapper::Mbc1Multicart,
+            b"MBC2" => Mapper::Mbc2,
+            b"MBC3" => Mapper::Mbc3,
+            b"MBC5" => Mapper::Mbc5,
+            b"MBC6" => Mapper::Mbc6,
+            b"MBC7" => Mapper::Mbc7,
+            b"MMM1" => Mapper::Mmm01,
+            b"CAMR" => Mapper::PocketCamera,
+            b"TAM5" => Mapper::Tama5,
+            b"HUC1" => Mapper::HuC1,
+            b"HUC3" => Mapper::HuC3,
+            _ => Mapper::Other(id),
+        }
+    }
+
+    pub fn gbx_id(&self) -> [u8; 4] {
+        match self {
+            Mapper::None => *b"ROM\0",
+            Mapper::Mbc1 => *b"MBC1",
+            Mapper::Mbc1Multicart => *b"MB1M",
+            Mapper::Mbc2 => *b"MBC2",
+            Mapper::Mbc3 => *b"MBC3",
+            Mapper::Mbc5 => *b"MBC5",
+            Mapper::Mbc6 => *b"MBC6",
+            Mapper::Mbc7 => *b"MBC7",
+            Mapper::Mmm01 => *b"MMM1",
+            Mapper::PocketCamera => *b"CAMR",
+            Mapper::Tama5 => *b"TAM5",
+            Mapper::HuC1 => *b"HUC1",
+            Mapper::HuC3 => *b"HUC3",
+            Mapper::Other(id) => *id,
+        }
+    }
+}
+
+/// Extra hardware present on the cartridge besides the ROM and the mapper.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CartridgeFeatures {
+    pub ram: bool,
+    pub battery: bool,
+    pub timer: bool,
+    pub rumble: bool,
+    pub sensor: bool,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CgbSupport {
+    None,
+    Compatible,
+    CgbOnly,
+}
+
+impl From<u8> for CgbSupport {
+    fn from(value: u8) -> Self {
+        match value {
+            0xC0 => CgbSupport::CgbOnly,
+            x if x & 0x80 != 0 => CgbSupport::Compatible,
+            _ => CgbSupport::None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Destination {
     Japan = 0x00,
     Overseas = 0x01,
 }
 
-impl From<u8> for Destination {
-    fn from(value: u8) -> Self {
-        match value {
+impl TryFrom<u8> for Destination {
+    type Error = CartridgeError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        Ok(match value {
             0x00 => Destination::Japan,
             0x01 => Destination::Overseas,
-            _ => panic!("Invalid Destination code: {}", value),
-        }
+            _ => return Err(CartridgeError::InvalidDestination(value)),
+        })
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[allow(non_camel_case_types)]
 pub enum OldLicensee {
     None = 0x00,
@@ -415,9 +670,11 @@ pub enum OldLicensee {
     LJN3 = 0xFF,
 }
 
-impl From<u8> for OldLicensee {
-    fn from(value: u8) -> Self {
-        match value {
+impl TryFrom<u8> for OldLicensee {
+    type Error = CartridgeError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        Ok(match value {
             0x00 => OldLicensee::None,
             0x01 => OldLicensee::Nintendo,
             0x08 => OldLicensee::Capcom,
@@ -565,33 +822,232 @@ impl From<u8> for OldLicensee {
             0xF0 => OldLicensee::A_Wave,
             0xF3 => OldLicensee::Extreme_Entertainment,
             0xFF => OldLicensee::LJN3,
-            _ => panic!("Invalid Old licensee code: {}", value),
+            _ => return Err(CartridgeError::InvalidOldLicensee(value)),
+        })
+    }
+}
+
+impl OldLicensee {
+    pub fn name(&self) -> &'static str {
+        match self {
+            OldLicensee::None => "None",
+            OldLicensee::Nintendo => "Nintendo",
+            OldLicensee::Capcom => "Capcom",
+            OldLicensee::Hot_B => "HOT-B",
+            OldLicensee::Jaleco => "Jaleco",
+            OldLicensee::Coconuts_Japan => "Coconuts Japan",
+            OldLicensee::Elite_Systems => "Elite Systems",
+            OldLicensee::EA => "Electronic Arts",
+            OldLicensee::Hudsonsoft => "Hudson Soft",
+            OldLicensee::ITC_Entertainment => "ITC Entertainment",
+            OldLicensee::Yanoman => "Yanoman",
+            OldLicensee::Japan_Clary => "Japan Clary",
+            OldLicensee::Virgin_Interactive => "Virgin Games",
+            OldLicensee::PCM_Complete => "PCM Complete",
+            OldLicensee::San_X => "San-X",
+            OldLicensee::Kotobuki_Systems => "Kemco",
+            OldLicensee::Seta => "SETA Corporation",
+            OldLicensee::Infogrames => "Infogrames",
+            OldLicensee::Nintendo2 => "Nintendo",
+            OldLicensee::Bandai => "Bandai",
+            OldLicensee::NewLicenseeCode => "New licensee code",
+            OldLicensee::Konami => "Konami",
+            OldLicensee::HectorSoft => "HectorSoft",
+            OldLicensee::Capcom2 => "Capcom",
+            OldLicensee::Banpresto => "Banpresto",
+            OldLicensee::Entertainment_i => "Entertainment Interactive",
+            OldLicensee::Gremlin => "Gremlin",
+            OldLicensee::Ubisoft => "Ubi Soft",
+            OldLicensee::Atlus => "Atlus",
+            OldLicensee::Malibu => "Malibu Interactive",
+            OldLicensee::Angel => "Angel",
+            OldLicensee::Spectrum_Holoby => "Spectrum HoloByte",
+            OldLicensee::Irem => "Irem",
+            OldLicensee::Virgin_Interactive2 => "Virgin Games",
+            OldLicensee::Malibu2 => "Malibu Interactive",
+            OldLicensee::US_Gold => "U.S. Gold",
+            OldLicensee::Absolute => "Absolute",
+            OldLicensee::Acclaim => "Acclaim Entertainment",
+            OldLicensee::Activision => "Activision",
+            OldLicensee::American_Sammy => "Sammy USA Corporation",
+            OldLicensee::GameTek => "GameTek",
+            OldLicensee::Park_Place => "Park Place",
+            OldLicensee::LJN => "LJN",
+            OldLicensee::Matchbox => "Matchbox",
+            OldLicensee::Milton_Bradley => "Milton Bradley Company",
+            OldLicensee::Mindscape => "Mindscape",
+            OldLicensee::Romstar => "Romstar",
+            OldLicensee::Naxat_Soft => "Naxat Soft",
+            OldLicensee::Tradewest => "Tradewest",
+            OldLicensee::Titus => "Titus Interactive",
+            OldLicensee::Virgin_Interactive3 => "Virgin Games",
+            OldLicensee::Ocean_Interactive => "Ocean Software",
+            OldLicensee::EA2 => "Electronic Arts",
+            OldLicensee::Elite_Systems2 => "Elite Systems",
+            OldLicensee::Electro_Brain => "Electro Brain",
+            OldLicensee::Infogrames2 => "Infogrames",
+            OldLicensee::Interplay => "Interplay Entertainment",
+            OldLicensee::Broderbund => "Broderbund",
+            OldLicensee::Sculptered_Soft => "Sculptured Software",
+            OldLicensee::The_Sales_Curve => "The Sales Curve Limited",
+            OldLicensee::t_hq => "THQ",
+            OldLicensee::Accolade => "Accolade",
+            OldLicensee::Triffix_Entertainment => "Triffix Entertainment",
+            OldLicensee::Microprose => "MicroProse",
+            OldLicensee::Kemco => "Kemco",
+            OldLicensee::Misawa_Entertainment => "Misawa Entertainment",
+            OldLicensee::Lozc => "LOZC G.",
+            OldLicensee::Tokuma_Shoten_Intermedia => "Tokuma Shoten",
+            OldLicensee::Bullet_Proof_Software => "Bullet-Proof Software",
+            OldLicensee::Vic_Tokai => "Vic Tokai",
+            OldLicensee::Ape => "Ape",
+            OldLicensee::I_Max => "I'Max",
+            OldLicensee::Chunsoft_Co => "Chunsoft",
+            OldLicensee::Video_System => "Video System",
+            OldLicensee::Tsubaraya_Productions_Co => "Tsubaraya Productions",
+            OldLicensee::Varie_Corporation => "Varie",
+            OldLicensee::Yonezawa_S_Pal => "Yonezawa/s'pal",
+            OldLicensee::Kaneko => "Kaneko",
+            OldLicensee::Arc => "Arc",
+            OldLicensee::Nihon_Bussan => "Nihon Bussan",
+            OldLicensee::Tecmo => "Tecmo",
+            OldLicensee::Imagineer => "Imagineer",
+            OldLicensee::Banpresto2 => "Banpresto",
+            OldLicensee::Nova => "Nova",
+            OldLicensee::Hori_Electric => "Hori Electric",
+            OldLicensee::Bandai2 => "Bandai",
+            OldLicensee::Konami2 => "Konami",
+            OldLicensee::Kawada => "Kawada",
+            OldLicensee::Takara => "Takara",
+            OldLicensee::Technos_Japan => "Technos Japan",
+            OldLicensee::Broderbund2 => "Broderbund",
+            OldLicensee::Toei_Animation => "Toei Animation",
+            OldLicensee::Toho => "Toho",
+            OldLicensee::Namco => "Namco",
+            OldLicensee::acclaim => "Acclaim Entertainment",
+            OldLicensee::ASCII_or_Nexsoft => "ASCII Corporation or Nexsoft",
+            OldLicensee::Bandai3 => "Bandai",
+            OldLicensee::Square_Enix => "Square Enix",
+            OldLicensee::HAL_Laboratory => "HAL Laboratory",
+            OldLicensee::SNK => "SNK",
+            OldLicensee::Pony_Canyon => "Pony Canyon",
+            OldLicensee::Culture_Brain => "Culture Brain",
+            OldLicensee::Sunsoft => "Sunsoft",
+            OldLicensee::Sony_Imagesoft => "Sony Imagesoft",
+            OldLicensee::Sammy => "Sammy Corporation",
+            OldLicensee::Taito => "Taito",
+            OldLicensee::Kemco2 => "Kemco",
+            OldLicensee::Squaresoft => "Square",
+            OldLicensee::Tokuma_Shoten_Intermedia2 => "Tokuma Shoten",
+            OldLicensee::Data_East => "Data East",
+            OldLicensee::Tonkinhouse => "Tonkin House",
+            OldLicensee::Koei => "Koei",
+            OldLicensee::UFL => "UFL",
+            OldLicensee::Ultra => "Ultra Games",
+            OldLicensee::Vap => "VAP",
+            OldLicensee::Use_Corporation => "Use Corporation",
+            OldLicensee::Meldac => "Meldac",
+            OldLicensee::Pony_Canyon_or => "Pony Canyon",
+            OldLicensee::Angel2 => "Angel",
+            OldLicensee::Taito2 => "Taito",
+            OldLicensee::Sofel => "SOFEL",
+            OldLicensee::Quest => "Quest",
+            OldLicensee::Sigma_Enterprises => "Sigma Enterprises",
+            OldLicensee::ASK_Kodansha_Co => "ASK Kodansha",
+            OldLicensee::Naxat_Soft2 => "Naxat Soft",
+            OldLicensee::Copya_System => "Copya System",
+            OldLicensee::Banpresto3 => "Banpresto",
+            OldLicensee::Tomy => "Tomy",
+            OldLicensee::LJN2 => "LJN",
+            OldLicensee::NCS => "Nippon Computer Systems",
+            OldLicensee::Human => "Human Entertainment",
+            OldLicensee::Altron => "Altron",
+            OldLicensee::Jaleco2 => "Jaleco",
+            OldLicensee::Towa_Chiki => "Towa Chiki",
+            OldLicensee::Yutaka => "Yutaka",
+            OldLicensee::Varie => "Varie",
+            OldLicensee::Epcoh => "Epoch",
+            OldLicensee::Athena => "Athena",
+            OldLicensee::Asmik_ACE_Entertainment => "Asmik Ace Entertainment",
+            OldLicensee::Natsume => "Natsume",
+            OldLicensee::King_Records => "King Records",
+            OldLicensee::Atlus2 => "Atlus",
+            OldLicensee::Epic_Sony_Records => "Epic/Sony Records",
+            OldLicensee::IGS => "IGS",
+            OldLicensee::A_Wave => "A Wave",
+            OldLicensee::Extreme_Entertainment => "Extreme Entertainment",
+            OldLicensee::LJN3 => "LJN",
         }
     }
 }
 
-pub struct Cartridge {
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CartridgeError {
+    TooShort(usize),
+    InvalidNewLicensee(String),
+    InvalidCartridgeType(u8),
+    InvalidRomSize(u8),
+    InvalidRamSize(u8),
+    InvalidDestination(u8),
+    InvalidOldLicensee(u8),
+    HeaderChecksum { computed: u8, expected: u8 },
+}
+
+impl Display for CartridgeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self {
+            CartridgeError::TooShort(size) => write!(f, "ROM too short for a header: {}", size),
+            CartridgeError::InvalidNewLicensee(code) => {
+                write!(f, "Invalid New Licensee code: {:?}", code)
+            }
+            CartridgeError::InvalidCartridgeType(value) => {
+                write!(f, "Invalid Cartridge type: {}", value)
+            }
+            CartridgeError::InvalidRomSize(value) => write!(f, "Invalid ROM size: {}", value),
+            CartridgeError::InvalidRamSize(value) => write!(f, "Invalid RAM size: {}", value),
+            CartridgeError::InvalidDestination(value) => {
+                write!(f, "Invalid Destination code: {}", value)
+            }
+            CartridgeError::InvalidOldLicensee(value) => {
+                write!(f, "Invalid Old licensee code: {}", value)
+            }
+            CartridgeError::HeaderChecksum { computed, expected } => write!(
+                f,
+                "Header checksum validation failed: {}/{}",
+                computed, expected
+            ),
+        }
+    }
+}
+
+pub struct CartridgeHeader {
     title: String,
+    /// The 16 bytes at 0x134-0x143 as stored, including the CGB flag and the manufacturer code.
+    raw_title: [u8; 16],
     manufacture: String,
     new_licensee: NewLicensee,
-    cartridge_type: CartridgeType,
+    /// The hardware bytes at 0x147-0x149 are only optional next to a GBX footer.
+    cartridge_type: Option<CartridgeType>,
     destination: Destination,
     old_licensee: OldLicensee,
     mask_rom_version: u8,
     cgb_flag: u8,
     sgb_flag: u8,
-    rom_bank_count: usize,
-    ram_bank_count: usize,
+    rom_bank_count: Option<usize>,
+    ram_bank_count: Option<usize>,
     header_checksum: u8,
     global_checksum: u16,
-    bank0: Option<Rom<0x4000>>,
-    bank1: Option<Rom<0x4000>>,
-    ram: Option<Ram<0x2000>>,
+    computed_header_checksum: u8,
+    computed_global_checksum: u16,
 }
 
-impl Cartridge {
-    fn decode_rom_bank_count(bank_count: u8) -> usize {
-        match bank_count {
+impl CartridgeHeader {
+    const ROM_BANK_SIZE: usize = 0x4000;
+    const RAM_BANK_SIZE: usize = 0x2000;
+    const END: usize = 0x0150;
+
+    fn decode_rom_bank_count(bank_count: u8) -> Result<usize, CartridgeError> {
+        Ok(match bank_count {
             0x00 => 2,
             0x01 => 4,
             0x02 => 8,
@@ -601,23 +1057,23 @@ impl Cartridge {
             0x06 => 128,
             0x07 => 256,
             0x08 => 512,
-            0x52 => 3,
-            0x53 => 3,
-            0x54 => 3,
-            x => panic!("Invalid ROM size: {}", x),
-        }
+            0x52 => 72,
+            0x53 => 80,
+            0x54 => 96,
+            x => return Err(CartridgeError::InvalidRomSize(x)),
+        })
     }
 
-    fn decode_ram_bank_count(bank_count: u8) -> usize {
-        match bank_count {
+    fn decode_ram_bank_count(bank_count: u8) -> Result<usize, CartridgeError> {
+        Ok(match bank_count {
             0x00 => 0,
             0x01 => 0,
             0x02 => 1,
             0x03 => 4,
             0x04 => 16,
             0x05 => 8,
-            x => panic!("Invalid RAM size: {}", x),
-        }
+            x => return Err(CartridgeError::InvalidRamSize(x)),
+        })
     }
 
     fn decode_ascii(raw_title: &[u8]) -> String {
@@ -636,64 +1092,293 @@ impl Cartridge {
         })
     }
 
-    pub fn load(content: &[u8]) -> Self {
-        let title = Cartridge::decode_ascii(&content[0x0134..=0x0143]);
-        let manufacture = Cartridge::decode_ascii(&content[0x013F..=0x0142]);
-        let cgb_flag = content[0x0143];
-        let new_licensee_code = &content[0x0144..=0x0145];
-        let sgb_flag = content[0x0146];
-        let cartridge_type = CartridgeType::from(content[0x0147]);
-        let rom_banks = Cartridge::decode_rom_bank_count(content[0x0148]);
-        let ram_banks = Cartridge::decode_ram_bank_count(content[0x0149]);
-        let destination_code = content[0x014A];
-        let old_licensee_code = content[0x014B];
-        let mask_rom_version = content[0x014C];
-        let header_checksum = content[0x014D];
-        let global_checksum = &content[0x014E..=0x014F];
-        let global_checksum = ((global_checksum[1] as u16) << 8) | (global_checksum[0] as u16);
-
-        let bank0 = Rom::new(content[0..0x4000].to_vec(), 1);
-        let bank1 = Rom::new(content[0x4000..].to_vec(), rom_banks - 1);
-        let ram = if ram_banks > 0 {
-            Some(Ram::new(ram_banks))
-        } else {
-            None
-        };
+    fn compute_global_checksum(content: &[u8]) -> u16 {
+        content
+            .iter()
+            .enumerate()
+            .filter(|(address, _)| !(0x014E..=0x014F).contains(address))
+            .fold(0u16, |checksum, (_, byte)| {
+                checksum.wrapping_add(*byte as u16)
+            })
+    }
 
-        let header_computed_checksum =
-            Cartridge::compute_header_checksum(&content[0x0134..=0x014C]);
-        if header_computed_checksum != header_checksum {
-            panic!(
-                "Header checksum validation failed: {}/{}",
-                header_computed_checksum, header_checksum
-            );
+    pub fn parse(content: &[u8]) -> Result<Self, CartridgeError> {
+        CartridgeHeader::parse_with(content, false)
+    }
+
+    /// With `gbx_footer`, unknown cartridge type, ROM size and RAM size bytes are accepted, as
+    /// the footer describes the hardware instead.
+    pub fn parse_with(content: &[u8], gbx_footer: bool) -> Result<Self, CartridgeError> {
+        fn hardware<T>(
+            value: Result<T, CartridgeError>,
+            optional: bool,
+        ) -> Result<Option<T>, CartridgeError> {
+            match value {
+                Ok(value) => Ok(Some(value)),
+                Err(_) if optional => Ok(None),
+                Err(err) => Err(err),
+            }
         }
 
-        Self {
-            title,
-            manufacture,
-            cgb_flag,
-            sgb_flag,
-            new_licensee: new_licensee_code
+        if content.len() < CartridgeHeader::END {
+            return Err(CartridgeError::TooShort(content.len()));
+        }
+
+        let global_checksum = &content[0x014E..=0x014F];
+        let mut raw_title = [0x00; 16];
+        raw_title.copy_from_slice(&content[0x0134..=0x0143]);
+
+        Ok(Self {
+            title: CartridgeHeader::decode_ascii(&content[0x0134..=0x0143]),
+            raw_title,
+            manufacture: CartridgeHeader::decode_ascii(&content[0x013F..=0x0142]),
+            cgb_flag: content[0x0143],
+            sgb_flag: content[0x0146],
+            new_licensee: content[0x0144..=0x0145]
                 .iter()
                 .map(|x| *x as char)
                 .collect::<Vec<_>>()
                 .as_slice()
-                .into(),
-            cartridge_type,
-            rom_bank_count: rom_banks,
-            ram_bank_count: ram_banks,
-            bank0: Some(bank0),
-            bank1: Some(bank1),
-            destination: destination_code.into(),
-            mask_rom_version,
-            header_checksum,
-            global_checksum,
-            ram,
-            old_licensee: old_licensee_code.into(),
+                .try_into()?,
+            cartridge_type: hardware(content[0x0147].try_into(), gbx_footer)?,
+            rom_bank_count: hardware(
+                CartridgeHeader::decode_rom_bank_count(content[0x0148]),
+                gbx_footer,
+            )?,
+            ram_bank_count: hardware(
+                CartridgeHeader::decode_ram_bank_count(content[0x0149]),
+                gbx_footer,
+            )?,
+            destination: content[0x014A].try_into()?,
+            old_licensee: content[0x014B].try_into()?,
+            mask_rom_version: content[0x014C],
+            header_checksum: content[0x014D],
+            global_checksum: ((global_checksum[0] as u16) << 8) | (global_checksum[1] as u16),
+            computed_header_checksum: CartridgeHeader::compute_header_checksum(
+                &content[0x0134..=0x014C],
+            ),
+            computed_global_checksum: CartridgeHeader::compute_global_checksum(content),
+        })
+    }
+
+    pub fn title(&self) -> &str {
+        &self.title
+    }
+
+    pub fn raw_title(&self) -> &[u8; 16] {
+        &self.raw_title
+    }
+
+    /// Sum of the raw title bytes, which the CGB boot ROM uses to recognize Nintendo games.
+    pub fn title_checksum(&self) -> u8 {
+        self.raw_title
+            .iter()
+            .fold(0u8, |sum, &byte| sum.wrapping_add(byte))
+    }
+
+    /// The old licensee code 0x01, or 0x33 with the new licensee code "01": the only games the
+    /// CGB boot ROM looks at the title of.
+    pub fn licensed_by_nintendo(&self) -> bool {
+        match self.old_licensee {
+            OldLicensee::Nintendo => true,
+            OldLicensee::NewLicenseeCode => self.new_licensee == NewLicensee::NintendoRnD1,
+            _ => false,
         }
     }
 
+    pub fn manufacturer_code(&self) -> &str {
+        &self.manufacture
+    }
+
+    pub fn new_licensee(&self) -> NewLicensee {
+        self.new_licensee
+    }
+
+    pub fn old_licensee(&self) -> OldLicensee {
+        self.old_licensee
+    }
+
+    /// Human readable publisher name. The new licensee code is only used when the old licensee
+    /// code is 0x33, as the boot ROM and the hardware do.
+    pub fn publisher(&self) -> &'static str {
+        match self.old_licensee {
+            OldLicensee::NewLicenseeCode => self.new_licensee.name(),
+            old_licensee => old_licensee.name(),
+        }
+    }
+
+    /// `None` when the byte is unknown, which is only accepted with a GBX footer.
+    pub fn cartridge_type(&self) -> Option<CartridgeType> {
+        self.cartridge_type
+    }
+
+    pub fn features(&self) -> Option<CartridgeFeatures> {
+        self.cartridge_type
+            .map(|cartridge_type| cartridge_type.features())
+    }
+
+    pub fn destination(&self) -> Destination {
+        self.destination
+    }
+
+    pub fn mask_rom_version(&self) -> u8 {
+        self.mask_rom_version
+    }
+
+    pub fn cgb_flag(&self) -> u8 {
+        self.cgb_flag
+    }
+
+    pub fn cgb_support(&self) -> CgbSupport {
+        self.cgb_flag.into()
+    }
+
+    pub fn sgb_flag(&self) -> u8 {
+        self.sgb_flag
+    }
+
+    /// SGB functions are only enabled when the SGB flag is 0x03 and the old licensee code is 0x33.
+    pub fn sgb_support(&self) -> bool {
+        self.sgb_flag == 0x03 && self.old_licensee == OldLicensee::NewLicenseeCode
+    }
+
+    pub fn rom_banks(&self) -> Option<usize> {
+        self.rom_bank_count
+    }
+
+    pub fn rom_size(&self) -> Option<usize> {
+        self.rom_bank_count
+            .map(|banks| banks * CartridgeHeader::ROM_BANK_SIZE)
+    }
+
+    pub fn ram_banks(&self) -> Option<usize> {
+        self.ram_bank_count
+    }
+
+    pub fn ram_size(&self) -> Option<usize> {
+        self.ram_bank_count
+            .map(|banks| banks * CartridgeHeader::RAM_BANK_SIZE)
+    }
+
+    pub fn header_checksum(&self) -> u8 {
+        self.header_checksum
+    }
+
+    pub fn global_checksum(&self) -> u16 {
+        self.global_checksum
+    }
+
+    /// The boot ROM refuses to run cartridges with an invalid header checksum.
+    pub fn header_checksum_valid(&self) -> bool {
+        self.header_checksum == self.computed_header_checksum
+    }
+
+    /// The global checksum isn't verified by the hardware, so many homebrew ROMs leave it wrong.
+    pub fn global_checksum_valid(&self) -> bool {
+        self.global_checksum == self.computed_global_checksum
+    }
+}
+
+pub struct Cartridge {
+    header: CartridgeHeader,
+    gbx_footer: Option<GbxFooter>,
+    mapper: Mapper,
+    features: CartridgeFeatures,
+    rom_banks: usize,
+    ram_banks: usize,
+    hashes: RomHashes,
+    bank0: Option<Rom<0x4000>>,
+    bank1: Option<Rom<0x4000>>,
+    ram: Option<Ram<0x2000>>,
+}
+
+impl Cartridge {
+    pub fn load(content: &[u8]) -> Self {
+        Cartridge::try_load(content).unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    /// A GBX footer, when present, takes priority over the header bytes at 0x147-0x149, which
+    /// can then be unknown, and the header checksum isn't required to be valid.
+    pub fn try_load(content: &[u8]) -> Result<Self, CartridgeError> {
+        let gbx_footer = GbxFooter::parse(content);
+        let mut rom = match gbx_footer {
+            Some(_) => content[..content.len() - GbxFooter::SIZE].to_vec(),
+            None => content.to_vec(),
+        };
+        let header = CartridgeHeader::parse_with(&rom, gbx_footer.is_some())?;
+
+        let (mapper, features, rom_banks, ram_banks) = match &gbx_footer {
+            Some(footer) => (
+                footer.mapper,
+                footer.features(),
+                (footer.rom_size as usize).div_ceil(CartridgeHeader::ROM_BANK_SIZE),
+                (footer.ram_size as usize).div_ceil(CartridgeHeader::RAM_BANK_SIZE),
+            ),
+            None => {
+                if !header.header_checksum_valid() {
+                    return Err(CartridgeError::HeaderChecksum {
+                        computed: header.computed_header_checksum,
+                        expected: header.header_checksum(),
+                    });
+                }
+
+                let cartridge_type = CartridgeType::try_from(rom[0x0147])?;
+                (
+                    cartridge_type.mapper(),
+                    cartridge_type.features(),
+                    CartridgeHeader::decode_rom_bank_count(rom[0x0148])?,
+                    CartridgeHeader::decode_ram_bank_count(rom[0x0149])?,
+                )
+            }
+        };
+
+        let hashes = RomHashes::compute(&rom);
+        rom.resize(rom_banks.max(2) * CartridgeHeader::ROM_BANK_SIZE, 0xff);
+        let bank1 = Rom::new(rom.split_off(0x4000), rom_banks.max(2) - 1);
+
+        Ok(Self {
+            header,
+            gbx_footer,
+            mapper,
+            features,
+            rom_banks,
+            ram_banks,
+            hashes,
+            bank0: Some(Rom::new(rom, 1)),
+            bank1: Some(bank1),
+            ram: (ram_banks > 0).then(|| Ram::new(ram_banks)),
+        })
+    }
+
+    pub fn header(&self) -> &CartridgeHeader {
+        &self.header
+    }
+
+    pub fn hashes(&self) -> &RomHashes {
+        &self.hashes
+    }
+
+    pub fn gbx_footer(&self) -> Option<&GbxFooter> {
+        self.gbx_footer.as_ref()
+    }
+
+    /// The GBX footer, when present, takes priority over the header bytes at 0x147-0x149.
+    pub fn mapper(&self) -> Mapper {
+        self.mapper
+    }
+
+    pub fn features(&self) -> CartridgeFeatures {
+        self.features
+    }
+
+    pub fn rom_banks(&self) -> usize {
+        self.rom_banks
+    }
+
+    pub fn ram_banks(&self) -> usize {
+        self.ram_banks
+    }
+
     pub fn take_bank0(&mut self) -> Rom<0x4000> {
         self.bank0.take().unwrap()
     }
@@ -709,32 +1394,139 @@ impl Cartridge {
 
 impl Display for Cartridge {
     fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        let header = &self.header;
+
         write!(
             f,
             "{}, made by {}\n\
             Old licensee code: {:?}\n\
             New licensee code: {:?}\n\
             Destination: {:?}\n\
-            Cartridge type: {:?}\n\
+            Mapper: {:?}\n\
             CGB: 0x{:02x}, SGB: 0x{:02x}\n\
             Mask ROM Version: {}\n\
             ROM: 16MiB x{}\n\
             RAM: 8MiB x{}\n\
             Header Checksum: 0x{:02x}\n\
             Global Checksum: 0x{:04x}",
-            self.title,
-            self.manufacture,
-            self.old_licensee,
-            self.new_licensee,
-            self.destination,
-            self.cartridge_type,
-            self.cgb_flag,
-            self.sgb_flag,
-            self.mask_rom_version,
-            self.rom_bank_count,
-            self.ram_bank_count,
-            self.header_checksum,
-            self.global_checksum,
-        )
+            header.title,
+            header.manufacture,
+            header.old_licensee,
+            header.new_licensee,
+            header.destination,
+            self.mapper,
+            header.cgb_flag,
+            header.sgb_flag,
+            header.mask_rom_version,
+            self.rom_banks,
+            self.ram_banks,
+            header.header_checksum,
+            header.global_checksum,
+        )?;
+
+        match (header.cartridge_type, self.gbx_footer) {
+            (_, Some(footer)) => write!(f, "\nGBX footer: {:?}", footer),
+            (Some(cartridge_type), None) => write!(f, "\nCartridge type: {:?}", cartridge_type),
+            (None, None) => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    fn rom(size: usize) -> Vec<u8> {
+        let mut rom = vec![0x00; size];
+        rom[0x0134..0x0138].copy_from_slice(b"TEST");
+        rom
+    }
+
+    fn set_checksums(rom: &mut [u8]) {
+        rom[0x014d] = CartridgeHeader::compute_header_checksum(&rom[0x0134..=0x014c]);
+        let global = CartridgeHeader::compute_global_checksum(rom);
+        rom[0x014e..=0x014f].copy_from_slice(&global.to_be_bytes());
+    }
+
+    #[test]
+    fn rom_sizes() {
+        for (code, banks) in [
+            (0x00, 2),
+            (0x05, 64),
+            (0x08, 512),
+            (0x52, 72),
+            (0x53, 80),
+            (0x54, 96),
+        ] {
+            let mut rom = rom(0x8000);
+            rom[0x0148] = code;
+            let header = CartridgeHeader::parse(&rom).unwrap();
+
+            assert_eq!(header.rom_banks(), Some(banks));
+            assert_eq!(header.rom_size(), Some(banks * 0x4000));
+        }
+
+        let mut rom = rom(0x8000);
+        rom[0x0148] = 0x09;
+        assert!(matches!(
+            CartridgeHeader::parse(&rom),
+            Err(CartridgeError::InvalidRomSize(0x09))
+        ));
+    }
+
+    #[test]
+    fn odd_rom_sizes_load_every_bank() {
+        for (code, banks) in [(0x52, 72), (0x53, 80), (0x54, 96)] {
+            let mut rom = rom(banks * 0x4000);
+            rom[0x0147] = 0x19;
+            rom[0x0148] = code;
+            set_checksums(&mut rom);
+
+            let cartridge = Cartridge::try_load(&rom).unwrap();
+            assert_eq!(cartridge.rom_banks(), banks);
+        }
+    }
+
+    #[test]
+    fn cgb_support() {
+        for (flag, support) in [
+            (0x00, CgbSupport::None),
+            (0x40, CgbSupport::None),
+            (0x80, CgbSupport::Compatible),
+            (0x88, CgbSupport::Compatible),
+            (0xc0, CgbSupport::CgbOnly),
+        ] {
+            let mut rom = rom(0x8000);
+            rom[0x0143] = flag;
+            let header = CartridgeHeader::parse(&rom).unwrap();
+
+            assert_eq!(header.cgb_flag(), flag);
+            assert_eq!(header.cgb_support(), support);
+        }
+    }
+
+    #[test]
+    fn global_checksum_is_big_endian() {
+        let mut rom = rom(0x8000);
+        rom[0x0200] = 0xff;
+        rom[0x0201] = 0x12;
+        set_checksums(&mut rom);
+
+        let header = CartridgeHeader::parse(&rom).unwrap();
+        let expected = rom
+            .iter()
+            .enumerate()
+            .filter(|&(address, _)| address != 0x014e && address != 0x014f)
+            .fold(0u16, |sum, (_, &byte)| sum.wrapping_add(byte as u16));
+        assert_eq!(header.global_checksum(), expected);
+        assert_eq!(rom[0x014e], (expected >> 8) as u8);
+        assert!(header.global_checksum_valid());
+        assert!(header.header_checksum_valid());
+
+        rom.swap(0x014e, 0x014f);
+        assert!(!CartridgeHeader::parse(&rom)
+            .unwrap()
+            .global_checksum_valid());
     }
 }
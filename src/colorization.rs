@@ -0,0 +1,280 @@
+use crate::cartridge::CartridgeHeader;
+use crate::joypad::JoyPadButton;
+
+/// Colors the CGB boot ROM picks from, four per palette, white to black for most of them.
+const COLORS: [u16; 120] = [
+    0x7fff, 0x32bf, 0x00d0, 0x0000, //
+    0x639f, 0x4279, 0x15b0, 0x04cb, //
+    0x7fff, 0x6e31, 0x454a, 0x0000, //
+    0x7fff, 0x1bef, 0x0200, 0x0000, //
+    0x7fff, 0x421f, 0x1cf2, 0x0000, //
+    0x7fff, 0x5294, 0x294a, 0x0000, //
+    0x7fff, 0x03ff, 0x012f, 0x0000, //
+    0x7fff, 0x03ef, 0x01d6, 0x0000, //
+    0x7fff, 0x42b5, 0x3dc8, 0x0000, //
+    0x7e74, 0x03ff, 0x0180, 0x0000, //
+    0x67ff, 0x77ac, 0x1a13, 0x2d6b, //
+    0x7ed6, 0x4bff, 0x2175, 0x0000, //
+    0x53ff, 0x4a5f, 0x7e52, 0x0000, //
+    0x4fff, 0x7ed2, 0x3a4c, 0x1ce0, //
+    0x03ed, 0x7fff, 0x255f, 0x0000, //
+    0x036a, 0x021f, 0x03ff, 0x7fff, //
+    0x7fff, 0x01df, 0x0112, 0x0000, //
+    0x231f, 0x035f, 0x00f2, 0x0009, //
+    0x7fff, 0x03ea, 0x011f, 0x0000, //
+    0x299f, 0x001a, 0x000c, 0x0000, //
+    0x7fff, 0x027f, 0x001f, 0x0000, //
+    0x7fff, 0x03e0, 0x0206, 0x0120, //
+    0x7fff, 0x7eeb, 0x001f, 0x7c00, //
+    0x7fff, 0x3fff, 0x7e00, 0x001f, //
+    0x7fff, 0x03ff, 0x001f, 0x0000, //
+    0x03ff, 0x001f, 0x000c, 0x0000, //
+    0x7fff, 0x033f, 0x0193, 0x0000, //
+    0x0000, 0x4200, 0x037f, 0x7fff, //
+    0x7fff, 0x7e8c, 0x7c00, 0x0000, //
+    0x7fff, 0x1bef, 0x6180, 0x0000, //
+];
+
+/// OBJ0, OBJ1 and BG palettes of a combination, as indices into `COLORS`.
+const fn palettes(obj0: u8, obj1: u8, bg: u8) -> [u8; 3] {
+    [obj0 * 4, obj1 * 4, bg * 4]
+}
+
+/// A few combinations start in the middle of a palette, so they're stored as color indices.
+const COMBINATIONS: [[u8; 3]; 51] = [
+    palettes(4, 4, 29),
+    palettes(18, 18, 18),
+    palettes(20, 20, 20),
+    palettes(24, 24, 24),
+    palettes(9, 9, 9),
+    palettes(0, 0, 0),
+    palettes(27, 27, 27),
+    palettes(5, 5, 5),
+    palettes(12, 12, 12),
+    palettes(26, 26, 26),
+    palettes(16, 8, 8),
+    palettes(4, 28, 28),
+    palettes(4, 2, 2),
+    palettes(3, 4, 4),
+    palettes(4, 29, 29),
+    palettes(28, 4, 28),
+    palettes(2, 17, 2),
+    palettes(16, 16, 8),
+    palettes(4, 4, 7),
+    palettes(4, 4, 18),
+    palettes(4, 4, 20),
+    palettes(19, 19, 9),
+    [15, 15, 44],
+    palettes(17, 17, 2),
+    palettes(4, 4, 2),
+    palettes(4, 4, 3),
+    palettes(28, 28, 0),
+    palettes(3, 3, 0),
+    palettes(0, 0, 1),
+    palettes(18, 22, 18),
+    palettes(20, 22, 20),
+    palettes(24, 22, 24),
+    palettes(16, 22, 8),
+    palettes(17, 4, 13),
+    [111, 0, 56],
+    [111, 16, 60],
+    palettes(19, 22, 9),
+    palettes(16, 28, 10),
+    palettes(4, 23, 28),
+    palettes(17, 22, 2),
+    palettes(4, 0, 2),
+    palettes(4, 28, 3),
+    palettes(28, 3, 0),
+    palettes(3, 28, 4),
+    palettes(21, 28, 4),
+    palettes(3, 28, 0),
+    palettes(25, 3, 28),
+    palettes(0, 28, 8),
+    palettes(4, 3, 28),
+    palettes(28, 3, 6),
+    palettes(4, 28, 29),
+];
+
+/// Title checksums of the Nintendo games the boot ROM knows about. The ones from
+/// `FIRST_AMBIGUOUS` on are shared by several titles and also need the fourth title letter to
+/// match.
+const TITLE_CHECKSUMS: [u8; 94] = [
+    0x00, 0x88, 0x16, 0x36, 0xd1, 0xdb, 0xf2, 0x3c, 0x8c, 0x92, 0x3d, 0x5c, 0x58, 0xc9, 0x3e, 0x70,
+    0x1d, 0x59, 0x69, 0x19, 0x35, 0xa8, 0x14, 0xaa, 0x75, 0x95, 0x99, 0x34, 0x6f, 0x15, 0xff, 0x97,
+    0x4b, 0x90, 0x17, 0x10, 0x39, 0xf7, 0xf6, 0xa2, 0x49, 0x4e, 0x43, 0x68, 0xe0, 0x8b, 0xf0, 0xce,
+    0x0c, 0x29, 0xe8, 0xb7, 0x86, 0x9a, 0x52, 0x01, 0x9d, 0x71, 0x9c, 0xbd, 0x5d, 0x6d, 0x67, 0x3f,
+    0x6b, 0xb3, 0x46, 0x28, 0xa5, 0xc6, 0xd3, 0x27, 0x61, 0x18, 0x66, 0x6a, 0xbf, 0x0d, 0xf4, 0xb3,
+    0x46, 0x28, 0xa5, 0xc6, 0xd3, 0x27, 0x61, 0x18, 0x66, 0x6a, 0xbf, 0x0d, 0xf4, 0xb3,
+];
+
+const FIRST_AMBIGUOUS: usize = 65;
+
+/// Fourth title letter of the entries from `FIRST_AMBIGUOUS` on.
+const FOURTH_LETTERS: &[u8; 29] = b"BEFAARBEKEK R-URAR INAILICE R";
+
+/// Combination for each entry of `TITLE_CHECKSUMS`.
+const TITLE_COMBINATIONS: [u8; 94] = [
+    0, 4, 5, 35, 34, 3, 31, 15, 10, 5, 19, 36, 7, 37, 30, 44, 21, 32, 31, 20, 5, 33, 13, 14, 5, 29,
+    5, 18, 9, 3, 2, 26, 25, 25, 41, 42, 26, 45, 42, 45, 36, 38, 26, 42, 30, 41, 34, 34, 5, 42, 6,
+    5, 33, 25, 42, 42, 40, 2, 16, 25, 42, 42, 5, 0, 39, 36, 22, 25, 6, 32, 12, 36, 11, 39, 18, 39,
+    24, 31, 50, 17, 46, 6, 27, 0, 47, 41, 41, 0, 0, 19, 34, 23, 18, 29,
+];
+
+/// Combinations picked on the boot screen, by direction (right, left, up, down) and then by the
+/// direction alone, with A or with B.
+const MANUAL_COMBINATIONS: [[u8; 4]; 3] = [[1, 48, 5, 8], [0, 40, 43, 3], [6, 7, 28, 49]];
+
+/// Palettes the CGB boot ROM sets up for software without CGB support.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CompatibilityPalettes {
+    pub bg: [u16; 4],
+    pub obj0: [u16; 4],
+    pub obj1: [u16; 4],
+}
+
+impl Default for CompatibilityPalettes {
+    /// The combination of games the boot ROM doesn't recognize, also picked with right and A.
+    fn default() -> Self {
+        CompatibilityPalettes::combination(0)
+    }
+}
+
+impl CompatibilityPalettes {
+    /// One of the 51 combinations of the boot ROM, 0 when out of range.
+    pub fn combination(index: usize) -> Self {
+        let [obj0, obj1, bg] = COMBINATIONS.get(index).copied().unwrap_or(COMBINATIONS[0]);
+        let palette = |start: u8| {
+            let mut palette = [0x0000; 4];
+            palette.copy_from_slice(&COLORS[start as usize..start as usize + 4]);
+            palette
+        };
+
+        CompatibilityPalettes {
+            bg: palette(bg),
+            obj0: palette(obj0),
+            obj1: palette(obj1),
+        }
+    }
+
+    /// The combination the boot ROM picks from the raw title bytes of a Nintendo game.
+    pub fn for_header(header: &CartridgeHeader) -> Self {
+        if !header.licensed_by_nintendo() {
+            return CompatibilityPalettes::default();
+        }
+
+        let checksum = header.title_checksum();
+        let fourth_letter = header.raw_title()[3];
+
+        let index = TITLE_CHECKSUMS
+            .iter()
+            .enumerate()
+            .filter(|&(_, &entry)| entry == checksum)
+            .map(|(index, _)| index)
+            .find(|&index| {
+                index < FIRST_AMBIGUOUS || FOURTH_LETTERS[index - FIRST_AMBIGUOUS] == fourth_letter
+            });
+
+        match index {
+            Some(index) => CompatibilityPalettes::combination(TITLE_COMBINATIONS[index] as usize),
+            None => CompatibilityPalettes::default(),
+        }
+    }
+
+    /// The combination picked by holding a direction, optionally with A or B, while the logo
+    /// is on screen. `pressed` is a mask of `JoyPadButton`s.
+    pub fn for_buttons(pressed: u8) -> Option<Self> {
+        let directions = [
+            JoyPadButton::Right,
+            JoyPadButton::Left,
+            JoyPadButton::Up,
+            JoyPadButton::Down,
+        ];
+        let direction = directions
+            .iter()
+            .position(|button| pressed & button.mask() != 0)?;
+
+        let modifier = if pressed & JoyPadButton::A.mask() != 0 {
+            1
+        } else if pressed & JoyPadButton::B.mask() != 0 {
+            2
+        } else {
+            0
+        };
+
+        Some(CompatibilityPalettes::combination(
+            MANUAL_COMBINATIONS[modifier][direction] as usize,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    fn header(title: &[u8], old_licensee: u8) -> CartridgeHeader {
+        let mut rom = vec![0x00; 0x8000];
+        rom[0x0134..0x0134 + title.len()].copy_from_slice(title);
+        rom[0x014b] = old_licensee;
+        CartridgeHeader::parse(&rom).unwrap()
+    }
+
+    #[test]
+    fn nintendo_title() {
+        assert_eq!(
+            CompatibilityPalettes::for_header(&header(b"TETRIS", 0x01)),
+            CompatibilityPalettes::combination(3)
+        );
+    }
+
+    #[test]
+    fn other_licensees_get_the_default() {
+        assert_eq!(
+            CompatibilityPalettes::for_header(&header(b"TETRIS", 0x00)),
+            CompatibilityPalettes::default()
+        );
+    }
+
+    #[test]
+    fn checksum_counts_every_raw_title_byte() {
+        // 0x80 + '[' sums to the checksum of "TETRIS", while the decoded title is only "[".
+        let header = header(&[0x80, b'['], 0x01);
+        assert_eq!(header.title(), "[");
+
+        assert_eq!(
+            CompatibilityPalettes::for_header(&header),
+            CompatibilityPalettes::combination(3)
+        );
+    }
+
+    #[test]
+    fn fourth_letter_is_the_raw_byte_at_0x137() {
+        // Checksum 0xb3 with 'B' at 0x137, the first ambiguous entry. The NUL and 0xc0 bytes
+        // are dropped from the decoded title, which is only "XYB".
+        let header = header(&[b'X', b'Y', 0x00, b'B', 0xc0], 0x01);
+        assert_eq!(header.title(), "XYB");
+
+        assert_eq!(
+            CompatibilityPalettes::for_header(&header),
+            CompatibilityPalettes::combination(36)
+        );
+    }
+
+    #[test]
+    fn buttons_override() {
+        let right_a = JoyPadButton::Right.mask() | JoyPadButton::A.mask();
+
+        assert_eq!(
+            CompatibilityPalettes::for_buttons(right_a),
+            Some(CompatibilityPalettes::combination(0))
+        );
+        assert_eq!(
+            CompatibilityPalettes::for_buttons(JoyPadButton::Left.mask()),
+            Some(CompatibilityPalettes::combination(48))
+        );
+        assert_eq!(
+            CompatibilityPalettes::for_buttons(JoyPadButton::A.mask()),
+            None
+        );
+    }
+}
@@ -0,0 +1,713 @@
+use crate::oam_bug::OamBugAccess;
+use crate::virtual_memory::{MemoryMappedPeripheral, VirtualMemory};
+use alloc::vec::Vec;
+
+const FLAG_Z: u8 = 0x80;
+const FLAG_N: u8 = 0x40;
+const FLAG_H: u8 = 0x20;
+const FLAG_C: u8 = 0x10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Registers {
+    pub a: u8,
+    pub f: u8,
+    pub b: u8,
+    pub c: u8,
+    pub d: u8,
+    pub e: u8,
+    pub h: u8,
+    pub l: u8,
+    pub sp: u16,
+    pub pc: u16,
+}
+
+impl Registers {
+    pub fn af(&self) -> u16 {
+        u16::from_be_bytes([self.a, self.f])
+    }
+
+    pub fn bc(&self) -> u16 {
+        u16::from_be_bytes([self.b, self.c])
+    }
+
+    pub fn de(&self) -> u16 {
+        u16::from_be_bytes([self.d, self.e])
+    }
+
+    pub fn hl(&self) -> u16 {
+        u16::from_be_bytes([self.h, self.l])
+    }
+
+    pub fn set_af(&mut self, value: u16) {
+        [self.a, self.f] = value.to_be_bytes();
+        self.f &= 0xf0;
+    }
+
+    pub fn set_bc(&mut self, value: u16) {
+        [self.b, self.c] = value.to_be_bytes();
+    }
+
+    pub fn set_de(&mut self, value: u16) {
+        [self.d, self.e] = value.to_be_bytes();
+    }
+
+    pub fn set_hl(&mut self, value: u16) {
+        [self.h, self.l] = value.to_be_bytes();
+    }
+
+    fn flag(&self, flag: u8) -> bool {
+        self.f & flag != 0
+    }
+
+    fn set_flags(&mut self, z: bool, n: bool, h: bool, c: bool) {
+        self.f = (z as u8) << 7 | (n as u8) << 6 | (h as u8) << 5 | (c as u8) << 4;
+    }
+}
+
+/// SM83 core. Every memory access and internal delay advances the rest of the machine by one
+/// M-cycle through `VirtualMemory::tick`.
+#[derive(Default)]
+pub struct Cpu {
+    registers: Registers,
+    ime: bool,
+    /// `EI` enables interrupts after the following instruction.
+    ime_scheduled: bool,
+    halted: bool,
+    /// `HALT` with interrupts disabled and one pending: the next opcode byte is read twice.
+    halt_bug: bool,
+    stopped: bool,
+    /// Executing an illegal opcode hangs the CPU until power off.
+    locked: bool,
+    cycles: u64,
+}
+
+impl Cpu {
+    /// Appends the registers, the interrupt and halt flags and the cycle count.
+    pub fn hash_state(&self, state: &mut Vec<u8>) {
+        let registers = &self.registers;
+        state.extend_from_slice(&[
+            registers.a,
+            registers.f,
+            registers.b,
+            registers.c,
+            registers.d,
+            registers.e,
+            registers.h,
+            registers.l,
+        ]);
+        state.extend_from_slice(&registers.sp.to_le_bytes());
+        state.extend_from_slice(&registers.pc.to_le_bytes());
+        state.extend_from_slice(&[
+            self.ime as u8,
+            self.ime_scheduled as u8,
+            self.halted as u8,
+            self.halt_bug as u8,
+            self.stopped as u8,
+            self.locked as u8,
+        ]);
+        state.extend_from_slice(&self.cycles.to_le_bytes());
+    }
+
+    pub fn registers(&self) -> &Registers {
+        &self.registers
+    }
+
+    pub fn registers_mut(&mut self) -> &mut Registers {
+        &mut self.registers
+    }
+
+    pub fn ime(&self) -> bool {
+        self.ime
+    }
+
+    pub fn halted(&self) -> bool {
+        self.halted
+    }
+
+    pub fn stopped(&self) -> bool {
+        self.stopped
+    }
+
+    pub fn locked(&self) -> bool {
+        self.locked
+    }
+
+    /// M-cycles since power on.
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    fn tick(&mut self, memory: &mut VirtualMemory) {
+        memory.tick();
+        self.cycles += 1;
+    }
+
+    fn read(&mut self, memory: &mut VirtualMemory, address: u16) -> u8 {
+        memory.oam_bug(address, OamBugAccess::Read);
+        let data = memory.read(address);
+        self.tick(memory);
+
+        data
+    }
+
+    /// Read while the address is incremented or decremented, as in `LD A, [HLI]` and `POP`.
+    fn read_inc_dec(&mut self, memory: &mut VirtualMemory, address: u16) -> u8 {
+        memory.oam_bug(address, OamBugAccess::ReadIncDec);
+        let data = memory.read(address);
+        self.tick(memory);
+
+        data
+    }
+
+    fn write(&mut self, memory: &mut VirtualMemory, address: u16, data: u8) {
+        memory.oam_bug(address, OamBugAccess::Write);
+        memory.write(address, data);
+        self.tick(memory);
+    }
+
+    /// Internal cycle spent incrementing or decrementing a 16-bit register holding `address`.
+    fn inc_dec(&mut self, memory: &mut VirtualMemory, address: u16) {
+        memory.oam_bug(address, OamBugAccess::IncDec);
+        self.tick(memory);
+    }
+
+    fn fetch(&mut self, memory: &mut VirtualMemory) -> u8 {
+        let data = self.read(memory, self.registers.pc);
+        self.registers.pc = self.registers.pc.wrapping_add(1);
+
+        data
+    }
+
+    fn fetch16(&mut self, memory: &mut VirtualMemory) -> u16 {
+        let low = self.fetch(memory);
+        let high = self.fetch(memory);
+
+        u16::from_le_bytes([low, high])
+    }
+
+    fn fetch_opcode(&mut self, memory: &mut VirtualMemory) -> u8 {
+        let opcode = memory.fetch_opcode(self.registers.pc);
+        self.tick(memory);
+
+        if self.halt_bug {
+            self.halt_bug = false;
+        } else {
+            self.registers.pc = self.registers.pc.wrapping_add(1);
+        }
+
+        opcode
+    }
+
+    fn push16(&mut self, memory: &mut VirtualMemory, value: u16) {
+        let [high, low] = value.to_be_bytes();
+
+        self.registers.sp = self.registers.sp.wrapping_sub(1);
+        self.write(memory, self.registers.sp, high);
+        self.registers.sp = self.registers.sp.wrapping_sub(1);
+        self.write(memory, self.registers.sp, low);
+    }
+
+    fn pop16(&mut self, memory: &mut VirtualMemory) -> u16 {
+        let low = self.read_inc_dec(memory, self.registers.sp);
+        self.registers.sp = self.registers.sp.wrapping_add(1);
+        let high = self.read(memory, self.registers.sp);
+        self.registers.sp = self.registers.sp.wrapping_add(1);
+
+        u16::from_le_bytes([low, high])
+    }
+
+    /// Executes one instruction, or services an interrupt, and returns the M-cycles it took.
+    pub fn step(&mut self, memory: &mut VirtualMemory) -> u32 {
+        let start = self.cycles;
+
+        if self.locked {
+            self.tick(memory);
+        } else if self.stopped {
+            self.tick(memory);
+            if memory.joypad_ref().pressed() != 0 {
+                self.stopped = false;
+            }
+        } else if self.halted && memory.pending_interrupts() == 0 {
+            self.tick(memory);
+        } else {
+            self.halted = false;
+
+            if self.ime && memory.pending_interrupts() != 0 {
+                self.dispatch_interrupt(memory);
+            } else {
+                let enable_ime = self.ime_scheduled;
+                let opcode = self.fetch_opcode(memory);
+                self.execute(memory, opcode);
+
+                // `DI` right after `EI` cancels it.
+                if enable_ime && self.ime_scheduled {
+                    self.ime = true;
+                    self.ime_scheduled = false;
+                }
+            }
+        }
+
+        for _ in 0..memory.take_cpu_stall() {
+            self.tick(memory);
+        }
+
+        (self.cycles - start) as u32
+    }
+
+    fn dispatch_interrupt(&mut self, memory: &mut VirtualMemory) {
+        self.ime = false;
+        self.tick(memory);
+        self.inc_dec(memory, self.registers.sp);
+
+        let [high, low] = self.registers.pc.to_be_bytes();
+        self.registers.sp = self.registers.sp.wrapping_sub(1);
+        self.write(memory, self.registers.sp, high);
+
+        // Pushing the high byte over IE can cancel the interrupt, which then jumps to 0x0000.
+        let pending = memory.pending_interrupts();
+        self.registers.sp = self.registers.sp.wrapping_sub(1);
+        self.write(memory, self.registers.sp, low);
+
+        self.registers.pc = if pending == 0 {
+            0x0000
+        } else {
+            let interrupt = pending.trailing_zeros() as u8;
+            memory.acknowledge_interrupt(interrupt);
+            0x0040 + interrupt as u16 * 8
+        };
+        self.tick(memory);
+    }
+
+    fn r8(&mut self, memory: &mut VirtualMemory, index: u8) -> u8 {
+        match index {
+            0 => self.registers.b,
+            1 => self.registers.c,
+            2 => self.registers.d,
+            3 => self.registers.e,
+            4 => self.registers.h,
+            5 => self.registers.l,
+            6 => self.read(memory, self.registers.hl()),
+            _ => self.registers.a,
+        }
+    }
+
+    fn set_r8(&mut self, memory: &mut VirtualMemory, index: u8, value: u8) {
+        match index {
+            0 => self.registers.b = value,
+            1 => self.registers.c = value,
+            2 => self.registers.d = value,
+            3 => self.registers.e = value,
+            4 => self.registers.h = value,
+            5 => self.registers.l = value,
+            6 => self.write(memory, self.registers.hl(), value),
+            _ => self.registers.a = value,
+        }
+    }
+
+    /// BC, DE, HL and SP.
+    fn r16(&self, index: u8) -> u16 {
+        match index {
+            0 => self.registers.bc(),
+            1 => self.registers.de(),
+            2 => self.registers.hl(),
+            _ => self.registers.sp,
+        }
+    }
+
+    fn set_r16(&mut self, index: u8, value: u16) {
+        match index {
+            0 => self.registers.set_bc(value),
+            1 => self.registers.set_de(value),
+            2 => self.registers.set_hl(value),
+            _ => self.registers.sp = value,
+        }
+    }
+
+    /// NZ, Z, NC and C.
+    fn condition(&self, index: u8) -> bool {
+        match index {
+            0 => !self.registers.flag(FLAG_Z),
+            1 => self.registers.flag(FLAG_Z),
+            2 => !self.registers.flag(FLAG_C),
+            _ => self.registers.flag(FLAG_C),
+        }
+    }
+
+    /// ADD, ADC, SUB, SBC, AND, XOR, OR and CP.
+    fn alu(&mut self, operation: u8, value: u8) {
+        let a = self.registers.a;
+        let carry = self.registers.flag(FLAG_C) as u8;
+
+        match operation {
+            0 | 1 => {
+                let carry = if operation == 1 { carry } else { 0 };
+                let result = a as u16 + value as u16 + carry as u16;
+                let half_carry = (a & 0x0f) + (value & 0x0f) + carry > 0x0f;
+
+                self.registers.a = result as u8;
+                self.registers
+                    .set_flags(result as u8 == 0, false, half_carry, result > 0xff);
+            }
+            2 | 3 | 7 => {
+                let carry = if operation == 3 { carry } else { 0 };
+                let result = (a as i16) - (value as i16) - (carry as i16);
+                let half_carry = ((a & 0x0f) as i16) - ((value & 0x0f) as i16) - (carry as i16) < 0;
+
+                if operation != 7 {
+                    self.registers.a = result as u8;
+                }
+                self.registers
+                    .set_flags(result as u8 == 0, true, half_carry, result < 0);
+            }
+            4 => {
+                self.registers.a &= value;
+                self.registers
+                    .set_flags(self.registers.a == 0, false, true, false);
+            }
+            5 => {
+                self.registers.a ^= value;
+                self.registers
+                    .set_flags(self.registers.a == 0, false, false, false);
+            }
+            _ => {
+                self.registers.a |= value;
+                self.registers
+                    .set_flags(self.registers.a == 0, false, false, false);
+            }
+        }
+    }
+
+    /// RLC, RRC, RL, RR, SLA, SRA, SWAP and SRL.
+    fn rotate(&mut self, operation: u8, value: u8) -> u8 {
+        let carry = self.registers.flag(FLAG_C) as u8;
+
+        let (result, carry_out) = match operation {
+            0 => (value.rotate_left(1), value & 0x80 != 0),
+            1 => (value.rotate_right(1), value & 0x01 != 0),
+            2 => (value << 1 | carry, value & 0x80 != 0),
+            3 => (value >> 1 | carry << 7, value & 0x01 != 0),
+            4 => (value << 1, value & 0x80 != 0),
+            5 => (value >> 1 | (value & 0x80), value & 0x01 != 0),
+            6 => (value.rotate_left(4), false),
+            _ => (value >> 1, value & 0x01 != 0),
+        };
+
+        self.registers
+            .set_flags(result == 0, false, false, carry_out);
+        result
+    }
+
+    fn add_sp_offset(&mut self, offset: u8) -> u16 {
+        let sp = self.registers.sp;
+        let half_carry = (sp & 0x0f) + (offset as u16 & 0x0f) > 0x0f;
+        let carry = (sp & 0xff) + offset as u16 > 0xff;
+
+        self.registers.set_flags(false, false, half_carry, carry);
+        sp.wrapping_add(offset as i8 as u16)
+    }
+
+    fn jump_relative(&mut self, memory: &mut VirtualMemory, offset: u8) {
+        self.registers.pc = self.registers.pc.wrapping_add(offset as i8 as u16);
+        self.tick(memory);
+    }
+
+    fn call(&mut self, memory: &mut VirtualMemory, address: u16) {
+        self.inc_dec(memory, self.registers.sp);
+        self.push16(memory, self.registers.pc);
+        self.registers.pc = address;
+    }
+
+    fn ret(&mut self, memory: &mut VirtualMemory) {
+        self.registers.pc = self.pop16(memory);
+        self.tick(memory);
+    }
+
+    fn daa(&mut self) {
+        let mut a = self.registers.a;
+        let mut carry = self.registers.flag(FLAG_C);
+        let subtract = self.registers.flag(FLAG_N);
+        let half_carry = self.registers.flag(FLAG_H);
+
+        if subtract {
+            if half_carry {
+                a = a.wrapping_sub(0x06);
+            }
+            if carry {
+                a = a.wrapping_sub(0x60);
+            }
+        } else {
+            if carry || a > 0x99 {
+                a = a.wrapping_add(0x60);
+                carry = true;
+            }
+            if half_carry || a & 0x0f > 0x09 {
+                a = a.wrapping_add(0x06);
+            }
+        }
+
+        self.registers.a = a;
+        self.registers.set_flags(a == 0, subtract, false, carry);
+    }
+
+    fn execute(&mut self, memory: &mut VirtualMemory, opcode: u8) {
+        let y = (opcode >> 3) & 0x07;
+        let z = opcode & 0x07;
+        let p = y >> 1;
+
+        match opcode {
+            0x00 => {}
+            0x01 | 0x11 | 0x21 | 0x31 => {
+                let value = self.fetch16(memory);
+                self.set_r16(p, value);
+            }
+            0x02 | 0x12 | 0x22 | 0x32 => {
+                let address = self.r16_memory(p);
+                self.write(memory, address, self.registers.a);
+            }
+            0x0a | 0x1a | 0x2a | 0x3a => {
+                let address = self.r16_memory(p);
+                self.registers.a = if p >= 2 {
+                    self.read_inc_dec(memory, address)
+                } else {
+                    self.read(memory, address)
+                };
+            }
+            0x03 | 0x13 | 0x23 | 0x33 => {
+                let value = self.r16(p);
+                self.inc_dec(memory, value);
+                self.set_r16(p, value.wrapping_add(1));
+            }
+            0x0b | 0x1b | 0x2b | 0x3b => {
+                let value = self.r16(p);
+                self.inc_dec(memory, value);
+                self.set_r16(p, value.wrapping_sub(1));
+            }
+            0x09 | 0x19 | 0x29 | 0x39 => {
+                let hl = self.registers.hl();
+                let value = self.r16(p);
+                let result = hl as u32 + value as u32;
+                let half_carry = (hl & 0x0fff) + (value & 0x0fff) > 0x0fff;
+
+                self.registers.set_flags(
+                    self.registers.flag(FLAG_Z),
+                    false,
+                    half_carry,
+                    result > 0xffff,
+                );
+                self.registers.set_hl(result as u16);
+                self.tick(memory);
+            }
+            0x04 | 0x0c | 0x14 | 0x1c | 0x24 | 0x2c | 0x34 | 0x3c => {
+                let value = self.r8(memory, y);
+                let result = value.wrapping_add(1);
+                self.registers.set_flags(
+                    result == 0,
+                    false,
+                    value & 0x0f == 0x0f,
+                    self.registers.flag(FLAG_C),
+                );
+                self.set_r8(memory, y, result);
+            }
+            0x05 | 0x0d | 0x15 | 0x1d | 0x25 | 0x2d | 0x35 | 0x3d => {
+                let value = self.r8(memory, y);
+                let result = value.wrapping_sub(1);
+                self.registers.set_flags(
+                    result == 0,
+                    true,
+                    value & 0x0f == 0x00,
+                    self.registers.flag(FLAG_C),
+                );
+                self.set_r8(memory, y, result);
+            }
+            0x06 | 0x0e | 0x16 | 0x1e | 0x26 | 0x2e | 0x36 | 0x3e => {
+                let value = self.fetch(memory);
+                self.set_r8(memory, y, value);
+            }
+            0x07 | 0x0f | 0x17 | 0x1f => {
+                self.registers.a = self.rotate(y, self.registers.a);
+                self.registers.f &= !FLAG_Z;
+            }
+            0x08 => {
+                let address = self.fetch16(memory);
+                let [high, low] = self.registers.sp.to_be_bytes();
+                self.write(memory, address, low);
+                self.write(memory, address.wrapping_add(1), high);
+            }
+            0x10 => {
+                self.fetch(memory);
+                if !memory.stop() {
+                    self.stopped = memory.joypad_ref().pressed() == 0;
+                }
+            }
+            0x18 => {
+                let offset = self.fetch(memory);
+                self.jump_relative(memory, offset);
+            }
+            0x20 | 0x28 | 0x30 | 0x38 => {
+                let offset = self.fetch(memory);
+                if self.condition(y - 4) {
+                    self.jump_relative(memory, offset);
+                }
+            }
+            0x27 => self.daa(),
+            0x2f => {
+                self.registers.a = !self.registers.a;
+                self.registers.f |= FLAG_N | FLAG_H;
+            }
+            0x37 => self.registers.f = (self.registers.f & FLAG_Z) | FLAG_C,
+            0x3f => self.registers.f = (self.registers.f & (FLAG_Z | FLAG_C)) ^ FLAG_C,
+            0x76 => {
+                if !self.ime && memory.pending_interrupts() != 0 {
+                    self.halt_bug = true;
+                } else {
+                    self.halted = true;
+                }
+            }
+            0x40..=0x7f => {
+                let value = self.r8(memory, z);
+                self.set_r8(memory, y, value);
+            }
+            0x80..=0xbf => {
+                let value = self.r8(memory, z);
+                self.alu(y, value);
+            }
+            0xc0 | 0xc8 | 0xd0 | 0xd8 => {
+                self.tick(memory);
+                if self.condition(y) {
+                    self.ret(memory);
+                }
+            }
+            0xc1 | 0xd1 | 0xe1 | 0xf1 => {
+                let value = self.pop16(memory);
+                match p & 0x03 {
+                    3 => self.registers.set_af(value),
+                    index => self.set_r16(index, value),
+                }
+            }
+            0xc5 | 0xd5 | 0xe5 | 0xf5 => {
+                let value = match p & 0x03 {
+                    3 => self.registers.af(),
+                    index => self.r16(index),
+                };
+                self.inc_dec(memory, self.registers.sp);
+                self.push16(memory, value);
+            }
+            0xc2 | 0xca | 0xd2 | 0xda => {
+                let address = self.fetch16(memory);
+                if self.condition(y) {
+                    self.registers.pc = address;
+                    self.tick(memory);
+                }
+            }
+            0xc3 => {
+                self.registers.pc = self.fetch16(memory);
+                self.tick(memory);
+            }
+            0xc4 | 0xcc | 0xd4 | 0xdc => {
+                let address = self.fetch16(memory);
+                if self.condition(y) {
+                    self.call(memory, address);
+                }
+            }
+            0xcd => {
+                let address = self.fetch16(memory);
+                self.call(memory, address);
+            }
+            0xc6 | 0xce | 0xd6 | 0xde | 0xe6 | 0xee | 0xf6 | 0xfe => {
+                let value = self.fetch(memory);
+                self.alu(y, value);
+            }
+            0xc7 | 0xcf | 0xd7 | 0xdf | 0xe7 | 0xef | 0xf7 | 0xff => {
+                self.call(memory, y as u16 * 8);
+            }
+            0xc9 => self.ret(memory),
+            0xd9 => {
+                self.ret(memory);
+                self.ime = true;
+            }
+            0xcb => {
+                let opcode = self.fetch(memory);
+                self.execute_cb(memory, opcode);
+            }
+            0xe0 => {
+                let offset = self.fetch(memory);
+                self.write(memory, 0xff00 | offset as u16, self.registers.a);
+            }
+            0xf0 => {
+                let offset = self.fetch(memory);
+                self.registers.a = self.read(memory, 0xff00 | offset as u16);
+            }
+            0xe2 => self.write(memory, 0xff00 | self.registers.c as u16, self.registers.a),
+            0xf2 => self.registers.a = self.read(memory, 0xff00 | self.registers.c as u16),
+            0xe8 => {
+                let offset = self.fetch(memory);
+                self.registers.sp = self.add_sp_offset(offset);
+                self.tick(memory);
+                self.tick(memory);
+            }
+            0xf8 => {
+                let offset = self.fetch(memory);
+                let value = self.add_sp_offset(offset);
+                self.registers.set_hl(value);
+                self.tick(memory);
+            }
+            0xe9 => self.registers.pc = self.registers.hl(),
+            0xf9 => {
+                self.registers.sp = self.registers.hl();
+                self.tick(memory);
+            }
+            0xea => {
+                let address = self.fetch16(memory);
+                self.write(memory, address, self.registers.a);
+            }
+            0xfa => {
+                let address = self.fetch16(memory);
+                self.registers.a = self.read(memory, address);
+            }
+            0xf3 => {
+                self.ime = false;
+                self.ime_scheduled = false;
+            }
+            0xfb => self.ime_scheduled = true,
+            // 0xD3, 0xDB, 0xDD, 0xE3, 0xE4, 0xEB, 0xEC, 0xED, 0xF4, 0xFC and 0xFD.
+            _ => self.locked = true,
+        }
+    }
+
+    /// Address for `LD [rr], A` and `LD A, [rr]`: BC, DE, then HL incremented or decremented.
+    fn r16_memory(&mut self, index: u8) -> u16 {
+        let hl = self.registers.hl();
+
+        match index {
+            0 => self.registers.bc(),
+            1 => self.registers.de(),
+            2 => {
+                self.registers.set_hl(hl.wrapping_add(1));
+                hl
+            }
+            _ => {
+                self.registers.set_hl(hl.wrapping_sub(1));
+                hl
+            }
+        }
+    }
+
+    fn execute_cb(&mut self, memory: &mut VirtualMemory, opcode: u8) {
+        let y = (opcode >> 3) & 0x07;
+        let z = opcode & 0x07;
+        let value = self.r8(memory, z);
+
+        match opcode >> 6 {
+            0 => {
+                let result = self.rotate(y, value);
+                self.set_r8(memory, z, result);
+            }
+            1 => {
+                let zero = value & (1 << y) == 0;
+                self.registers.f = (self.registers.f & FLAG_C) | FLAG_H | (zero as u8) << 7;
+            }
+            2 => self.set_r8(memory, z, value & !(1 << y)),
+            _ => self.set_r8(memory, z, value | (1 << y)),
+        }
+    }
+}
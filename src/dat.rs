@@ -0,0 +1,533 @@
+use crate::hash::{crc32, RomHashes};
+use alloc::collections::BTreeMap;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt::{Display, Formatter};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DatError {
+    UnexpectedEof,
+    MalformedTag(usize),
+    InvalidHash(String),
+    InvalidSize(String),
+}
+
+impl Display for DatError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self {
+            DatError::UnexpectedEof => write!(f, "Unexpected end of DAT file"),
+            DatError::MalformedTag(position) => write!(f, "Malformed tag at byte {}", position),
+            DatError::InvalidHash(hash) => write!(f, "Invalid hash: {}", hash),
+            DatError::InvalidSize(size) => write!(f, "Invalid ROM size: {}", size),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DumpStatus {
+    Good,
+    Verified,
+    BadDump,
+    NoDump,
+}
+
+impl From<&str> for DumpStatus {
+    fn from(value: &str) -> Self {
+        match value {
+            "verified" => DumpStatus::Verified,
+            "baddump" => DumpStatus::BadDump,
+            "nodump" => DumpStatus::NoDump,
+            _ => DumpStatus::Good,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DatRom {
+    pub name: String,
+    pub size: Option<usize>,
+    pub crc32: Option<u32>,
+    pub md5: Option<[u8; 16]>,
+    pub sha1: Option<[u8; 20]>,
+    pub status: DumpStatus,
+}
+
+impl DatRom {
+    fn matches(&self, hashes: &RomHashes) -> bool {
+        if self.size.is_some_and(|size| size != hashes.size) {
+            return false;
+        }
+
+        match (self.sha1, self.md5, self.crc32) {
+            (Some(sha1), _, _) => sha1 == hashes.sha1,
+            (None, Some(md5), _) => md5 == hashes.md5,
+            (None, None, Some(crc32)) => crc32 == hashes.crc32,
+            (None, None, None) => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DatGame {
+    pub name: String,
+    pub description: String,
+    pub roms: Vec<DatRom>,
+}
+
+impl DatGame {
+    /// The region is the first parenthesized group of the No-Intro/Redump naming convention,
+    /// e.g. "USA, Europe" in "Tetris (USA, Europe) (Rev 1)".
+    pub fn region(&self) -> Option<&str> {
+        self.tags().next()
+    }
+
+    /// Revision from a "(Rev x)" tag. Dumps without that tag are the original release.
+    pub fn revision(&self) -> Option<&str> {
+        self.tags().find_map(|tag| tag.strip_prefix("Rev "))
+    }
+
+    fn tags(&self) -> impl Iterator<Item = &str> {
+        self.name
+            .split('(')
+            .skip(1)
+            .filter_map(|tag| tag.split_once(')').map(|(tag, _)| tag.trim()))
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Identification<'a> {
+    pub game: &'a DatGame,
+    pub rom: &'a DatRom,
+    /// The ROM only matches once the trailing data past the size listed in the DAT is removed.
+    pub overdump: bool,
+}
+
+impl Identification<'_> {
+    pub fn bad_dump(&self) -> bool {
+        self.rom.status == DumpStatus::BadDump
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct DatFile {
+    pub name: String,
+    pub version: String,
+    pub games: Vec<DatGame>,
+}
+
+impl DatFile {
+    pub fn parse(content: &str) -> Result<Self, DatError> {
+        let mut dat = DatFile::default();
+        let mut game: Option<DatGame> = None;
+        let mut text_target: Option<&str> = None;
+        let mut in_header = false;
+
+        for event in XmlReader::new(content) {
+            match event? {
+                XmlEvent::Start {
+                    name,
+                    attributes,
+                    self_closing,
+                } => {
+                    let attribute = |key: &str| {
+                        attributes
+                            .iter()
+                            .find(|(attribute, _)| *attribute == key)
+                            .map(|(_, value)| value.as_str())
+                    };
+
+                    match name {
+                        "header" => in_header = !self_closing,
+                        "game" | "machine" => {
+                            game = Some(DatGame {
+                                name: attribute("name").unwrap_or_default().to_string(),
+                                description: String::new(),
+                                roms: Vec::new(),
+                            })
+                        }
+                        "rom" => {
+                            if let Some(game) = game.as_mut() {
+                                game.roms.push(DatRom {
+                                    name: attribute("name").unwrap_or_default().to_string(),
+                                    size: attribute("size").map(parse_size).transpose()?,
+                                    crc32: attribute("crc")
+                                        .map(|crc| {
+                                            u32::from_str_radix(crc, 16)
+                                                .map_err(|_| DatError::InvalidHash(crc.to_string()))
+                                        })
+                                        .transpose()?,
+                                    md5: attribute("md5").map(parse_digest).transpose()?,
+                                    sha1: attribute("sha1").map(parse_digest).transpose()?,
+                                    status: attribute("status").unwrap_or_default().into(),
+                                });
+                            }
+                        }
+                        _ => {}
+                    }
+
+                    text_target = if self_closing { None } else { Some(name) };
+                }
+                XmlEvent::Text(text) => match (text_target, game.as_mut()) {
+                    (Some("description"), Some(game)) => game.description = text,
+                    (Some("name"), None) if in_header => dat.name = text,
+                    (Some("version"), None) if in_header => dat.version = text,
+                    _ => {}
+                },
+                XmlEvent::End(name) => {
+                    match name {
+                        "header" => in_header = false,
+                        "game" | "machine" => dat.games.extend(game.take()),
+                        _ => {}
+                    }
+
+                    text_target = None;
+                }
+            }
+        }
+
+        Ok(dat)
+    }
+
+    /// Matches `hashes`, computed over the whole of `content`, then the prefixes of `content`
+    /// for overdumps.
+    pub fn identify(&self, content: &[u8], hashes: &RomHashes) -> Option<Identification<'_>> {
+        self.identify_hashes(hashes)
+            .or_else(|| self.identify_overdump(content))
+    }
+
+    pub fn identify_hashes(&self, hashes: &RomHashes) -> Option<Identification<'_>> {
+        self.roms()
+            .find(|(_, rom)| rom.matches(hashes))
+            .map(|(game, rom)| Identification {
+                game,
+                rom,
+                overdump: false,
+            })
+    }
+
+    fn identify_overdump(&self, content: &[u8]) -> Option<Identification<'_>> {
+        let mut prefix_crcs = BTreeMap::new();
+
+        self.roms()
+            .find(|(_, rom)| match (rom.size, rom.crc32) {
+                (Some(size), Some(crc)) if size > 0 && size < content.len() => {
+                    *prefix_crcs
+                        .entry(size)
+                        .or_insert_with(|| crc32(&content[..size]))
+                        == crc
+                }
+                _ => false,
+            })
+            .map(|(game, rom)| Identification {
+                game,
+                rom,
+                overdump: true,
+            })
+    }
+
+    fn roms(&self) -> impl Iterator<Item = (&DatGame, &DatRom)> {
+        self.games
+            .iter()
+            .flat_map(|game| game.roms.iter().map(move |rom| (game, rom)))
+    }
+}
+
+fn parse_size(size: &str) -> Result<usize, DatError> {
+    let parsed = match size.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16),
+        None => size.parse(),
+    };
+
+    parsed.map_err(|_| DatError::InvalidSize(size.to_string()))
+}
+
+fn parse_digest<const N: usize>(hex: &str) -> Result<[u8; N], DatError> {
+    let invalid = || DatError::InvalidHash(hex.to_string());
+
+    if hex.len() != N * 2 || !hex.is_ascii() {
+        return Err(invalid());
+    }
+
+    let mut digest = [0u8; N];
+    for (i, byte) in digest.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).map_err(|_| invalid())?;
+    }
+
+    Ok(digest)
+}
+
+enum XmlEvent<'a> {
+    Start {
+        name: &'a str,
+        attributes: Vec<(&'a str, String)>,
+        self_closing: bool,
+    },
+    End(&'a str),
+    Text(String),
+}
+
+/// Just enough XML to read DAT files: elements, attributes, text and the predefined entities.
+/// Declarations, doctypes and comments are skipped.
+struct XmlReader<'a> {
+    content: &'a str,
+    position: usize,
+}
+
+impl<'a> XmlReader<'a> {
+    fn new(content: &'a str) -> Self {
+        Self {
+            content,
+            position: 0,
+        }
+    }
+
+    fn skip_until(&mut self, terminator: &str) -> Result<(), DatError> {
+        match self.content[self.position..].find(terminator) {
+            Some(offset) => {
+                self.position += offset + terminator.len();
+                Ok(())
+            }
+            None => Err(DatError::UnexpectedEof),
+        }
+    }
+
+    fn read_tag(&mut self) -> Result<Option<XmlEvent<'a>>, DatError> {
+        let rest = &self.content[self.position..];
+
+        if rest.starts_with("<!--") {
+            self.skip_until("-->")?;
+            return Ok(None);
+        }
+
+        if rest.starts_with("<?") {
+            self.skip_until("?>")?;
+            return Ok(None);
+        }
+
+        if rest.starts_with("<!") {
+            self.skip_until(">")?;
+            return Ok(None);
+        }
+
+        let start = self.position;
+        let end = rest.find('>').ok_or(DatError::UnexpectedEof)?;
+        let tag = &rest[1..end];
+        self.position += end + 1;
+
+        if let Some(name) = tag.strip_prefix('/') {
+            return Ok(Some(XmlEvent::End(name.trim())));
+        }
+
+        let (tag, self_closing) = match tag.strip_suffix('/') {
+            Some(tag) => (tag, true),
+            None => (tag, false),
+        };
+
+        let name_end = tag
+            .find(|c: char| c.is_ascii_whitespace())
+            .unwrap_or(tag.len());
+        let name = &tag[..name_end];
+        if name.is_empty() {
+            return Err(DatError::MalformedTag(start));
+        }
+
+        let mut attributes = Vec::new();
+        let mut rest = tag[name_end..].trim_start();
+
+        while !rest.is_empty() {
+            let (key, value) = rest.split_once('=').ok_or(DatError::MalformedTag(start))?;
+            let value = value.trim_start();
+            let quote = value
+                .chars()
+                .next()
+                .filter(|quote| *quote == '"' || *quote == '\'')
+                .ok_or(DatError::MalformedTag(start))?;
+            let value_end = value[1..]
+                .find(quote)
+                .ok_or(DatError::MalformedTag(start))?;
+
+            attributes.push((key.trim(), decode_entities(&value[1..=value_end])));
+            rest = value[value_end + 2..].trim_start();
+        }
+
+        Ok(Some(XmlEvent::Start {
+            name,
+            attributes,
+            self_closing,
+        }))
+    }
+}
+
+impl<'a> Iterator for XmlReader<'a> {
+    type Item = Result<XmlEvent<'a>, DatError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.position < self.content.len() {
+            let rest = &self.content[self.position..];
+
+            if rest.starts_with('<') {
+                match self.read_tag() {
+                    Ok(Some(event)) => return Some(Ok(event)),
+                    Ok(None) => continue,
+                    Err(error) => {
+                        self.position = self.content.len();
+                        return Some(Err(error));
+                    }
+                }
+            }
+
+            let end = rest.find('<').unwrap_or(rest.len());
+            self.position += end;
+
+            let text = rest[..end].trim();
+            if !text.is_empty() {
+                return Some(Ok(XmlEvent::Text(decode_entities(text))));
+            }
+        }
+
+        None
+    }
+}
+
+fn decode_entities(text: &str) -> String {
+    let mut output = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(start) = rest.find('&') {
+        output.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let Some(end) = rest.find(';') else {
+            break;
+        };
+
+        let decoded = match &rest[1..end] {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            entity => entity
+                .strip_prefix("#x")
+                .map(|hex| u32::from_str_radix(hex, 16))
+                .or_else(|| entity.strip_prefix('#').map(|decimal| decimal.parse()))
+                .and_then(|code| code.ok())
+                .and_then(char::from_u32),
+        };
+
+        match decoded {
+            Some(decoded) => {
+                output.push(decoded);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                output.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+
+    output.push_str(rest);
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hash::to_hex;
+    use alloc::format;
+    use alloc::vec;
+
+    fn dat_for(content: &[u8], status: &str) -> String {
+        let hashes = RomHashes::compute(content);
+
+        format!(
+            r#"<?xml version="1.0"?>
+<!DOCTYPE datafile>
+<datafile>
+    <header>
+        <name>Nintendo - Game Boy</name>
+        <version>20240101</version>
+    </header>
+    <!-- a comment -->
+    <game name="Test &amp; Game (USA, Europe) (Rev 1)">
+        <description>Test &amp; Game</description>
+        <rom name="test.gb" size="{}" crc="{:08X}" md5="{}" sha1="{}" status="{}"/>
+    </game>
+</datafile>"#,
+            hashes.size,
+            hashes.crc32,
+            to_hex(&hashes.md5),
+            to_hex(&hashes.sha1),
+            status
+        )
+    }
+
+    #[test]
+    fn parse_header_and_games() {
+        let dat = DatFile::parse(&dat_for(&[0x12; 0x100], "verified")).unwrap();
+
+        assert_eq!(dat.name, "Nintendo - Game Boy");
+        assert_eq!(dat.version, "20240101");
+        assert_eq!(dat.games.len(), 1);
+
+        let game = &dat.games[0];
+        assert_eq!(game.name, "Test & Game (USA, Europe) (Rev 1)");
+        assert_eq!(game.description, "Test & Game");
+        assert_eq!(game.region(), Some("USA, Europe"));
+        assert_eq!(game.revision(), Some("1"));
+        assert_eq!(game.roms[0].size, Some(0x100));
+        assert_eq!(game.roms[0].status, DumpStatus::Verified);
+    }
+
+    #[test]
+    fn identify_exact_match() {
+        let content = vec![0x34; 0x100];
+        let dat = DatFile::parse(&dat_for(&content, "baddump")).unwrap();
+
+        let identification = dat
+            .identify(&content, &RomHashes::compute(&content))
+            .unwrap();
+        assert!(!identification.overdump);
+        assert!(identification.bad_dump());
+    }
+
+    #[test]
+    fn identify_overdump() {
+        let mut content = vec![0x56; 0x100];
+        let dat = DatFile::parse(&dat_for(&content, "")).unwrap();
+        content.extend_from_slice(&[0xff; 0x100]);
+
+        let identification = dat
+            .identify(&content, &RomHashes::compute(&content))
+            .unwrap();
+        assert!(identification.overdump);
+    }
+
+    #[test]
+    fn unknown_rom() {
+        let dat = DatFile::parse(&dat_for(&[0x78; 0x100], "")).unwrap();
+        let content = [0x9a; 0x100];
+
+        assert!(dat
+            .identify(&content, &RomHashes::compute(&content))
+            .is_none());
+    }
+
+    #[test]
+    fn decode_numeric_entities() {
+        assert_eq!(decode_entities("&#65;&#x42;&lt;&unknown;"), "AB<&unknown;");
+    }
+
+    #[test]
+    fn malformed_dat() {
+        assert_eq!(
+            DatFile::parse("<datafile><game name=\"x\"").unwrap_err(),
+            DatError::UnexpectedEof
+        );
+        assert!(matches!(
+            DatFile::parse(r#"<game><rom crc="xyz"/></game>"#),
+            Err(DatError::InvalidHash(_))
+        ));
+    }
+}
@@ -0,0 +1,209 @@
+/// OAM DMA transfer started by writing 0xFF46. After a one M-cycle start-up delay, one byte is
+/// copied every M-cycle for 160 M-cycles. Writing 0xFF46 again restarts the transfer, while the
+/// previous one keeps running during the new start-up delay.
+#[derive(Default)]
+pub struct OamDma {
+    register: u8,
+    starting: Option<(u16, u8)>,
+    transfer: Option<(u16, u16)>,
+    last_byte: u8,
+}
+
+impl OamDma {
+    pub const LENGTH: u16 = 0xA0;
+    const START_UP_DELAY: u8 = 1;
+
+    pub fn register(&self) -> u8 {
+        self.register
+    }
+
+    pub fn start(&mut self, data: u8) {
+        self.register = data;
+        self.starting = Some(((data as u16) << 8, OamDma::START_UP_DELAY));
+    }
+
+    /// OAM is only blocked once bytes are being transferred, not during the start-up delay of
+    /// the first transfer.
+    pub fn is_active(&self) -> bool {
+        self.transfer.is_some()
+    }
+
+    pub fn source(&self) -> Option<u16> {
+        self.transfer.map(|(source, _)| source)
+    }
+
+    /// Byte currently on the bus used by the transfer.
+    pub fn last_byte(&self) -> u8 {
+        self.last_byte
+    }
+
+    /// Advances one M-cycle and returns the source address and OAM offset of the byte to copy
+    /// during it, if any.
+    pub fn tick(&mut self) -> Option<(u16, u16)> {
+        match self.starting {
+            Some((source, 0)) => {
+                self.starting = None;
+                self.transfer = Some((source, 0));
+            }
+            Some((source, delay)) => self.starting = Some((source, delay - 1)),
+            None => {}
+        }
+
+        let (source, index) = self.transfer?;
+        self.transfer = if index + 1 < OamDma::LENGTH {
+            Some((source, index + 1))
+        } else {
+            None
+        };
+
+        Some((source + index, index))
+    }
+
+    pub fn set_last_byte(&mut self, data: u8) {
+        self.last_byte = data;
+    }
+}
+
+/// CGB VRAM DMA set up through HDMA1-HDMA5, copying blocks of 16 bytes to the selected VRAM
+/// bank. General purpose DMA copies all the blocks at once, H-Blank DMA one block at the start
+/// of each H-Blank.
+pub struct VramDma {
+    source: u16,
+    /// Offset in VRAM.
+    destination: u16,
+    /// Blocks left minus one, as HDMA5 reports them.
+    remaining: u8,
+    hblank: bool,
+}
+
+impl Default for VramDma {
+    /// HDMA5 reads 0xFF until the first transfer, like after one.
+    fn default() -> Self {
+        Self {
+            source: 0x0000,
+            destination: 0x0000,
+            remaining: 0x7f,
+            hblank: false,
+        }
+    }
+}
+
+impl VramDma {
+    pub const BLOCK_SIZE: u16 = 0x10;
+
+    /// Handles a write to HDMA1-HDMA5, at offsets 0x51-0x55. Returns the number of blocks to
+    /// copy right away when a general purpose transfer is started.
+    pub fn write(&mut self, address: u16, data: u8) -> Option<u8> {
+        match address {
+            0x51 => self.source = (self.source & 0x00ff) | (data as u16) << 8,
+            0x52 => self.source = (self.source & 0xff00) | (data & 0xf0) as u16,
+            0x53 => self.destination = (self.destination & 0x00ff) | ((data & 0x1f) as u16) << 8,
+            0x54 => self.destination = (self.destination & 0xff00) | (data & 0xf0) as u16,
+            // Clearing bit 7 during an H-Blank transfer stops it instead of starting a new one.
+            0x55 if self.hblank && data & 0x80 == 0 => self.hblank = false,
+            0x55 if data & 0x80 != 0 => {
+                self.remaining = data & 0x7f;
+                self.hblank = true;
+            }
+            0x55 => {
+                self.remaining = 0x7f;
+                return Some((data & 0x7f) + 1);
+            }
+            _ => {}
+        }
+
+        None
+    }
+
+    /// HDMA5: bit 7 clear while an H-Blank transfer is running, 0xFF once a transfer is done.
+    pub fn status(&self) -> u8 {
+        ((!self.hblank) as u8) << 7 | self.remaining
+    }
+
+    pub fn hblank_active(&self) -> bool {
+        self.hblank
+    }
+
+    /// Source address and VRAM offset of the next block, moving past it.
+    pub fn next_block(&mut self) -> (u16, u16) {
+        let block = (self.source, self.destination);
+        self.source = self.source.wrapping_add(VramDma::BLOCK_SIZE);
+        self.destination = (self.destination + VramDma::BLOCK_SIZE) & 0x1ff0;
+
+        if self.hblank {
+            self.remaining = self.remaining.wrapping_sub(1) & 0x7f;
+            self.hblank = self.remaining != 0x7f;
+        }
+
+        block
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn status_before_any_transfer() {
+        assert_eq!(VramDma::default().status(), 0xff);
+    }
+
+    #[test]
+    fn general_purpose_transfer() {
+        let mut dma = VramDma::default();
+
+        assert_eq!(dma.write(0x55, 0x03), Some(4));
+        assert!(!dma.hblank_active());
+        assert_eq!(dma.status(), 0xff);
+    }
+
+    #[test]
+    fn hblank_transfer_counts_down() {
+        let mut dma = VramDma::default();
+
+        assert_eq!(dma.write(0x55, 0x82), None);
+        assert!(dma.hblank_active());
+        assert_eq!(dma.status(), 0x02);
+
+        dma.next_block();
+        assert_eq!(dma.status(), 0x01);
+        dma.next_block();
+        assert_eq!(dma.status(), 0x00);
+        dma.next_block();
+        assert!(!dma.hblank_active());
+        assert_eq!(dma.status(), 0xff);
+    }
+
+    #[test]
+    fn stopping_an_hblank_transfer_keeps_the_remaining_blocks() {
+        let mut dma = VramDma::default();
+        dma.write(0x55, 0x85);
+        dma.next_block();
+
+        assert_eq!(dma.write(0x55, 0x00), None);
+        assert!(!dma.hblank_active());
+        assert_eq!(dma.status(), 0x84);
+    }
+
+    #[test]
+    fn writing_bit_7_restarts_an_hblank_transfer() {
+        let mut dma = VramDma::default();
+        dma.write(0x55, 0x85);
+        dma.write(0x55, 0x81);
+
+        assert!(dma.hblank_active());
+        assert_eq!(dma.status(), 0x01);
+    }
+
+    #[test]
+    fn addresses_ignore_the_low_nibble_and_wrap_in_vram() {
+        let mut dma = VramDma::default();
+        dma.write(0x51, 0xc1);
+        dma.write(0x52, 0x2f);
+        dma.write(0x53, 0xff);
+        dma.write(0x54, 0xff);
+
+        assert_eq!(dma.next_block(), (0xc120, 0x1ff0));
+        assert_eq!(dma.next_block(), (0xc130, 0x0000));
+    }
+}
@@ -0,0 +1,529 @@
+use crate::audio::Apu;
+use crate::boot_rom::{self, BootRomError};
+use crate::cartridge::Cartridge;
+use crate::cpu::Cpu;
+use crate::graphics::DmgPalette;
+use crate::hash;
+use crate::joypad::JoyPad;
+use crate::model::Model;
+use crate::movie::{Desync, Movie, MovieBoot};
+use crate::power_on::PowerOnSettings;
+use crate::virtual_memory::VirtualMemory;
+use crate::watchpoint::WatchHit;
+use alloc::vec::Vec;
+
+/// Why `run_cycles` or `run_frame` returned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    /// The PPU entered VBlank.
+    FrameDone,
+    CyclesDone,
+    /// A watchpoint with the pause action was hit.
+    Watchpoint(WatchHit),
+}
+
+/// How the machine gets from power on to the cartridge at 0x0100.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum Boot {
+    /// The DMG boot ROM built into the crate on the DMG, `Skip` on the other models.
+    #[default]
+    Default,
+    /// A boot ROM dumped from the selected model.
+    BootRom(Vec<u8>),
+    /// Starts at 0x0100 with the state the boot ROM leaves behind.
+    Skip,
+}
+
+pub struct GameBoyBuilder {
+    cartridge: Cartridge,
+    model: Option<Model>,
+    boot: Boot,
+    palette: DmgPalette,
+    sample_rate: u32,
+    power_on: PowerOnSettings,
+    held_buttons: u8,
+    /// SHA-1 the boot ROM must have to play a movie back.
+    movie_boot_rom: Option<[u8; 20]>,
+}
+
+impl GameBoyBuilder {
+    /// Overrides the model picked from the cartridge header.
+    pub fn model(mut self, model: Model) -> Self {
+        self.model = Some(model);
+        self
+    }
+
+    /// 256 bytes for the DMG, MGB and SGB models, 2304 bytes for the CGB and AGB.
+    pub fn boot_rom(mut self, boot_rom: Vec<u8>) -> Self {
+        self.boot = Boot::BootRom(boot_rom);
+        self
+    }
+
+    pub fn skip_boot(mut self) -> Self {
+        self.boot = Boot::Skip;
+        self
+    }
+
+    pub fn palette(mut self, palette: DmgPalette) -> Self {
+        self.palette = palette;
+        self
+    }
+
+    pub fn sample_rate(mut self, sample_rate: u32) -> Self {
+        self.sample_rate = sample_rate;
+        self
+    }
+
+    pub fn power_on(mut self, power_on: PowerOnSettings) -> Self {
+        self.power_on = power_on;
+        self
+    }
+
+    /// Buttons held down at power on, a mask of `JoyPadButton`s. On the CGB, a direction with
+    /// or without A or B picks the palettes of DMG software.
+    pub fn held_buttons(mut self, held_buttons: u8) -> Self {
+        self.held_buttons = held_buttons;
+        self
+    }
+
+    /// Model, power-on settings, boot and held buttons a movie was recorded with. Movies only
+    /// keep the hash of a dumped boot ROM, so the dump itself goes to `boot_rom`, and `build`
+    /// checks it is the same one.
+    pub fn movie(mut self, movie: &Movie) -> Self {
+        self.movie_boot_rom = None;
+        match movie.boot {
+            MovieBoot::Default => self.boot = Boot::Default,
+            MovieBoot::BootRom(sha1) => self.movie_boot_rom = Some(sha1),
+            MovieBoot::Skip => self.boot = Boot::Skip,
+        }
+
+        self.model(movie.model)
+            .power_on(movie.power_on)
+            .held_buttons(movie.held_buttons)
+    }
+
+    pub fn build(self) -> Result<GameBoy, BootRomError> {
+        if let Some(sha1) = self.movie_boot_rom {
+            match &self.boot {
+                Boot::BootRom(boot_rom) if hash::sha1(boot_rom) == sha1 => {}
+                _ => return Err(BootRomError::MovieMismatch),
+            }
+        }
+        if let Boot::BootRom(boot_rom) = &self.boot {
+            boot_rom::validate(self.selected_model(), boot_rom)?;
+        }
+
+        Ok(self.build_validated())
+    }
+
+    fn selected_model(&self) -> Model {
+        self.model
+            .unwrap_or_else(|| Model::for_header(self.cartridge.header()))
+    }
+
+    /// Only a boot ROM passed to `boot_rom` can have the wrong size, so this can't fail once it
+    /// has been validated.
+    fn build_validated(self) -> GameBoy {
+        let model = self.selected_model();
+        let post_boot_registers = model.post_boot_registers(self.cartridge.header());
+        let mut memory = VirtualMemory::with_power_on(self.cartridge, model, self.power_on);
+        memory.ppu_mut().set_palette(self.palette);
+        *memory.apu_mut() = Apu::new(model, self.sample_rate);
+        memory.joypad_ref().set_pressed(self.held_buttons);
+
+        let mut cpu = Cpu::default();
+        match self.boot {
+            Boot::BootRom(boot_rom) => memory.map_boot_rom(boot_rom),
+            Boot::Default if model == Model::Dmg => {
+                memory.map_boot_rom(boot_rom::DMG_BOOT_ROM.to_vec())
+            }
+            Boot::Default | Boot::Skip => {
+                memory.skip_boot();
+                *cpu.registers_mut() = post_boot_registers;
+            }
+        }
+
+        GameBoy { cpu, memory }
+    }
+}
+
+/// The whole machine: the CPU driving the memory map and every peripheral behind it.
+pub struct GameBoy {
+    cpu: Cpu,
+    memory: VirtualMemory,
+}
+
+impl GameBoy {
+    /// T-cycles in a frame, the time between two VBlanks with the LCD on, twice as many in
+    /// double speed.
+    pub const CYCLES_PER_FRAME: u32 = 70224;
+
+    /// Starts with the default boot for the model the cartridge header asks for.
+    pub fn new(cartridge: Cartridge) -> Self {
+        GameBoy::builder(cartridge).build_validated()
+    }
+
+    pub fn builder(cartridge: Cartridge) -> GameBoyBuilder {
+        GameBoyBuilder {
+            cartridge,
+            model: None,
+            boot: Boot::Default,
+            palette: DmgPalette::default(),
+            sample_rate: Apu::DEFAULT_SAMPLE_RATE,
+            power_on: PowerOnSettings::default(),
+            held_buttons: 0x00,
+            movie_boot_rom: None,
+        }
+    }
+
+    pub fn cpu(&self) -> &Cpu {
+        &self.cpu
+    }
+
+    pub fn cpu_mut(&mut self) -> &mut Cpu {
+        &mut self.cpu
+    }
+
+    pub fn memory(&self) -> &VirtualMemory {
+        &self.memory
+    }
+
+    pub fn memory_mut(&mut self) -> &mut VirtualMemory {
+        &mut self.memory
+    }
+
+    pub fn joypad(&self) -> &JoyPad {
+        self.memory.joypad_ref()
+    }
+
+    /// Runs one instruction, or one M-cycle while halted, and returns the T-cycles it took at
+    /// the current CPU speed.
+    pub fn step_instruction(&mut self) -> u32 {
+        self.cpu.step(&mut self.memory) * 4
+    }
+
+    /// Runs whole instructions until at least `cycles` T-cycles have passed.
+    pub fn run_cycles(&mut self, cycles: u32) -> StopReason {
+        let mut elapsed = 0;
+
+        while elapsed < cycles {
+            elapsed += self.step_instruction();
+
+            if let Some(hit) = self.memory.take_watch_hit() {
+                return StopReason::Watchpoint(hit);
+            }
+        }
+
+        StopReason::CyclesDone
+    }
+
+    /// Runs until the next VBlank. With the LCD off, returns after a frame worth of cycles.
+    pub fn run_frame(&mut self) -> StopReason {
+        let mut elapsed = 0;
+
+        while elapsed < GameBoy::CYCLES_PER_FRAME << self.memory.double_speed() as u32 {
+            elapsed += self.step_instruction();
+
+            if let Some(hit) = self.memory.take_watch_hit() {
+                return StopReason::Watchpoint(hit);
+            }
+            if self.memory.take_frame_ready() {
+                return StopReason::FrameDone;
+            }
+        }
+
+        StopReason::CyclesDone
+    }
+
+    /// CRC32 of the whole machine state, which movies check to catch desyncs.
+    pub fn state_hash(&self) -> u32 {
+        let mut state = Vec::new();
+        self.cpu.hash_state(&mut state);
+        self.memory.hash_state(&mut state);
+
+        crate::hash::crc32(&state)
+    }
+
+    /// Runs a frame with `buttons` held, a mask of `JoyPadButton`s, and appends it to `movie`.
+    /// The frame counts as a lag frame when the game doesn't read the joypad.
+    pub fn record_frame(&mut self, movie: &mut Movie, buttons: u8) -> Option<WatchHit> {
+        let hit = self.run_movie_frame(buttons);
+        let lag = self.memory.was_lag_frame();
+        movie.record_frame(buttons, lag, || self.state_hash());
+
+        hit
+    }
+
+    /// Runs `frame` of `movie` with the buttons it recorded, nothing pressed past its end, and
+    /// compares the state hash when the frame has a checkpoint.
+    pub fn play_frame(&mut self, movie: &Movie, frame: u64) -> Result<Option<WatchHit>, Desync> {
+        let hit = self.run_movie_frame(movie.buttons(frame).unwrap_or(0x00));
+        movie.verify(frame, || self.state_hash())?;
+
+        Ok(hit)
+    }
+
+    /// Movie frames always run to the next VBlank, so watchpoints don't stop them early. The
+    /// first watchpoint hit is returned instead. With the LCD off, the frame worth of cycles
+    /// ends the frame for the lag frame count.
+    fn run_movie_frame(&mut self, buttons: u8) -> Option<WatchHit> {
+        self.joypad().set_pressed(buttons);
+
+        let mut first_hit = None;
+        loop {
+            match self.run_frame() {
+                StopReason::Watchpoint(hit) => {
+                    first_hit.get_or_insert(hit);
+                }
+                StopReason::FrameDone => break,
+                StopReason::CyclesDone => {
+                    self.memory.end_frame();
+                    break;
+                }
+            }
+        }
+
+        first_hit
+    }
+
+    /// `SCREEN_WIDTH` x `SCREEN_HEIGHT` RGB pixels of the last frame.
+    pub fn framebuffer(&self) -> &[u32] {
+        self.memory.ppu().framebuffer()
+    }
+
+    /// Interleaved stereo samples produced since the last call.
+    pub fn drain_audio(&mut self) -> Vec<i16> {
+        self.memory.apu_mut().drain_samples()
+    }
+
+    /// Bytes sent through the link port since the last call.
+    pub fn drain_serial(&mut self) -> Vec<u8> {
+        self.memory.serial_mut().take_output()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hash::RomHashes;
+    use crate::joypad::JoyPadButton;
+    use crate::power_on::PowerOnPattern;
+    use crate::virtual_memory::{MemoryDomain, MemoryMappedPeripheral};
+    use crate::watchpoint::{Access, Watchpoint};
+    use alloc::vec;
+
+    /// 32 KiB ROM that keeps reading the action buttons and storing them to 0xC000.
+    fn rom() -> Vec<u8> {
+        let mut rom = vec![0x00; 0x8000];
+        rom[0x0100..0x010b].copy_from_slice(&[
+            0x3e, 0x10, // LD A, 0x10
+            0xe0, 0x00, // LDH [0x00], A
+            0xf0, 0x00, // LDH A, [0x00]
+            0xea, 0x00, 0xc0, // LD [0xC000], A
+            0x18, 0xf9, // JR -7
+        ]);
+        set_header_checksum(&mut rom);
+        rom
+    }
+
+    fn set_header_checksum(rom: &mut [u8]) {
+        rom[0x014d] = rom[0x0134..0x014d]
+            .iter()
+            .fold(0u8, |sum, &byte| sum.wrapping_sub(byte).wrapping_sub(1));
+    }
+
+    fn gameboy(power_on: PowerOnSettings) -> GameBoy {
+        GameBoy::builder(Cartridge::load(&rom()))
+            .model(Model::Dmg)
+            .power_on(power_on)
+            .skip_boot()
+            .build()
+            .unwrap()
+    }
+
+    fn record() -> Movie {
+        let power_on = PowerOnSettings::uniform(PowerOnPattern::Random, 42);
+        let mut movie = Movie::new(Model::Dmg, power_on, &RomHashes::compute(&rom()));
+        movie.boot = MovieBoot::Skip;
+        movie.checkpoint_interval = 2;
+
+        let mut gameboy = gameboy(power_on);
+        for frame in 0..6 {
+            assert_eq!(gameboy.record_frame(&mut movie, frame << 4), None);
+        }
+        assert_eq!(gameboy.memory().peek_bus(0xc000), 0xd0 | !0x05 & 0x0f);
+
+        movie
+    }
+
+    #[test]
+    fn playback_matches_recording() {
+        let movie = record();
+        assert_eq!(movie.checkpoints().len(), 3);
+        assert_eq!(movie.lag_frames(), 0);
+
+        let mut gameboy = GameBoy::builder(Cartridge::load(&rom()))
+            .movie(&movie)
+            .build()
+            .unwrap();
+        for frame in 0..movie.len() {
+            assert_eq!(gameboy.play_frame(&movie, frame), Ok(None));
+        }
+    }
+
+    #[test]
+    fn movie_sets_up_the_boot_and_held_buttons() {
+        let mut movie = Movie::new(
+            Model::Dmg,
+            PowerOnSettings::default(),
+            &RomHashes::compute(&rom()),
+        );
+        movie.boot = MovieBoot::from(&Boot::BootRom(boot_rom::DMG_BOOT_ROM.to_vec()));
+        movie.held_buttons = JoyPadButton::Start.mask();
+        let builder = || GameBoy::builder(Cartridge::load(&rom())).model(Model::Cgb);
+
+        assert_eq!(
+            builder().movie(&movie).build().err(),
+            Some(BootRomError::MovieMismatch)
+        );
+        assert_eq!(
+            builder()
+                .boot_rom(vec![0x00; 0x100])
+                .movie(&movie)
+                .build()
+                .err(),
+            Some(BootRomError::MovieMismatch)
+        );
+
+        let mut gameboy = builder()
+            .boot_rom(boot_rom::DMG_BOOT_ROM.to_vec())
+            .movie(&movie)
+            .build()
+            .unwrap();
+        assert_eq!(gameboy.memory().model(), Model::Dmg);
+        assert_eq!(gameboy.cpu().registers().pc, 0x0000);
+        assert_eq!(gameboy.memory().peek_bus(0x0000), boot_rom::DMG_BOOT_ROM[0]);
+
+        gameboy.memory_mut().write(0xff00, 0x10);
+        assert_eq!(gameboy.memory_mut().read(0xff00) & 0x0f, 0x07);
+
+        movie.boot = MovieBoot::Skip;
+        let gameboy = builder()
+            .boot_rom(boot_rom::DMG_BOOT_ROM.to_vec())
+            .movie(&movie)
+            .build()
+            .unwrap();
+        assert_eq!(gameboy.cpu().registers().pc, 0x0100);
+    }
+
+    #[test]
+    fn playback_with_other_power_on_desyncs() {
+        let movie = record();
+        let mut gameboy = gameboy(PowerOnSettings::uniform(PowerOnPattern::Random, 43));
+
+        assert_eq!(gameboy.play_frame(&movie, 0), Ok(None));
+        assert!(matches!(
+            gameboy.play_frame(&movie, 1),
+            Err(Desync { frame: 1, .. })
+        ));
+    }
+
+    #[test]
+    fn mbc5_maps_bank_0_at_0x4000() {
+        let mut rom = vec![0x00; 0x10000];
+        for (bank, data) in rom.chunks_mut(0x4000).enumerate() {
+            data[0x0000] = bank as u8;
+        }
+        rom[0x0147] = 0x19;
+        rom[0x0148] = 0x01;
+        set_header_checksum(&mut rom);
+
+        let mut gameboy = GameBoy::builder(Cartridge::load(&rom))
+            .skip_boot()
+            .build()
+            .unwrap();
+        let memory = gameboy.memory_mut();
+        assert_eq!(memory.read(0x4000), 1);
+
+        memory.write(0x2000, 0x03);
+        assert_eq!(memory.read(0x4000), 3);
+
+        memory.write(0x2000, 0x00);
+        assert_eq!(memory.read(0x4000), 0);
+        assert_eq!(memory.peek_bus(0x4000), 0);
+        assert_eq!(memory.domain_at(0x4000), Some((MemoryDomain::Rom, 0x0000)));
+    }
+
+    #[test]
+    fn build_rejects_a_boot_rom_of_the_wrong_size() {
+        let result = GameBoy::builder(Cartridge::load(&rom()))
+            .model(Model::Cgb)
+            .boot_rom(vec![0x00; 0x100])
+            .build();
+
+        assert_eq!(
+            result.err(),
+            Some(BootRomError::InvalidSize {
+                model: Model::Cgb,
+                expected: 0x900,
+                actual: 0x100,
+            })
+        );
+    }
+
+    #[test]
+    fn boot_rom_unmapping_latches() {
+        let mut gameboy = GameBoy::builder(Cartridge::load(&rom()))
+            .model(Model::Dmg)
+            .build()
+            .unwrap();
+        let memory = gameboy.memory_mut();
+        assert_eq!(memory.read(0x0000), boot_rom::DMG_BOOT_ROM[0]);
+
+        memory.write(0xff50, 0xfe);
+        assert_eq!(memory.read(0x0000), boot_rom::DMG_BOOT_ROM[0]);
+
+        memory.write(0xff50, 0x01);
+        assert_eq!(memory.read(0x0000), 0x00);
+
+        memory.write(0xff50, 0x00);
+        assert_eq!(memory.read(0x0000), 0x00);
+    }
+
+    #[test]
+    fn pausing_watchpoint_stops_after_the_instruction() {
+        let mut gameboy = gameboy(PowerOnSettings::default());
+        let id = gameboy
+            .memory_mut()
+            .add_watchpoint(Watchpoint::new(Access::Write, 0xc000..=0xc000));
+
+        let StopReason::Watchpoint(hit) = gameboy.run_frame() else {
+            panic!("the watchpoint didn't pause");
+        };
+        assert_eq!((hit.id, hit.address, hit.pc), (id, 0xc000, 0x0106));
+        assert_eq!(gameboy.cpu().registers().pc, 0x0109);
+        assert_eq!(gameboy.memory().take_watch_hit(), None);
+
+        // The next run goes around the loop once more before hitting it again.
+        assert!(matches!(
+            gameboy.run_cycles(1000),
+            StopReason::Watchpoint(_)
+        ));
+        assert_eq!(gameboy.cpu().registers().pc, 0x0109);
+    }
+
+    #[test]
+    fn key0_is_locked_once_the_boot_rom_is_unmapped() {
+        let mut gameboy = GameBoy::builder(Cartridge::load(&rom()))
+            .model(Model::Cgb)
+            .boot_rom(vec![0x00; 0x900])
+            .build()
+            .unwrap();
+        let memory = gameboy.memory_mut();
+        memory.write(0xff50, 0x01);
+        memory.write(0xff50, 0x00);
+        memory.write(0xff4c, 0x04);
+
+        assert!(memory.ppu().cgb_mode());
+    }
+}
@@ -0,0 +1,186 @@
+use crate::cartridge::{CartridgeFeatures, Mapper};
+use alloc::vec::Vec;
+
+/// Footer appended to the end of a ROM that describes the cartridge hardware explicitly, so that
+/// homebrew and unusual mappers don't depend on the header bytes at 0x147-0x149.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GbxFooter {
+    pub mapper: Mapper,
+    pub battery: bool,
+    pub rumble: bool,
+    pub timer: bool,
+    pub rom_size: u32,
+    pub ram_size: u32,
+    pub mapper_variables: [u8; 32],
+}
+
+impl GbxFooter {
+    pub const SIZE: usize = 0x40;
+    const MAGIC: &'static [u8] = b"GBX!";
+    const MAJOR_VERSION: u32 = 1;
+    const MINOR_VERSION: u32 = 0;
+
+    pub fn new(mapper: Mapper, rom_size: u32, ram_size: u32) -> Self {
+        Self {
+            mapper,
+            battery: false,
+            rumble: false,
+            timer: false,
+            rom_size,
+            ram_size,
+            mapper_variables: [0x00; 32],
+        }
+    }
+
+    fn read_u32(footer: &[u8], offset: usize) -> u32 {
+        u32::from_be_bytes(footer[offset..offset + 4].try_into().unwrap())
+    }
+
+    /// Looks for a footer at the end of `content`. Footers from an unknown major version are
+    /// ignored, since their layout can't be trusted.
+    pub fn parse(content: &[u8]) -> Option<Self> {
+        let footer = content.get(content.len().checked_sub(GbxFooter::SIZE)?..)?;
+
+        if &footer[0x3C..] != GbxFooter::MAGIC
+            || GbxFooter::read_u32(footer, 0x30) as usize != GbxFooter::SIZE
+            || GbxFooter::read_u32(footer, 0x34) != GbxFooter::MAJOR_VERSION
+        {
+            return None;
+        }
+
+        Some(Self {
+            mapper: Mapper::from_gbx_id(footer[0x00..0x04].try_into().unwrap()),
+            battery: footer[0x04] != 0,
+            rumble: footer[0x05] != 0,
+            timer: footer[0x06] != 0,
+            rom_size: GbxFooter::read_u32(footer, 0x08),
+            ram_size: GbxFooter::read_u32(footer, 0x0C),
+            mapper_variables: footer[0x10..0x30].try_into().unwrap(),
+        })
+    }
+
+    pub fn to_bytes(&self) -> [u8; GbxFooter::SIZE] {
+        let mut footer = [0x00; GbxFooter::SIZE];
+
+        footer[0x00..0x04].copy_from_slice(&self.mapper.gbx_id());
+        footer[0x04] = self.battery as u8;
+        footer[0x05] = self.rumble as u8;
+        footer[0x06] = self.timer as u8;
+        footer[0x08..0x0C].copy_from_slice(&self.rom_size.to_be_bytes());
+        footer[0x0C..0x10].copy_from_slice(&self.ram_size.to_be_bytes());
+        footer[0x10..0x30].copy_from_slice(&self.mapper_variables);
+        footer[0x30..0x34].copy_from_slice(&(GbxFooter::SIZE as u32).to_be_bytes());
+        footer[0x34..0x38].copy_from_slice(&GbxFooter::MAJOR_VERSION.to_be_bytes());
+        footer[0x38..0x3C].copy_from_slice(&GbxFooter::MINOR_VERSION.to_be_bytes());
+        footer[0x3C..].copy_from_slice(GbxFooter::MAGIC);
+
+        footer
+    }
+
+    /// Appends the footer to `rom`, replacing the footer it already has, if any.
+    pub fn write_to(&self, rom: &mut Vec<u8>) {
+        if GbxFooter::parse(rom).is_some() {
+            rom.truncate(rom.len() - GbxFooter::SIZE);
+        }
+
+        rom.extend_from_slice(&self.to_bytes());
+    }
+
+    pub fn features(&self) -> CartridgeFeatures {
+        CartridgeFeatures {
+            ram: self.ram_size > 0,
+            battery: self.battery,
+            timer: self.timer,
+            rumble: self.rumble,
+            sensor: self.mapper == Mapper::Mbc7,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::{Cartridge, CartridgeError};
+    use crate::hash::RomHashes;
+    use alloc::vec;
+
+    /// 32 KiB ROM with an unknown cartridge type and a wrong header checksum.
+    fn rom() -> Vec<u8> {
+        let mut rom = vec![0x00; 0x8000];
+        rom[0x0147] = 0xaa;
+        rom[0x0148] = 0xaa;
+        rom[0x0149] = 0xaa;
+        rom[0x014d] = 0x12;
+        rom
+    }
+
+    fn footer() -> GbxFooter {
+        GbxFooter {
+            battery: true,
+            timer: true,
+            mapper_variables: [0x5a; 32],
+            ..GbxFooter::new(Mapper::Mbc5, 0x10000, 0x8000)
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let bytes = footer().to_bytes();
+
+        assert_eq!(&bytes[0x00..0x04], b"MBC5");
+        assert_eq!(&bytes[0x3c..], b"GBX!");
+        assert_eq!(GbxFooter::parse(&bytes), Some(footer()));
+    }
+
+    #[test]
+    fn other_mapper_round_trip() {
+        let footer = GbxFooter::new(Mapper::Other(*b"LICH"), 0x8000, 0);
+
+        assert_eq!(GbxFooter::parse(&footer.to_bytes()), Some(footer));
+    }
+
+    #[test]
+    fn unknown_major_version() {
+        let mut bytes = footer().to_bytes();
+        bytes[0x37] = 2;
+
+        assert_eq!(GbxFooter::parse(&bytes), None);
+        assert_eq!(GbxFooter::parse(&bytes[1..]), None);
+    }
+
+    #[test]
+    fn write_replaces_footer() {
+        let mut rom = rom();
+        footer().write_to(&mut rom);
+        GbxFooter::new(Mapper::Mbc1, 0x8000, 0).write_to(&mut rom);
+
+        assert_eq!(rom.len(), 0x8000 + GbxFooter::SIZE);
+        assert_eq!(GbxFooter::parse(&rom).unwrap().mapper, Mapper::Mbc1);
+    }
+
+    #[test]
+    fn footer_overrides_header() {
+        assert_eq!(
+            Cartridge::try_load(&rom()).err(),
+            Some(CartridgeError::InvalidCartridgeType(0xaa))
+        );
+
+        let mut valid_type = rom();
+        valid_type[0x0147..0x014a].fill(0x00);
+        assert!(matches!(
+            Cartridge::try_load(&valid_type),
+            Err(CartridgeError::HeaderChecksum { .. })
+        ));
+
+        let mut content = rom();
+        footer().write_to(&mut content);
+        let cartridge = Cartridge::try_load(&content).unwrap();
+
+        assert_eq!(cartridge.header().cartridge_type(), None);
+        assert_eq!(cartridge.mapper(), Mapper::Mbc5);
+        assert_eq!(cartridge.rom_banks(), 4);
+        assert_eq!(cartridge.ram_banks(), 4);
+        assert!(cartridge.features().battery && cartridge.features().timer);
+        assert_eq!(*cartridge.hashes(), RomHashes::compute(&rom()));
+    }
+}
@@ -0,0 +1,803 @@
+use crate::model::Model;
+use crate::virtual_memory::MemoryMappedPeripheral;
+use alloc::vec;
+use alloc::vec::Vec;
+
+pub const SCREEN_WIDTH: usize = 160;
+pub const SCREEN_HEIGHT: usize = 144;
+
+/// RGB colors (0x00RRGGBB) of the four DMG shades, from lightest to darkest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DmgPalette(pub [u32; 4]);
+
+impl Default for DmgPalette {
+    fn default() -> Self {
+        DmgPalette([0xffffff, 0xaaaaaa, 0x555555, 0x000000])
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PpuMode {
+    HBlank = 0,
+    VBlank = 1,
+    OamScan = 2,
+    Drawing = 3,
+}
+
+/// Interrupts requested during a PPU tick.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PpuEvents {
+    /// Also marks the end of a frame.
+    pub vblank: bool,
+    pub stat: bool,
+    /// A visible line was drawn and mode 0 started, when H-Blank DMA copies a block.
+    pub hblank: bool,
+}
+
+/// Scanline renderer with the timing of the PPU modes: each line is drawn at once when the line
+/// enters mode 3, which has a fixed length.
+pub struct Ppu {
+    lcdc: u8,
+    /// Interrupt sources of STAT, bits 3-6.
+    stat: u8,
+    scy: u8,
+    scx: u8,
+    ly: u8,
+    lyc: u8,
+    bgp: u8,
+    obp0: u8,
+    obp1: u8,
+    wy: u8,
+    wx: u8,
+    dot: u16,
+    mode: PpuMode,
+    /// Line of the window to draw next, which only advances on lines showing it.
+    window_line: u8,
+    /// WY matched LY at some point during the frame.
+    window_triggered: bool,
+    stat_line: bool,
+    palette: DmgPalette,
+    /// Running on a CGB, which colors DMG software with its palette RAM.
+    cgb_hardware: bool,
+    /// CGB features enabled, as opposed to the DMG compatibility mode picked through KEY0.
+    cgb_mode: bool,
+    /// BCPS and OCPS: palette RAM address, with auto-increment in bit 7.
+    bcps: u8,
+    ocps: u8,
+    /// 8 palettes of 4 little-endian 15-bit colors each.
+    bg_palettes: [u8; 64],
+    obj_palettes: [u8; 64],
+    opri: u8,
+    framebuffer: Vec<u32>,
+}
+
+impl Default for Ppu {
+    fn default() -> Self {
+        Ppu::new(Model::default())
+    }
+}
+
+impl Ppu {
+    pub fn new(model: Model) -> Self {
+        Self {
+            lcdc: 0x00,
+            stat: 0x00,
+            scy: 0x00,
+            scx: 0x00,
+            ly: 0x00,
+            lyc: 0x00,
+            bgp: 0x00,
+            obp0: 0x00,
+            obp1: 0x00,
+            wy: 0x00,
+            wx: 0x00,
+            dot: 0,
+            mode: PpuMode::HBlank,
+            window_line: 0,
+            window_triggered: false,
+            stat_line: false,
+            palette: DmgPalette::default(),
+            cgb_hardware: model.is_cgb(),
+            cgb_mode: model.is_cgb(),
+            bcps: 0x00,
+            ocps: 0x00,
+            bg_palettes: [0xff; 64],
+            obj_palettes: [0xff; 64],
+            opri: 0x00,
+            framebuffer: vec![DmgPalette::default().0[0]; SCREEN_WIDTH * SCREEN_HEIGHT],
+        }
+    }
+
+    const DOTS_PER_LINE: u16 = 456;
+    const OAM_SCAN_DOTS: u16 = 80;
+    const DRAWING_DOTS: u16 = 172;
+    const LINES: u8 = 154;
+    const MAX_SPRITES_PER_LINE: usize = 10;
+
+    pub fn set_palette(&mut self, palette: DmgPalette) {
+        self.palette = palette;
+    }
+
+    pub fn palette(&self) -> DmgPalette {
+        self.palette
+    }
+
+    pub fn cgb_mode(&self) -> bool {
+        self.cgb_mode
+    }
+
+    /// Switches between CGB mode and DMG compatibility mode, which only a CGB can do.
+    pub fn set_cgb_mode(&mut self, cgb_mode: bool) {
+        self.cgb_mode = cgb_mode && self.cgb_hardware;
+    }
+
+    /// Sets the 15-bit colors of background palette `palette`, 0-7.
+    pub fn set_bg_palette(&mut self, palette: usize, colors: [u16; 4]) {
+        set_palette_colors(&mut self.bg_palettes, palette, colors);
+    }
+
+    /// Sets the 15-bit colors of object palette `palette`, 0-7.
+    pub fn set_obj_palette(&mut self, palette: usize, colors: [u16; 4]) {
+        set_palette_colors(&mut self.obj_palettes, palette, colors);
+    }
+
+    /// The last frame drawn, `SCREEN_WIDTH` x `SCREEN_HEIGHT` RGB pixels.
+    pub fn framebuffer(&self) -> &[u32] {
+        &self.framebuffer
+    }
+
+    pub fn lcd_enabled(&self) -> bool {
+        self.lcdc & 0x80 != 0
+    }
+
+    pub fn mode(&self) -> PpuMode {
+        self.mode
+    }
+
+    /// Appends the registers and the timing state. The framebuffer and the DMG colors only
+    /// follow from them.
+    pub fn hash_state(&self, state: &mut Vec<u8>) {
+        state.extend_from_slice(&[
+            self.lcdc, self.stat, self.scy, self.scx, self.ly, self.lyc, self.bgp, self.obp0,
+            self.obp1, self.wy, self.wx,
+        ]);
+        state.extend_from_slice(&self.dot.to_le_bytes());
+        state.extend_from_slice(&[
+            self.mode as u8,
+            self.window_line,
+            self.window_triggered as u8,
+            self.stat_line as u8,
+            self.cgb_mode as u8,
+            self.bcps,
+            self.ocps,
+            self.opri,
+        ]);
+        state.extend_from_slice(&self.bg_palettes);
+        state.extend_from_slice(&self.obj_palettes);
+    }
+
+    pub fn ly(&self) -> u8 {
+        self.ly
+    }
+
+    /// Row of OAM read during the current M-cycle of OAM scan.
+    pub fn oam_scan_row(&self) -> Option<usize> {
+        (self.lcd_enabled() && self.mode == PpuMode::OamScan).then_some(self.dot as usize / 4)
+    }
+
+    fn stat_line(&self) -> bool {
+        let coincidence = self.ly == self.lyc && self.stat & 0x40 != 0;
+        let mode = match self.mode {
+            PpuMode::HBlank => self.stat & 0x08 != 0,
+            PpuMode::VBlank => self.stat & 0x10 != 0,
+            PpuMode::OamScan => self.stat & 0x20 != 0,
+            PpuMode::Drawing => false,
+        };
+
+        coincidence || mode
+    }
+
+    /// Advances one M-cycle, 4 dots. `vram` holds both banks, the second one is only read in
+    /// CGB mode.
+    pub fn tick(&mut self, vram: [&[u8]; 2], oam: &[u8]) -> PpuEvents {
+        let mut events = PpuEvents::default();
+
+        if !self.lcd_enabled() {
+            return events;
+        }
+
+        self.dot += 4;
+        if self.dot >= Ppu::DOTS_PER_LINE {
+            self.dot -= Ppu::DOTS_PER_LINE;
+            self.ly += 1;
+
+            if self.ly == Ppu::LINES {
+                self.ly = 0;
+                self.window_line = 0;
+                self.window_triggered = false;
+            }
+        }
+
+        let mode = if self.ly >= SCREEN_HEIGHT as u8 {
+            PpuMode::VBlank
+        } else if self.dot < Ppu::OAM_SCAN_DOTS {
+            PpuMode::OamScan
+        } else if self.dot < Ppu::OAM_SCAN_DOTS + Ppu::DRAWING_DOTS {
+            PpuMode::Drawing
+        } else {
+            PpuMode::HBlank
+        };
+
+        if mode != self.mode {
+            self.mode = mode;
+
+            match mode {
+                PpuMode::OamScan if self.ly == self.wy => self.window_triggered = true,
+                PpuMode::Drawing => self.draw_line(vram, oam),
+                PpuMode::VBlank => events.vblank = true,
+                PpuMode::HBlank => events.hblank = true,
+                _ => {}
+            }
+        }
+
+        let stat_line = self.stat_line();
+        events.stat = stat_line && !self.stat_line;
+        self.stat_line = stat_line;
+
+        events
+    }
+
+    /// The two bit planes of `row` in `tile`, from VRAM `bank`.
+    fn tile_row(
+        &self,
+        vram: [&[u8]; 2],
+        bank: usize,
+        tile: u8,
+        row: u8,
+        signed_addressing: bool,
+    ) -> (u8, u8) {
+        let base = if signed_addressing {
+            (0x1000 + (tile as i8 as i32) * 16) as usize
+        } else {
+            tile as usize * 16
+        };
+        let address = base + row as usize * 2;
+
+        (vram[bank][address], vram[bank][address + 1])
+    }
+
+    /// Color `index` of a palette in CGB palette RAM, as RGB.
+    fn cgb_color(palettes: &[u8; 64], palette: u8, index: u8) -> u32 {
+        let offset = palette as usize * 8 + index as usize * 2;
+        rgb555(u16::from_le_bytes([palettes[offset], palettes[offset + 1]]))
+    }
+
+    /// Background color for `index`. Outside CGB mode it goes through BGP first, and a CGB
+    /// running DMG software then looks the shade up in background palette 0.
+    fn bg_color(&self, palette: u8, index: u8) -> u32 {
+        if self.cgb_mode {
+            return Ppu::cgb_color(&self.bg_palettes, palette, index);
+        }
+
+        let shade = (self.bgp >> (index * 2)) & 0x03;
+        if self.cgb_hardware {
+            Ppu::cgb_color(&self.bg_palettes, 0, shade)
+        } else {
+            self.palette.0[shade as usize]
+        }
+    }
+
+    fn obj_color(&self, attributes: u8, index: u8) -> u32 {
+        if self.cgb_mode {
+            return Ppu::cgb_color(&self.obj_palettes, attributes & 0x07, index);
+        }
+
+        let (obp, palette) = if attributes & 0x10 != 0 {
+            (self.obp1, 1)
+        } else {
+            (self.obp0, 0)
+        };
+        let shade = (obp >> (index * 2)) & 0x03;
+        if self.cgb_hardware {
+            Ppu::cgb_color(&self.obj_palettes, palette, shade)
+        } else {
+            self.palette.0[shade as usize]
+        }
+    }
+
+    fn draw_line(&mut self, vram: [&[u8]; 2], oam: &[u8]) {
+        let ly = self.ly;
+        let mut bg_indexes = [0u8; SCREEN_WIDTH];
+        // BG-to-OAM priority attribute of the tile under each pixel, CGB mode only.
+        let mut bg_priorities = [false; SCREEN_WIDTH];
+        let line = ly as usize * SCREEN_WIDTH;
+
+        // In CGB mode LCDC bit 0 doesn't hide the background, it only takes its priority away.
+        if self.cgb_mode || self.lcdc & 0x01 != 0 {
+            let signed_addressing = self.lcdc & 0x10 == 0;
+            let window_visible = self.lcdc & 0x20 != 0 && self.window_triggered && self.wx <= 166;
+
+            for x in 0..SCREEN_WIDTH {
+                let in_window = window_visible && x as u16 + 7 >= self.wx as u16;
+                let (map, map_x, map_y) = if in_window {
+                    let map = if self.lcdc & 0x40 != 0 {
+                        0x1c00
+                    } else {
+                        0x1800
+                    };
+                    (map, (x as u16 + 7 - self.wx as u16) as u8, self.window_line)
+                } else {
+                    let map = if self.lcdc & 0x08 != 0 {
+                        0x1c00
+                    } else {
+                        0x1800
+                    };
+                    (
+                        map,
+                        self.scx.wrapping_add(x as u8),
+                        self.scy.wrapping_add(ly),
+                    )
+                };
+
+                let map_address = map + (map_y as usize / 8) * 32 + map_x as usize / 8;
+                let tile = vram[0][map_address];
+                let attributes = if self.cgb_mode {
+                    vram[1][map_address]
+                } else {
+                    0x00
+                };
+
+                let bank = (attributes >> 3) as usize & 0x01;
+                let row = if attributes & 0x40 != 0 {
+                    7 - map_y % 8
+                } else {
+                    map_y % 8
+                };
+                let (low, high) = self.tile_row(vram, bank, tile, row, signed_addressing);
+                let bit = if attributes & 0x20 != 0 {
+                    map_x % 8
+                } else {
+                    7 - map_x % 8
+                };
+                let index = ((high >> bit) & 0x01) << 1 | ((low >> bit) & 0x01);
+
+                bg_indexes[x] = index;
+                bg_priorities[x] = attributes & 0x80 != 0;
+                self.framebuffer[line + x] = self.bg_color(attributes & 0x07, index);
+            }
+
+            if window_visible {
+                self.window_line += 1;
+            }
+        } else {
+            let color = if self.cgb_hardware {
+                Ppu::cgb_color(&self.bg_palettes, 0, 0)
+            } else {
+                self.palette.0[0]
+            };
+            self.framebuffer[line..line + SCREEN_WIDTH].fill(color);
+        }
+
+        if self.lcdc & 0x02 != 0 {
+            self.draw_sprites(vram, oam, &bg_indexes, &bg_priorities);
+        }
+    }
+
+    fn draw_sprites(
+        &mut self,
+        vram: [&[u8]; 2],
+        oam: &[u8],
+        bg_indexes: &[u8; SCREEN_WIDTH],
+        bg_priorities: &[bool; SCREEN_WIDTH],
+    ) {
+        let ly = self.ly as u16 + 16;
+        let height = if self.lcdc & 0x04 != 0 { 16 } else { 8 };
+
+        let mut sprites: Vec<&[u8]> = oam
+            .chunks_exact(4)
+            .filter(|sprite| (sprite[0] as u16..sprite[0] as u16 + height).contains(&ly))
+            .take(Ppu::MAX_SPRITES_PER_LINE)
+            .collect();
+
+        // Lower X wins, then lower OAM index. CGB mode only looks at the OAM index, unless OPRI
+        // asks for the DMG order.
+        if !self.cgb_mode || self.opri & 0x01 != 0 {
+            sprites.sort_by_key(|sprite| sprite[1]);
+        }
+
+        // The first opaque object pixel wins over the other objects, even when the background
+        // then wins over it: a hidden object still hides the objects behind it.
+        let mut pixels: [Option<(u8, u8)>; SCREEN_WIDTH] = [None; SCREEN_WIDTH];
+        for sprite in sprites {
+            let (y, x, mut tile, attributes) = (sprite[0], sprite[1], sprite[2], sprite[3]);
+            let mut row = ly - y as u16;
+
+            if attributes & 0x40 != 0 {
+                row = height - 1 - row;
+            }
+            if height == 16 {
+                tile &= 0xfe;
+            }
+
+            let bank = if self.cgb_mode {
+                (attributes >> 3) as usize & 0x01
+            } else {
+                0
+            };
+            let (low, high) = self.tile_row(vram, bank, tile, row as u8, false);
+
+            for column in 0..8u8 {
+                let screen_x = x as i16 - 8 + column as i16;
+                if !(0..SCREEN_WIDTH as i16).contains(&screen_x) {
+                    continue;
+                }
+
+                let bit = if attributes & 0x20 != 0 {
+                    column
+                } else {
+                    7 - column
+                };
+                let index = ((high >> bit) & 0x01) << 1 | ((low >> bit) & 0x01);
+                let pixel = &mut pixels[screen_x as usize];
+                if index != 0 && pixel.is_none() {
+                    *pixel = Some((attributes, index));
+                }
+            }
+        }
+
+        let master_priority = !self.cgb_mode || self.lcdc & 0x01 != 0;
+        let line = self.ly as usize * SCREEN_WIDTH;
+        for (screen_x, pixel) in pixels.iter().enumerate() {
+            let Some((attributes, index)) = *pixel else {
+                continue;
+            };
+
+            let bg_wins = master_priority
+                && bg_indexes[screen_x] != 0
+                && (attributes & 0x80 != 0 || bg_priorities[screen_x]);
+            if !bg_wins {
+                self.framebuffer[line + screen_x] = self.obj_color(attributes, index);
+            }
+        }
+    }
+}
+
+fn set_palette_colors(palettes: &mut [u8; 64], palette: usize, colors: [u16; 4]) {
+    for (index, color) in colors.iter().enumerate() {
+        let offset = (palette % 8) * 8 + index * 2;
+        palettes[offset..offset + 2].copy_from_slice(&color.to_le_bytes());
+    }
+}
+
+/// 15-bit CGB color to RGB, scaling each 5-bit channel to 8 bits.
+fn rgb555(color: u16) -> u32 {
+    let channel = |shift: u16| {
+        let value = ((color >> shift) & 0x1f) as u32;
+        value << 3 | value >> 2
+    };
+
+    channel(0) << 16 | channel(5) << 8 | channel(10)
+}
+
+/// Addresses are the offsets of the registers from 0xFF00.
+impl MemoryMappedPeripheral for Ppu {
+    fn write(&mut self, address: u16, data: u8) {
+        match address {
+            0x40 => {
+                let was_enabled = self.lcd_enabled();
+                self.lcdc = data;
+
+                if was_enabled && !self.lcd_enabled() {
+                    self.ly = 0;
+                    self.dot = 0;
+                    self.mode = PpuMode::HBlank;
+                    self.stat_line = false;
+                } else if !was_enabled && self.lcd_enabled() {
+                    self.window_line = 0;
+                    self.window_triggered = false;
+                }
+            }
+            0x41 => self.stat = data & 0x78,
+            0x42 => self.scy = data,
+            0x43 => self.scx = data,
+            0x45 => self.lyc = data,
+            0x47 => self.bgp = data,
+            0x48 => self.obp0 = data,
+            0x49 => self.obp1 = data,
+            0x4a => self.wy = data,
+            0x4b => self.wx = data,
+            0x68 => self.bcps = data & 0xbf,
+            0x69 => write_palette_data(&mut self.bg_palettes, &mut self.bcps, data, self.mode),
+            0x6a => self.ocps = data & 0xbf,
+            0x6b => write_palette_data(&mut self.obj_palettes, &mut self.ocps, data, self.mode),
+            0x6c => self.opri = data & 0x01,
+            _ => {}
+        }
+    }
+
+    fn read(&self, address: u16) -> u8 {
+        match address {
+            0x40 => self.lcdc,
+            0x41 => {
+                let coincidence = ((self.ly == self.lyc) as u8) << 2;
+                self.stat | coincidence | self.mode as u8
+            }
+            0x42 => self.scy,
+            0x43 => self.scx,
+            0x44 => self.ly,
+            0x45 => self.lyc,
+            0x47 => self.bgp,
+            0x48 => self.obp0,
+            0x49 => self.obp1,
+            0x4a => self.wy,
+            0x4b => self.wx,
+            0x68 => self.bcps,
+            0x69 => read_palette_data(&self.bg_palettes, self.bcps, self.mode),
+            0x6a => self.ocps,
+            0x6b => read_palette_data(&self.obj_palettes, self.ocps, self.mode),
+            0x6c => self.opri,
+            _ => 0xff,
+        }
+    }
+}
+
+/// Palette RAM can't be accessed while the PPU reads it in mode 3, but the address is still
+/// incremented.
+fn write_palette_data(palettes: &mut [u8; 64], specification: &mut u8, data: u8, mode: PpuMode) {
+    if mode != PpuMode::Drawing {
+        palettes[(*specification & 0x3f) as usize] = data;
+    }
+
+    if *specification & 0x80 != 0 {
+        *specification = 0x80 | (specification.wrapping_add(1) & 0x3f);
+    }
+}
+
+fn read_palette_data(palettes: &[u8; 64], specification: u8, mode: PpuMode) -> u8 {
+    if mode == PpuMode::Drawing {
+        return 0xff;
+    }
+
+    palettes[(specification & 0x3f) as usize]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    const BG_COLORS: [[u16; 4]; 2] = [
+        [0x7fff, 0x001f, 0x03e0, 0x7c00],
+        [0x0000, 0x0010, 0x0200, 0x4000],
+    ];
+    const OBJ_COLORS: [[u16; 4]; 2] = [
+        [0x0000, 0x0001, 0x0002, 0x0003],
+        [0x0000, 0x0020, 0x0040, 0x0060],
+    ];
+
+    /// CGB PPU with the LCD, the background and the objects on, and two palettes of each kind.
+    fn cgb_ppu() -> Ppu {
+        let mut ppu = Ppu::new(Model::Cgb);
+        ppu.write(0x40, 0x93);
+        for (palette, colors) in BG_COLORS.iter().enumerate() {
+            ppu.set_bg_palette(palette, *colors);
+        }
+        for (palette, colors) in OBJ_COLORS.iter().enumerate() {
+            ppu.set_obj_palette(palette, *colors);
+        }
+        ppu
+    }
+
+    /// Both VRAM banks, tile 1 of bank 0 has color 1 on the left column of its first row, and
+    /// tile 1 of bank 1 color 2 on the right column of its last row. Tile 2 is all color 3 and
+    /// tile 3 all color 1, in both banks. The background map shows tile 1.
+    fn vram() -> [Vec<u8>; 2] {
+        let mut banks = [vec![0x00; 0x2000], vec![0x00; 0x2000]];
+        banks[0][0x0010] = 0x80;
+        banks[1][0x001f] = 0x01;
+        for bank in &mut banks {
+            bank[0x0020..0x0030].fill(0xff);
+            for row in 0..8 {
+                bank[0x0030 + row * 2] = 0xff;
+            }
+        }
+        banks[0][0x1800..0x1c00].fill(0x01);
+        banks
+    }
+
+    fn draw_line(ppu: &mut Ppu, vram: &[Vec<u8>; 2], oam: &[u8], ly: u8) -> Vec<u32> {
+        ppu.ly = ly;
+        ppu.draw_line([&vram[0], &vram[1]], oam);
+        let line = ly as usize * SCREEN_WIDTH;
+        ppu.framebuffer()[line..line + SCREEN_WIDTH].to_vec()
+    }
+
+    fn bg(palette: usize, index: usize) -> u32 {
+        rgb555(BG_COLORS[palette][index])
+    }
+
+    fn obj(palette: usize, index: usize) -> u32 {
+        rgb555(OBJ_COLORS[palette][index])
+    }
+
+    #[test]
+    fn rgb555_scales_channels() {
+        assert_eq!(rgb555(0x0000), 0x000000);
+        assert_eq!(rgb555(0x7fff), 0xffffff);
+        assert_eq!(rgb555(0x001f), 0xff0000);
+        assert_eq!(rgb555(0x03e0), 0x00ff00);
+        assert_eq!(rgb555(0x7c00), 0x0000ff);
+        assert_eq!(rgb555(0x0010 | 0x0001 << 5 | 0x000f << 10), 0x84087b);
+        // Bit 15 isn't a color.
+        assert_eq!(rgb555(0x8000), 0x000000);
+    }
+
+    #[test]
+    fn palette_specification_auto_increment() {
+        for (specification, data) in [(0x68, 0x69), (0x6a, 0x6b)] {
+            let mut ppu = Ppu::new(Model::Cgb);
+
+            // Bit 6 doesn't exist, and the address wraps around without clearing bit 7.
+            ppu.write(specification, 0xfe);
+            assert_eq!(ppu.read(specification), 0xbe);
+            for byte in [0x11, 0x22, 0x33] {
+                ppu.write(data, byte);
+            }
+            assert_eq!(ppu.read(specification), 0x81);
+            ppu.write(specification, 0x3e);
+            assert_eq!(ppu.read(data), 0x11);
+            ppu.write(specification, 0x3f);
+            assert_eq!(ppu.read(data), 0x22);
+            ppu.write(specification, 0x00);
+            assert_eq!(ppu.read(data), 0x33);
+
+            // Without auto-increment the address stays, and reads never move it.
+            ppu.write(specification, 0x05);
+            ppu.write(data, 0x44);
+            ppu.write(data, 0x55);
+            assert_eq!(ppu.read(data), 0x55);
+            assert_eq!(ppu.read(specification), 0x05);
+
+            // Mode 3 blocks the palette RAM but still increments the address.
+            ppu.write(specification, 0x80);
+            ppu.mode = PpuMode::Drawing;
+            ppu.write(data, 0x66);
+            assert_eq!(ppu.read(data), 0xff);
+            assert_eq!(ppu.read(specification), 0x81);
+            ppu.mode = PpuMode::HBlank;
+            ppu.write(specification, 0x00);
+            assert_eq!(ppu.read(data), 0x33);
+        }
+    }
+
+    #[test]
+    fn bg_attributes_pick_bank_palette_and_flips() {
+        let mut ppu = cgb_ppu();
+        let mut vram = vram();
+        let oam = [0x00; 0xa0];
+
+        let line = draw_line(&mut ppu, &vram, &oam, 0);
+        assert_eq!(line[0], bg(0, 1));
+        assert_eq!(line[1..8], [bg(0, 0); 7]);
+
+        // Bank 1 and palette 1.
+        vram[1][0x1800] = 0x09;
+        let line = draw_line(&mut ppu, &vram, &oam, 7);
+        assert_eq!(line[7], bg(1, 2));
+        assert_eq!(line[0..7], [bg(1, 0); 7]);
+        assert_eq!(line[8], bg(0, 0));
+
+        // Horizontal flip.
+        vram[1][0x1800] = 0x20;
+        let line = draw_line(&mut ppu, &vram, &oam, 0);
+        assert_eq!(line[7], bg(0, 1));
+        assert_eq!(line[0], bg(0, 0));
+
+        // Vertical flip.
+        vram[1][0x1800] = 0x40;
+        assert_eq!(draw_line(&mut ppu, &vram, &oam, 7)[0], bg(0, 1));
+        assert_eq!(draw_line(&mut ppu, &vram, &oam, 0)[0], bg(0, 0));
+
+        // Both flips, from bank 1.
+        vram[1][0x1800] = 0x68;
+        assert_eq!(draw_line(&mut ppu, &vram, &oam, 0)[0], bg(0, 2));
+
+        // Attributes and bank 1 are ignored in DMG compatibility mode.
+        ppu.set_cgb_mode(false);
+        ppu.write(0x47, 0xe4);
+        let line = draw_line(&mut ppu, &vram, &oam, 0);
+        assert_eq!(line[0], bg(0, 1));
+    }
+
+    #[test]
+    fn object_bank_comes_from_its_attributes() {
+        let mut ppu = cgb_ppu();
+        let mut vram = vram();
+        vram[1][0x0020..0x0030].fill(0x00);
+        let mut oam = [0x00; 0xa0];
+
+        oam[0..4].copy_from_slice(&[16, 8 + 16, 0x02, 0x00]);
+        assert_eq!(draw_line(&mut ppu, &vram, &oam, 0)[17], obj(0, 3));
+
+        oam[3] = 0x08;
+        assert_eq!(draw_line(&mut ppu, &vram, &oam, 0)[17], bg(0, 0));
+    }
+
+    #[test]
+    fn bg_priority_attribute() {
+        let mut ppu = cgb_ppu();
+        let mut vram = vram();
+        let mut oam = [0x00; 0xa0];
+        oam[0..4].copy_from_slice(&[16, 8, 0x02, 0x00]);
+
+        let line = draw_line(&mut ppu, &vram, &oam, 0);
+        assert_eq!(line[0..8], [obj(0, 3); 8]);
+
+        // The background wins, but only over its non-zero colors.
+        vram[1][0x1800] = 0x80;
+        let line = draw_line(&mut ppu, &vram, &oam, 0);
+        assert_eq!(line[0], bg(0, 1));
+        assert_eq!(line[1..8], [obj(0, 3); 7]);
+
+        // Same with the object attribute instead.
+        vram[1][0x1800] = 0x00;
+        oam[3] = 0x80;
+        let line = draw_line(&mut ppu, &vram, &oam, 0);
+        assert_eq!(line[0], bg(0, 1));
+        assert_eq!(line[1..8], [obj(0, 3); 7]);
+    }
+
+    #[test]
+    fn lcdc_bit_0_removes_bg_priority_in_cgb_mode() {
+        let mut ppu = cgb_ppu();
+        let mut vram = vram();
+        vram[1][0x1800] = 0x80;
+        let mut oam = [0x00; 0xa0];
+        oam[0..4].copy_from_slice(&[16, 8, 0x02, 0x80]);
+
+        ppu.write(0x40, 0x92);
+        let line = draw_line(&mut ppu, &vram, &oam, 0);
+        assert_eq!(line[0..8], [obj(0, 3); 8]);
+        // The background is still drawn.
+        assert_eq!(line[8], bg(0, 1));
+        assert_eq!(line[9], bg(0, 0));
+
+        // In DMG compatibility mode it hides the background.
+        ppu.set_cgb_mode(false);
+        ppu.write(0x47, 0xe4);
+        let line = draw_line(&mut ppu, &vram, &oam, 0);
+        assert_eq!(line[8], bg(0, 0));
+    }
+
+    #[test]
+    fn opri_selects_object_priority() {
+        let mut ppu = cgb_ppu();
+        let vram = vram();
+        let mut oam = [0x00; 0xa0];
+        // The first object in OAM is further right.
+        oam[0..4].copy_from_slice(&[16, 12 + 16, 0x02, 0x01]);
+        oam[4..8].copy_from_slice(&[16, 8 + 16, 0x03, 0x00]);
+
+        let line = draw_line(&mut ppu, &vram, &oam, 0);
+        assert_eq!(line[16..20], [obj(0, 1); 4]);
+        assert_eq!(line[20..24], [obj(1, 3); 4]);
+
+        ppu.write(0x6c, 0x01);
+        let line = draw_line(&mut ppu, &vram, &oam, 0);
+        assert_eq!(line[16..24], [obj(0, 1); 8]);
+    }
+
+    #[test]
+    fn hidden_object_still_hides_objects_behind_it() {
+        let mut ppu = cgb_ppu();
+        let vram = vram();
+        let mut oam = [0x00; 0xa0];
+        // The first object wins over the second one, and the background over the first one.
+        oam[0..4].copy_from_slice(&[16, 8, 0x02, 0x80]);
+        oam[4..8].copy_from_slice(&[16, 8, 0x03, 0x01]);
+
+        let line = draw_line(&mut ppu, &vram, &oam, 0);
+        assert_eq!(line[0], bg(0, 1));
+        assert_eq!(line[1..8], [obj(0, 3); 7]);
+    }
+}
@@ -0,0 +1,487 @@
+use crate::joypad::{JoyPad, JoyPadButton};
+use alloc::boxed::Box;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt::{Display, Formatter};
+
+/// Buttons a source asks for during one frame, as `JoyPadButton` masks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct SourceState {
+    pub pressed: u8,
+    /// Buttons held with turbo: pressed and released at the turbo rate.
+    pub turbo: u8,
+}
+
+/// Anything that produces input: a keyboard, a bot, a movie being played back...
+pub trait InputSource {
+    fn poll(&mut self, frame: u64) -> SourceState;
+}
+
+/// Bot input: one button mask per frame, nothing pressed after the last one.
+pub struct ScriptedSource {
+    frames: Vec<u8>,
+    start: u64,
+}
+
+impl ScriptedSource {
+    /// `frames[0]` is applied on frame `start`.
+    pub fn new(frames: Vec<u8>, start: u64) -> Self {
+        Self { frames, start }
+    }
+}
+
+impl InputSource for ScriptedSource {
+    fn poll(&mut self, frame: u64) -> SourceState {
+        let pressed = frame
+            .checked_sub(self.start)
+            .and_then(|index| self.frames.get(index as usize))
+            .copied()
+            .unwrap_or(0x00);
+
+        SourceState { pressed, turbo: 0 }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InputConfigError {
+    /// Line without `=`.
+    Syntax(usize),
+    UnknownButton(usize, String),
+    InvalidValue(usize, String),
+}
+
+impl Display for InputConfigError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self {
+            InputConfigError::Syntax(line) => write!(f, "Line {}: expected `key = button`", line),
+            InputConfigError::UnknownButton(line, button) => {
+                write!(f, "Line {}: unknown button {}", line, button)
+            }
+            InputConfigError::InvalidValue(line, value) => {
+                write!(f, "Line {}: invalid value {}", line, value)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyBinding {
+    /// Host key name, as reported by the frontend.
+    pub key: String,
+    pub button: JoyPadButton,
+    pub turbo: bool,
+}
+
+/// Key mapping and input settings. The file has one entry per line, `#` starting a comment:
+///
+/// ```text
+/// Z = A
+/// X = B
+/// turbo S = A
+/// turbo_frames = 2
+/// block_opposite_directions = true
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InputConfig {
+    pub bindings: Vec<KeyBinding>,
+    /// Frames a turbo button stays pressed, then released.
+    pub turbo_frames: u32,
+    pub block_opposite_directions: bool,
+}
+
+impl Default for InputConfig {
+    fn default() -> Self {
+        let bindings = [
+            ("Right", JoyPadButton::Right),
+            ("Left", JoyPadButton::Left),
+            ("Up", JoyPadButton::Up),
+            ("Down", JoyPadButton::Down),
+            ("X", JoyPadButton::A),
+            ("Z", JoyPadButton::B),
+            ("Backspace", JoyPadButton::Select),
+            ("Return", JoyPadButton::Start),
+        ];
+
+        Self {
+            bindings: bindings
+                .into_iter()
+                .map(|(key, button)| KeyBinding {
+                    key: key.to_string(),
+                    button,
+                    turbo: false,
+                })
+                .collect(),
+            turbo_frames: 2,
+            block_opposite_directions: false,
+        }
+    }
+}
+
+impl InputConfig {
+    /// Settings not in `input` keep their default value, but bindings replace the default ones.
+    pub fn parse(input: &str) -> Result<Self, InputConfigError> {
+        let mut config = InputConfig {
+            bindings: Vec::new(),
+            ..InputConfig::default()
+        };
+
+        for (index, line) in input.lines().enumerate() {
+            let number = index + 1;
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let (name, value) = line
+                .split_once('=')
+                .ok_or(InputConfigError::Syntax(number))?;
+            let (name, value) = (name.trim(), value.trim());
+            let invalid_value = || InputConfigError::InvalidValue(number, value.to_string());
+
+            match name {
+                "turbo_frames" => {
+                    config.turbo_frames = value
+                        .parse()
+                        .ok()
+                        .filter(|&frames| frames > 0)
+                        .ok_or_else(invalid_value)?
+                }
+                "block_opposite_directions" => {
+                    config.block_opposite_directions = value.parse().map_err(|_| invalid_value())?
+                }
+                _ => {
+                    let (key, turbo) = match name.strip_prefix("turbo ") {
+                        Some(key) => (key.trim(), true),
+                        None => (name, false),
+                    };
+                    let button = JoyPadButton::from_name(value).ok_or_else(|| {
+                        InputConfigError::UnknownButton(number, value.to_string())
+                    })?;
+
+                    config.bindings.push(KeyBinding {
+                        key: key.to_string(),
+                        button,
+                        turbo,
+                    });
+                }
+            }
+        }
+
+        Ok(config)
+    }
+}
+
+/// Host keys mapped through the bindings of an `InputConfig`.
+pub struct KeyboardSource {
+    bindings: Vec<KeyBinding>,
+    held: Vec<bool>,
+}
+
+impl KeyboardSource {
+    pub fn new(bindings: Vec<KeyBinding>) -> Self {
+        Self {
+            held: alloc::vec![false; bindings.len()],
+            bindings,
+        }
+    }
+
+    fn set_key(&mut self, key: &str, state: bool) {
+        for (binding, held) in self.bindings.iter().zip(self.held.iter_mut()) {
+            if binding.key == key {
+                *held = state;
+            }
+        }
+    }
+
+    pub fn key_down(&mut self, key: &str) {
+        self.set_key(key, true);
+    }
+
+    pub fn key_up(&mut self, key: &str) {
+        self.set_key(key, false);
+    }
+
+    pub fn release_all(&mut self) {
+        self.held.fill(false);
+    }
+}
+
+impl InputSource for KeyboardSource {
+    fn poll(&mut self, _frame: u64) -> SourceState {
+        let mut state = SourceState::default();
+
+        for (binding, _) in self
+            .bindings
+            .iter()
+            .zip(&self.held)
+            .filter(|(_, &held)| held)
+        {
+            if binding.turbo {
+                state.turbo |= binding.button.mask();
+            } else {
+                state.pressed |= binding.button.mask();
+            }
+        }
+
+        state
+    }
+}
+
+/// Merges the keyboard and any other sources into the joypad state, once per frame. Turbo and
+/// autofire buttons alternate between pressed and released every `turbo_frames` frames.
+pub struct Input {
+    keyboard: KeyboardSource,
+    sources: Vec<Box<dyn InputSource>>,
+    autofire: u8,
+    turbo_frames: u32,
+    block_opposite_directions: bool,
+    frame: u64,
+}
+
+impl Default for Input {
+    fn default() -> Self {
+        Input::new(InputConfig::default())
+    }
+}
+
+impl Input {
+    pub fn new(config: InputConfig) -> Self {
+        Self {
+            keyboard: KeyboardSource::new(config.bindings),
+            sources: Vec::new(),
+            autofire: 0x00,
+            turbo_frames: config.turbo_frames.max(1),
+            block_opposite_directions: config.block_opposite_directions,
+            frame: 0,
+        }
+    }
+
+    pub fn keyboard_mut(&mut self) -> &mut KeyboardSource {
+        &mut self.keyboard
+    }
+
+    pub fn add_source(&mut self, source: Box<dyn InputSource>) {
+        self.sources.push(source);
+    }
+
+    pub fn clear_sources(&mut self) {
+        self.sources.clear();
+    }
+
+    /// Autofire buttons keep firing at the turbo rate without being held.
+    pub fn set_autofire(&mut self, button: JoyPadButton, enabled: bool) {
+        if enabled {
+            self.autofire |= button.mask();
+        } else {
+            self.autofire &= !button.mask();
+        }
+    }
+
+    pub fn set_turbo_frames(&mut self, frames: u32) {
+        self.turbo_frames = frames.max(1);
+    }
+
+    pub fn set_block_opposite_directions(&mut self, block: bool) {
+        self.block_opposite_directions = block;
+    }
+
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    /// Buttons pressed for the next frame, as a `JoyPadButton` mask.
+    pub fn poll(&mut self) -> u8 {
+        let frame = self.frame;
+        self.frame += 1;
+
+        let mut state = self.keyboard.poll(frame);
+        for source in self.sources.iter_mut() {
+            let source = source.poll(frame);
+            state.pressed |= source.pressed;
+            state.turbo |= source.turbo;
+        }
+
+        let turbo_on = (frame / self.turbo_frames as u64).is_multiple_of(2);
+        let mut pressed = state.pressed;
+        if turbo_on {
+            pressed |= state.turbo | self.autofire;
+        }
+
+        if self.block_opposite_directions {
+            pressed = Input::block_opposite_directions(pressed);
+        }
+
+        pressed
+    }
+
+    /// Pressing both directions of an axis is impossible on the real d-pad, so neither counts.
+    fn block_opposite_directions(pressed: u8) -> u8 {
+        let horizontal = JoyPadButton::Left.mask() | JoyPadButton::Right.mask();
+        let vertical = JoyPadButton::Up.mask() | JoyPadButton::Down.mask();
+        let mut pressed = pressed;
+
+        for axis in [horizontal, vertical] {
+            if pressed & axis == axis {
+                pressed &= !axis;
+            }
+        }
+
+        pressed
+    }
+
+    /// Polls the sources and hands the result to the joypad.
+    pub fn update(&mut self, joypad: &JoyPad) -> u8 {
+        let pressed = self.poll();
+        joypad.set_pressed(pressed);
+
+        pressed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    fn mask(buttons: &[JoyPadButton]) -> u8 {
+        buttons
+            .iter()
+            .fold(0x00, |mask, button| mask | button.mask())
+    }
+
+    #[test]
+    fn turbo_alternates_every_turbo_frames() {
+        let config = InputConfig::parse("S = A\nturbo S = B\nturbo_frames = 3").unwrap();
+        let mut input = Input::new(config);
+        input.keyboard_mut().key_down("S");
+
+        let (a, b) = (JoyPadButton::A.mask(), JoyPadButton::B.mask());
+        let polls = (0..12).map(|_| input.poll()).collect::<Vec<_>>();
+        assert_eq!(
+            polls,
+            [a | b, a | b, a | b, a, a, a, a | b, a | b, a | b, a, a, a]
+        );
+    }
+
+    #[test]
+    fn autofire_without_holding() {
+        let mut input = Input::default();
+        input.set_turbo_frames(1);
+        input.set_autofire(JoyPadButton::Start, true);
+
+        let start = JoyPadButton::Start.mask();
+        let polls = (0..4).map(|_| input.poll()).collect::<Vec<_>>();
+        assert_eq!(polls, [start, 0x00, start, 0x00]);
+
+        input.set_autofire(JoyPadButton::Start, false);
+        assert_eq!(input.poll(), 0x00);
+        assert_eq!(input.frame(), 5);
+    }
+
+    #[test]
+    fn opposite_directions_are_blocked() {
+        let mut input = Input::default();
+        input.add_source(Box::new(ScriptedSource::new(
+            vec![
+                mask(&[JoyPadButton::Left, JoyPadButton::Right, JoyPadButton::Up]),
+                mask(&[JoyPadButton::Up, JoyPadButton::Down, JoyPadButton::A]),
+            ],
+            0,
+        )));
+        input.keyboard_mut().key_down("Down");
+
+        assert_eq!(
+            input.poll(),
+            mask(&[
+                JoyPadButton::Left,
+                JoyPadButton::Right,
+                JoyPadButton::Up,
+                JoyPadButton::Down
+            ])
+        );
+
+        input.set_block_opposite_directions(true);
+        assert_eq!(input.poll(), JoyPadButton::A.mask());
+        assert_eq!(input.poll(), JoyPadButton::Down.mask());
+    }
+
+    #[test]
+    fn sources_are_merged() {
+        let mut input = Input::default();
+        input.add_source(Box::new(ScriptedSource::new(vec![0x01, 0x02], 1)));
+        input.keyboard_mut().key_down("Return");
+        let start = JoyPadButton::Start.mask();
+
+        assert_eq!(input.poll(), start);
+        assert_eq!(input.poll(), start | 0x01);
+        assert_eq!(input.poll(), start | 0x02);
+        input.keyboard_mut().release_all();
+        assert_eq!(input.poll(), 0x00);
+    }
+
+    #[test]
+    fn config_parsing() {
+        let config = InputConfig::parse(
+            "# Keys\n\
+             \n\
+             Z = A   # main button\n\
+             turbo  X = B\n\
+             Space = start\n\
+             turbo_frames = 4\n\
+             block_opposite_directions = true\n",
+        )
+        .unwrap();
+
+        assert_eq!(config.turbo_frames, 4);
+        assert!(config.block_opposite_directions);
+        assert_eq!(
+            config.bindings,
+            [
+                KeyBinding {
+                    key: "Z".to_string(),
+                    button: JoyPadButton::A,
+                    turbo: false,
+                },
+                KeyBinding {
+                    key: "X".to_string(),
+                    button: JoyPadButton::B,
+                    turbo: true,
+                },
+                KeyBinding {
+                    key: "Space".to_string(),
+                    button: JoyPadButton::Start,
+                    turbo: false,
+                },
+            ]
+        );
+
+        let defaults = InputConfig::parse("").unwrap();
+        assert!(defaults.bindings.is_empty());
+        assert_eq!(defaults.turbo_frames, InputConfig::default().turbo_frames);
+    }
+
+    #[test]
+    fn malformed_config_lines() {
+        for (input, error) in [
+            ("Z = A\nZ A", InputConfigError::Syntax(2)),
+            (
+                "Z = Turbo",
+                InputConfigError::UnknownButton(1, "Turbo".to_string()),
+            ),
+            (
+                "\nturbo_frames = 0",
+                InputConfigError::InvalidValue(2, "0".to_string()),
+            ),
+            (
+                "turbo_frames = fast",
+                InputConfigError::InvalidValue(1, "fast".to_string()),
+            ),
+            (
+                "block_opposite_directions = yes",
+                InputConfigError::InvalidValue(1, "yes".to_string()),
+            ),
+        ] {
+            assert_eq!(InputConfig::parse(input), Err(error));
+        }
+    }
+}
@@ -0,0 +1,220 @@
+use crate::model::Model;
+use crate::virtual_memory::MemoryMappedPeripheral;
+
+/// Bits that always read as 1 for each register at 0xFF00-0xFF7F. Write-only registers read as
+/// 0xFF and `None` marks addresses with no register behind them.
+const DMG_READ_MASKS: [Option<u8>; 0x80] = {
+    let mut masks = [None; 0x80];
+
+    masks[0x00] = Some(0xC0); // P1
+    masks[0x01] = Some(0x00); // SB
+    masks[0x02] = Some(0x7E); // SC
+    masks[0x04] = Some(0x00); // DIV
+    masks[0x05] = Some(0x00); // TIMA
+    masks[0x06] = Some(0x00); // TMA
+    masks[0x07] = Some(0xF8); // TAC
+    masks[0x0F] = Some(0xE0); // IF
+
+    masks[0x10] = Some(0x80); // NR10
+    masks[0x11] = Some(0x3F); // NR11
+    masks[0x12] = Some(0x00); // NR12
+    masks[0x13] = Some(0xFF); // NR13
+    masks[0x14] = Some(0xBF); // NR14
+    masks[0x16] = Some(0x3F); // NR21
+    masks[0x17] = Some(0x00); // NR22
+    masks[0x18] = Some(0xFF); // NR23
+    masks[0x19] = Some(0xBF); // NR24
+    masks[0x1A] = Some(0x7F); // NR30
+    masks[0x1B] = Some(0xFF); // NR31
+    masks[0x1C] = Some(0x9F); // NR32
+    masks[0x1D] = Some(0xFF); // NR33
+    masks[0x1E] = Some(0xBF); // NR34
+    masks[0x20] = Some(0xFF); // NR41
+    masks[0x21] = Some(0x00); // NR42
+    masks[0x22] = Some(0x00); // NR43
+    masks[0x23] = Some(0xBF); // NR44
+    masks[0x24] = Some(0x00); // NR50
+    masks[0x25] = Some(0x00); // NR51
+    masks[0x26] = Some(0x70); // NR52
+
+    let mut wave = 0x30;
+    while wave < 0x40 {
+        masks[wave] = Some(0x00);
+        wave += 1;
+    }
+
+    masks[0x40] = Some(0x00); // LCDC
+    masks[0x41] = Some(0x80); // STAT
+    masks[0x42] = Some(0x00); // SCY
+    masks[0x43] = Some(0x00); // SCX
+    masks[0x44] = Some(0x00); // LY
+    masks[0x45] = Some(0x00); // LYC
+    masks[0x46] = Some(0x00); // DMA
+    masks[0x47] = Some(0x00); // BGP
+    masks[0x48] = Some(0x00); // OBP0
+    masks[0x49] = Some(0x00); // OBP1
+    masks[0x4A] = Some(0x00); // WY
+    masks[0x4B] = Some(0x00); // WX
+    masks[0x50] = Some(0xFF); // BANK
+
+    masks
+};
+
+const CGB_READ_MASKS: [Option<u8>; 0x80] = {
+    let mut masks = DMG_READ_MASKS;
+
+    masks[0x02] = Some(0x7C); // SC
+    masks[0x4D] = Some(0x7E); // KEY1
+    masks[0x4F] = Some(0xFE); // VBK
+    masks[0x51] = Some(0xFF); // HDMA1
+    masks[0x52] = Some(0xFF); // HDMA2
+    masks[0x53] = Some(0xFF); // HDMA3
+    masks[0x54] = Some(0xFF); // HDMA4
+    masks[0x55] = Some(0x00); // HDMA5
+    masks[0x56] = Some(0x3C); // RP
+    masks[0x68] = Some(0x40); // BCPS
+    masks[0x69] = Some(0x00); // BCPD
+    masks[0x6A] = Some(0x40); // OCPS
+    masks[0x6B] = Some(0x00); // OCPD
+    masks[0x6C] = Some(0xFE); // OPRI
+    masks[0x70] = Some(0xF8); // SVBK
+    masks[0x72] = Some(0x00);
+    masks[0x73] = Some(0x00);
+    masks[0x74] = Some(0x00);
+    masks[0x75] = Some(0x8F);
+    masks[0x76] = Some(0x00); // PCM12
+    masks[0x77] = Some(0x00); // PCM34
+
+    masks
+};
+
+/// Backing store for the I/O registers that don't have a peripheral behind them yet. Reads apply
+/// the unused-bit masks of the selected model, and writes to unmapped addresses are dropped.
+pub struct IoRegisters {
+    registers: [u8; 0x80],
+    masks: &'static [Option<u8>; 0x80],
+}
+
+impl IoRegisters {
+    pub fn new(model: Model) -> Self {
+        Self {
+            registers: [0x00; 0x80],
+            masks: if model.is_cgb() {
+                &CGB_READ_MASKS
+            } else {
+                &DMG_READ_MASKS
+            },
+        }
+    }
+
+    pub fn is_mapped(&self, address: u16) -> bool {
+        self.masks
+            .get(address as usize)
+            .is_some_and(|mask| mask.is_some())
+    }
+
+    pub fn read_mask(&self, address: u16) -> u8 {
+        self.masks
+            .get(address as usize)
+            .copied()
+            .flatten()
+            .unwrap_or(0xFF)
+    }
+
+    /// Register value as last written, without the unused-bit mask.
+    pub fn raw(&self, address: u16) -> u8 {
+        self.registers[address as usize]
+    }
+}
+
+impl MemoryMappedPeripheral for IoRegisters {
+    fn write(&mut self, address: u16, data: u8) {
+        if !self.is_mapped(address) {
+            return;
+        }
+
+        self.registers[address as usize] = data;
+    }
+
+    fn read(&self, address: u16) -> u8 {
+        if !self.is_mapped(address) {
+            return 0xFF;
+        }
+
+        self.registers[address as usize] | self.read_mask(address)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Address, then the value read back after writing 0x00 on the DMG and on the CGB. Unmapped
+    /// addresses read 0xFF whatever was written.
+    const READ_BACK: [(u16, u8, u8); 24] = [
+        (0x00, 0xc0, 0xc0), // P1
+        (0x02, 0x7e, 0x7c), // SC
+        (0x03, 0xff, 0xff),
+        (0x07, 0xf8, 0xf8), // TAC
+        (0x0f, 0xe0, 0xe0), // IF
+        (0x10, 0x80, 0x80), // NR10
+        (0x13, 0xff, 0xff), // NR13
+        (0x15, 0xff, 0xff),
+        (0x1a, 0x7f, 0x7f), // NR30
+        (0x1c, 0x9f, 0x9f), // NR32
+        (0x26, 0x70, 0x70), // NR52
+        (0x27, 0xff, 0xff),
+        (0x30, 0x00, 0x00), // Wave RAM
+        (0x41, 0x80, 0x80), // STAT
+        (0x4d, 0xff, 0x7e), // KEY1
+        (0x4f, 0xff, 0xfe), // VBK
+        (0x50, 0xff, 0xff), // BANK
+        (0x55, 0xff, 0x00), // HDMA5
+        (0x56, 0xff, 0x3c), // RP
+        (0x68, 0xff, 0x40), // BCPS
+        (0x6c, 0xff, 0xfe), // OPRI
+        (0x70, 0xff, 0xf8), // SVBK
+        (0x75, 0xff, 0x8f),
+        (0x7f, 0xff, 0xff),
+    ];
+
+    #[test]
+    fn unused_bits_read_as_1() {
+        for (model, column) in [(Model::Dmg, 0), (Model::Cgb, 1)] {
+            let mut registers = IoRegisters::new(model);
+
+            for (address, dmg, cgb) in READ_BACK {
+                let expected = [dmg, cgb][column];
+                registers.write(address, 0x00);
+
+                assert_eq!(
+                    registers.read(address),
+                    expected,
+                    "{:?} 0xFF{:02X}",
+                    model,
+                    address
+                );
+                assert_eq!(registers.read_mask(address), expected);
+
+                registers.write(address, 0xff);
+                assert_eq!(registers.read(address), 0xff);
+            }
+        }
+    }
+
+    #[test]
+    fn unmapped_writes_are_dropped() {
+        let mut registers = IoRegisters::new(Model::Dmg);
+
+        registers.write(0x03, 0x12);
+        registers.write(0x70, 0x12);
+        registers.write(0x06, 0x12);
+
+        assert!(!registers.is_mapped(0x03));
+        assert!(!registers.is_mapped(0x70));
+        assert_eq!(registers.raw(0x03), 0x00);
+        assert_eq!(registers.raw(0x70), 0x00);
+        assert_eq!(registers.raw(0x06), 0x12);
+        assert!(IoRegisters::new(Model::Agb).is_mapped(0x70));
+    }
+}
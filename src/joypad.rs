@@ -1,60 +1,254 @@
 use crate::virtual_memory::MemoryMappedPeripheral;
-use core::sync::atomic::{AtomicU8, Ordering};
+use core::sync::atomic::{AtomicU32, AtomicU8, Ordering};
 
+/// P1 register. The button state is handed over through an atomic so the frontend can update
+/// it from its own thread.
 pub struct JoyPad {
+    /// One bit per `JoyPadButton`, 1 when pressed.
     buttons: AtomicU8,
+    /// Select lines P14 and P15, 0 when selected.
     register: u8,
+    /// Input lines P10-P13 as of the last interrupt check, to detect falling edges.
+    lines: u8,
+    /// Reads of P1 with a button group selected since the last `take_polls`.
+    polls: AtomicU32,
 }
 
 impl Default for JoyPad {
     fn default() -> Self {
         Self {
             buttons: AtomicU8::new(0),
-            register: 0x3F,
+            register: 0x30,
+            lines: 0x0F,
+            polls: AtomicU32::new(0),
         }
     }
 }
 
 impl JoyPad {
     pub fn update_button_state(&self, button: JoyPadButton, state: bool) {
-        self.buttons
-            .fetch_or((state as u8) << button as usize, Ordering::SeqCst);
+        let mask = button.mask();
+
+        if state {
+            self.buttons.fetch_or(mask, Ordering::SeqCst);
+        } else {
+            self.buttons.fetch_and(!mask, Ordering::SeqCst);
+        }
+    }
+
+    pub fn press(&self, button: JoyPadButton) {
+        self.update_button_state(button, true);
+    }
+
+    pub fn release(&self, button: JoyPadButton) {
+        self.update_button_state(button, false);
+    }
+
+    /// Replaces the state of every button at once, from a `JoyPadButton` mask.
+    pub fn set_pressed(&self, buttons: u8) {
+        self.buttons.store(buttons, Ordering::Release);
+    }
+
+    /// Bits of the pressed buttons, indexed by `JoyPadButton`.
+    pub fn pressed(&self) -> u8 {
+        self.buttons.load(Ordering::Acquire)
+    }
+
+    /// P10-P13 as the CPU sees them: a line is low when a pressed button is on a selected row,
+    /// so selecting both rows merges them.
+    fn input_lines(&self) -> u8 {
+        let buttons = self.pressed();
+        let mut pressed = 0x00;
+
+        if self.register & 0x10 == 0 {
+            pressed |= buttons & 0x0F;
+        }
+        if self.register & 0x20 == 0 {
+            pressed |= buttons >> 4;
+        }
+
+        !pressed & 0x0F
+    }
+
+    /// P1 as the CPU would read it, without counting as an input poll.
+    pub fn peek(&self) -> u8 {
+        0xC0 | self.register | self.input_lines()
+    }
+
+    /// Number of input polls since the last call.
+    pub fn take_polls(&self) -> u32 {
+        self.polls.swap(0, Ordering::AcqRel)
+    }
+
+    /// Returns true when one of P10-P13 went from high to low since the last check, which
+    /// requests the joypad interrupt.
+    pub fn check_interrupt(&mut self) -> bool {
+        let lines = self.input_lines();
+        let falling = self.lines & !lines != 0;
+        self.lines = lines;
+
+        falling
     }
 }
 
+/// Buttons in the order of the P1 bits: directions on P14, actions on P15.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum JoyPadButton {
-    Down,
-    Up,
-    Left,
     Right,
-    Start,
-    Select,
-    B,
+    Left,
+    Up,
+    Down,
     A,
+    B,
+    Select,
+    Start,
+}
+
+impl JoyPadButton {
+    pub const ALL: [JoyPadButton; 8] = [
+        JoyPadButton::Right,
+        JoyPadButton::Left,
+        JoyPadButton::Up,
+        JoyPadButton::Down,
+        JoyPadButton::A,
+        JoyPadButton::B,
+        JoyPadButton::Select,
+        JoyPadButton::Start,
+    ];
+
+    pub fn mask(self) -> u8 {
+        1 << self as u8
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            JoyPadButton::Right => "Right",
+            JoyPadButton::Left => "Left",
+            JoyPadButton::Up => "Up",
+            JoyPadButton::Down => "Down",
+            JoyPadButton::A => "A",
+            JoyPadButton::B => "B",
+            JoyPadButton::Select => "Select",
+            JoyPadButton::Start => "Start",
+        }
+    }
+
+    /// Case-insensitive inverse of `name`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        JoyPadButton::ALL
+            .into_iter()
+            .find(|button| button.name().eq_ignore_ascii_case(name))
+    }
 }
 
 impl MemoryMappedPeripheral for JoyPad {
     fn write(&mut self, _address: u16, data: u8) {
-        if data & 0x20 != 0 {
-            self.register |= 0x20;
-        } else {
-            self.register &= 0x20u8.reverse_bits();
+        self.register = data & 0x30;
+    }
+
+    fn read(&self, _address: u16) -> u8 {
+        if self.register & 0x30 != 0x30 {
+            self.polls.fetch_add(1, Ordering::AcqRel);
         }
 
-        if data & 0x10 != 0 {
-            self.register |= 0x10;
-        } else {
-            self.register &= 0x10u8.reverse_bits();
+        self.peek()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn joypad(select: u8, buttons: &[JoyPadButton]) -> JoyPad {
+        let mut joypad = JoyPad::default();
+        joypad.write(0xff00, select);
+        for &button in buttons {
+            joypad.press(button);
         }
+        joypad
     }
 
-    fn read(&self, _address: u16) -> u8 {
-        if (self.register & 0x20) == 0 {
-            0b0010_0000 | (self.buttons.load(Ordering::Acquire) >> 4)
-        } else if (self.register & 0x10) == 0 {
-            0b0001_0000 | (self.buttons.load(Ordering::Acquire) & 0x0f)
-        } else {
-            0x3F
+    #[test]
+    fn nothing_selected_reads_released() {
+        let joypad = joypad(0x30, &JoyPadButton::ALL);
+        assert_eq!(joypad.read(0xff00), 0xff);
+    }
+
+    #[test]
+    fn directions_row() {
+        let joypad = joypad(
+            0x20,
+            &[JoyPadButton::Left, JoyPadButton::Down, JoyPadButton::A],
+        );
+        assert_eq!(joypad.read(0xff00), 0xe5);
+    }
+
+    #[test]
+    fn actions_row() {
+        let joypad = joypad(
+            0x10,
+            &[JoyPadButton::Left, JoyPadButton::B, JoyPadButton::Start],
+        );
+        assert_eq!(joypad.read(0xff00), 0xd5);
+    }
+
+    #[test]
+    fn both_rows_merge() {
+        let joypad = joypad(0x00, &[JoyPadButton::Right, JoyPadButton::B]);
+        assert_eq!(joypad.read(0xff00), 0xcc);
+    }
+
+    #[test]
+    fn every_button_lowers_its_line() {
+        for (index, button) in JoyPadButton::ALL.into_iter().enumerate() {
+            let select = if index < 4 { 0x20 } else { 0x10 };
+            let joypad = joypad(select, &[button]);
+            assert_eq!(joypad.peek(), 0xc0 | select | (0x0f & !(1 << (index % 4))));
         }
     }
+
+    #[test]
+    fn only_the_select_bits_are_writable() {
+        let joypad = joypad(0xcf, &[]);
+        assert_eq!(joypad.peek(), 0xcf);
+    }
+
+    #[test]
+    fn reads_with_a_row_selected_count_as_polls() {
+        let joypad = joypad(0x30, &[]);
+        joypad.read(0xff00);
+        assert_eq!(joypad.take_polls(), 0);
+
+        let joypad = self::joypad(0x20, &[]);
+        joypad.read(0xff00);
+        joypad.read(0xff00);
+        joypad.peek();
+        assert_eq!(joypad.take_polls(), 2);
+        assert_eq!(joypad.take_polls(), 0);
+    }
+
+    #[test]
+    fn interrupt_on_falling_edge_of_a_selected_line() {
+        let mut joypad = joypad(0x20, &[]);
+        assert!(!joypad.check_interrupt());
+
+        joypad.press(JoyPadButton::A);
+        assert!(!joypad.check_interrupt());
+
+        joypad.press(JoyPadButton::Up);
+        assert!(joypad.check_interrupt());
+        assert!(!joypad.check_interrupt());
+
+        joypad.release(JoyPadButton::Up);
+        assert!(!joypad.check_interrupt());
+    }
+
+    #[test]
+    fn selecting_a_row_with_a_held_button_requests_the_interrupt() {
+        let mut joypad = joypad(0x30, &[JoyPadButton::Start]);
+        assert!(!joypad.check_interrupt());
+
+        joypad.write(0xff00, 0x10);
+        assert!(joypad.check_interrupt());
+    }
 }
@@ -4,10 +4,31 @@
 extern crate alloc;
 
 pub mod audio;
+pub mod boot_rom;
 pub mod cartridge;
+pub mod colorization;
 pub mod cpu;
+pub mod dat;
+pub mod dma;
+pub mod gameboy;
+pub mod gbx;
 pub mod graphics;
+pub mod hash;
+pub mod input;
+pub mod io_registers;
 pub mod joypad;
+pub mod mbc;
+pub mod model;
+pub mod movie;
+pub mod oam_bug;
+pub mod page_table;
+pub mod patch;
+pub mod peripheral_registry;
+pub mod power_on;
 pub mod ram;
 pub mod serial_data;
+pub mod symbols;
+pub mod timer;
+pub mod uninitialized;
 pub mod virtual_memory;
+pub mod watchpoint;
@@ -0,0 +1,128 @@
+use crate::cartridge::Mapper;
+
+/// Bank controller of the cartridge: writes to 0x0000-0x7FFF select the ROM and RAM banks and
+/// enable the external RAM. Mappers without support here behave like plain ROM.
+pub struct Mbc {
+    mapper: Mapper,
+    rom_banks: usize,
+    ram_enabled: bool,
+    rom_bank: usize,
+    ram_bank: usize,
+    /// MBC1 upper bank bits, used for ROM or RAM depending on `mode`.
+    upper_bits: usize,
+    mode: u8,
+}
+
+impl Mbc {
+    pub fn new(mapper: Mapper, rom_banks: usize) -> Self {
+        Self {
+            mapper,
+            rom_banks: rom_banks.max(2),
+            ram_enabled: !matches!(
+                mapper,
+                Mapper::Mbc1 | Mapper::Mbc1Multicart | Mapper::Mbc2 | Mapper::Mbc3 | Mapper::Mbc5
+            ),
+            rom_bank: 1,
+            ram_bank: 0,
+            upper_bits: 0,
+            mode: 0,
+        }
+    }
+
+    pub fn mapper(&self) -> Mapper {
+        self.mapper
+    }
+
+    /// Handles a write to the ROM area. Returns true when the mapping changed.
+    pub fn write(&mut self, address: u16, data: u8) -> bool {
+        let before = (self.ram_enabled, self.rom_bank(), self.ram_bank());
+
+        match (self.mapper, address) {
+            (Mapper::Mbc1 | Mapper::Mbc1Multicart, 0x0000..=0x1fff)
+            | (Mapper::Mbc3 | Mapper::Mbc5, 0x0000..=0x1fff) => {
+                self.ram_enabled = data & 0x0f == 0x0a
+            }
+            (Mapper::Mbc1 | Mapper::Mbc1Multicart, 0x2000..=0x3fff) => {
+                self.rom_bank = (data & 0x1f).max(1) as usize
+            }
+            (Mapper::Mbc1 | Mapper::Mbc1Multicart, 0x4000..=0x5fff) => {
+                self.upper_bits = (data & 0x03) as usize
+            }
+            (Mapper::Mbc1 | Mapper::Mbc1Multicart, 0x6000..=0x7fff) => self.mode = data & 0x01,
+            (Mapper::Mbc2, 0x0000..=0x3fff) if address & 0x0100 == 0 => {
+                self.ram_enabled = data & 0x0f == 0x0a
+            }
+            (Mapper::Mbc2, 0x0000..=0x3fff) => self.rom_bank = (data & 0x0f).max(1) as usize,
+            (Mapper::Mbc3, 0x2000..=0x3fff) => self.rom_bank = (data & 0x7f).max(1) as usize,
+            // Values 0x08-0x0C select the RTC registers, which aren't emulated.
+            (Mapper::Mbc3, 0x4000..=0x5fff) => self.ram_bank = (data & 0x03) as usize,
+            (Mapper::Mbc5, 0x2000..=0x2fff) => {
+                self.rom_bank = (self.rom_bank & 0x100) | data as usize
+            }
+            (Mapper::Mbc5, 0x3000..=0x3fff) => {
+                self.rom_bank = (self.rom_bank & 0xff) | ((data as usize & 0x01) << 8)
+            }
+            (Mapper::Mbc5, 0x4000..=0x5fff) => self.ram_bank = (data & 0x0f) as usize,
+            _ => {}
+        }
+
+        before != (self.ram_enabled, self.rom_bank(), self.ram_bank())
+    }
+
+    pub fn ram_enabled(&self) -> bool {
+        self.ram_enabled
+    }
+
+    /// Bank mapped at 0x4000-0x7FFF, counted from the start of the ROM.
+    pub fn rom_bank(&self) -> usize {
+        let bank = match self.mapper {
+            Mapper::Mbc1 => self.upper_bits << 5 | self.rom_bank,
+            Mapper::Mbc1Multicart => self.upper_bits << 4 | (self.rom_bank & 0x0f),
+            _ => self.rom_bank,
+        };
+
+        bank % self.rom_banks
+    }
+
+    pub fn ram_bank(&self) -> usize {
+        match self.mapper {
+            Mapper::Mbc1 | Mapper::Mbc1Multicart if self.mode == 1 => self.upper_bits,
+            Mapper::Mbc1 | Mapper::Mbc1Multicart => 0,
+            _ => self.ram_bank,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bank_0_selects_bank_1_on_mbc1_to_mbc3() {
+        for (mapper, address) in [
+            (Mapper::Mbc1, 0x2000),
+            (Mapper::Mbc2, 0x2100),
+            (Mapper::Mbc3, 0x2000),
+        ] {
+            let mut mbc = Mbc::new(mapper, 8);
+            mbc.write(address, 0x03);
+            mbc.write(address, 0x00);
+            assert_eq!(mbc.rom_bank(), 1);
+        }
+    }
+
+    #[test]
+    fn mbc5_maps_bank_0() {
+        let mut mbc = Mbc::new(Mapper::Mbc5, 8);
+        assert!(mbc.write(0x2000, 0x00));
+        assert_eq!(mbc.rom_bank(), 0);
+    }
+
+    #[test]
+    fn mbc5_ninth_bank_bit() {
+        let mut mbc = Mbc::new(Mapper::Mbc5, 0x200);
+        mbc.write(0x2000, 0x05);
+        mbc.write(0x3000, 0x01);
+        assert_eq!(mbc.rom_bank(), 0x105);
+    }
+}
@@ -0,0 +1,233 @@
+use crate::cartridge::{CartridgeHeader, CgbSupport};
+use crate::cpu::Registers;
+
+/// Hardware revision being emulated. Each one has its own boot ROM and post-boot state, and the
+/// CGB and AGB add the CGB features.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Model {
+    /// Early DMG boot ROM revision.
+    Dmg0,
+    #[default]
+    Dmg,
+    /// Game Boy Pocket and Light.
+    Mgb,
+    Sgb,
+    Sgb2,
+    Cgb,
+    /// Game Boy Advance running Game Boy software.
+    Agb,
+}
+
+impl Model {
+    pub const ALL: [Model; 7] = [
+        Model::Dmg0,
+        Model::Dmg,
+        Model::Mgb,
+        Model::Sgb,
+        Model::Sgb2,
+        Model::Cgb,
+        Model::Agb,
+    ];
+
+    /// Picks the CGB for games that support it, the SGB for games with SGB functions and the
+    /// DMG otherwise.
+    pub fn for_header(header: &CartridgeHeader) -> Self {
+        match header.cgb_support() {
+            CgbSupport::Compatible | CgbSupport::CgbOnly => Model::Cgb,
+            CgbSupport::None if header.sgb_support() => Model::Sgb,
+            CgbSupport::None => Model::Dmg,
+        }
+    }
+
+    pub fn is_cgb(&self) -> bool {
+        matches!(self, Model::Cgb | Model::Agb)
+    }
+
+    pub fn is_sgb(&self) -> bool {
+        matches!(self, Model::Sgb | Model::Sgb2)
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Model::Dmg0 => "dmg0",
+            Model::Dmg => "dmg",
+            Model::Mgb => "mgb",
+            Model::Sgb => "sgb",
+            Model::Sgb2 => "sgb2",
+            Model::Cgb => "cgb",
+            Model::Agb => "agb",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Model::ALL
+            .into_iter()
+            .find(|model| model.name().eq_ignore_ascii_case(name))
+    }
+
+    /// CPU registers when the boot ROM hands over to the cartridge at 0x0100. Games read A, and
+    /// B on the AGB, to detect the hardware they run on.
+    pub fn post_boot_registers(&self, header: &CartridgeHeader) -> Registers {
+        // The DMG boot ROM ends comparing the header checksum, which sets H and C unless it's 0.
+        let dmg_flags = if header.header_checksum() == 0x00 {
+            0x80
+        } else {
+            0xb0
+        };
+        let dmg_compatibility = header.cgb_support() == CgbSupport::None;
+        let (af, bc, de, hl) = match self {
+            Model::Dmg0 => (0x0100, 0xff13, 0x00c1, 0x8403),
+            Model::Dmg => (0x0100 | dmg_flags, 0x0013, 0x00d8, 0x014d),
+            Model::Mgb => (0xff00 | dmg_flags, 0x0013, 0x00d8, 0x014d),
+            Model::Sgb => (0x0100, 0x0014, 0x0000, 0xc060),
+            Model::Sgb2 => (0xff00, 0x0014, 0x0000, 0xc060),
+            Model::Cgb | Model::Agb if dmg_compatibility => {
+                Model::dmg_compatibility_registers(*self, header)
+            }
+            Model::Cgb => (0x1180, 0x0000, 0xff56, 0x000d),
+            Model::Agb => (0x1100, 0x0100, 0xff56, 0x000d),
+        };
+
+        let mut registers = Registers {
+            sp: 0xfffe,
+            pc: 0x0100,
+            ..Registers::default()
+        };
+        registers.set_af(af);
+        registers.set_bc(bc);
+        registers.set_de(de);
+        registers.set_hl(hl);
+
+        registers
+    }
+
+    /// AF, BC, DE and HL the CGB boot ROM leaves after setting up the palettes of DMG software.
+    /// B holds the title checksum of Nintendo games, which also decides HL. The AGB boot ROM
+    /// then increments B.
+    fn dmg_compatibility_registers(model: Model, header: &CartridgeHeader) -> (u16, u16, u16, u16) {
+        let nintendo = header.licensed_by_nintendo();
+        let checksum = if nintendo {
+            header.title_checksum()
+        } else {
+            0x00
+        };
+        let hl = if nintendo && matches!(checksum, 0x43 | 0x58) {
+            0x991a
+        } else {
+            0x007c
+        };
+
+        if model == Model::Agb {
+            let b = checksum.wrapping_add(1);
+            let zero = if b == 0x00 { 0x80 } else { 0x00 };
+            let half_carry = if b & 0x0f == 0x00 { 0x20 } else { 0x00 };
+            (0x1100 | zero | half_carry, (b as u16) << 8, 0x0008, hl)
+        } else {
+            (0x1180, (checksum as u16) << 8, 0x0008, hl)
+        }
+    }
+
+    /// DIV when the boot ROM hands over. The SGB and CGB boot ROMs take a time that depends on
+    /// the cartridge header, so they start from 0.
+    pub fn post_boot_div(&self) -> u8 {
+        match self {
+            Model::Dmg0 => 0x18,
+            Model::Dmg | Model::Mgb => 0xab,
+            Model::Sgb | Model::Sgb2 | Model::Cgb | Model::Agb => 0x00,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    fn header(title: &[u8], cgb_flag: u8, old_licensee: u8) -> CartridgeHeader {
+        let mut rom = vec![0x00; 0x8000];
+        rom[0x0134..0x0134 + title.len()].copy_from_slice(title);
+        rom[0x0143] = cgb_flag;
+        rom[0x014b] = old_licensee;
+        CartridgeHeader::parse(&rom).unwrap()
+    }
+
+    fn registers(model: Model, header: &CartridgeHeader) -> (u16, u16, u16, u16) {
+        let registers = model.post_boot_registers(header);
+        (
+            registers.af(),
+            registers.bc(),
+            registers.de(),
+            registers.hl(),
+        )
+    }
+
+    #[test]
+    fn cgb_software_on_cgb_and_agb() {
+        let header = header(b"TETRIS", 0x80, 0x01);
+
+        assert_eq!(
+            registers(Model::Cgb, &header),
+            (0x1180, 0x0000, 0xff56, 0x000d)
+        );
+        assert_eq!(
+            registers(Model::Agb, &header),
+            (0x1100, 0x0100, 0xff56, 0x000d)
+        );
+    }
+
+    #[test]
+    fn nintendo_dmg_software_on_cgb_and_agb() {
+        // "TETRIS" sums to 0xdb.
+        let header = header(b"TETRIS", 0x00, 0x01);
+        assert_eq!(header.title_checksum(), 0xdb);
+
+        assert_eq!(
+            registers(Model::Cgb, &header),
+            (0x1180, 0xdb00, 0x0008, 0x007c)
+        );
+        assert_eq!(
+            registers(Model::Agb, &header),
+            (0x1100, 0xdc00, 0x0008, 0x007c)
+        );
+    }
+
+    #[test]
+    fn title_checksums_pointing_hl_into_the_tile_map() {
+        for title in [b"C", b"X"] {
+            let header = header(title, 0x00, 0x01);
+            assert_eq!(registers(Model::Cgb, &header).3, 0x991a);
+            assert_eq!(registers(Model::Agb, &header).3, 0x991a);
+        }
+    }
+
+    #[test]
+    fn other_dmg_software_on_cgb_and_agb() {
+        let header = header(b"C", 0x00, 0x00);
+
+        assert_eq!(
+            registers(Model::Cgb, &header),
+            (0x1180, 0x0000, 0x0008, 0x007c)
+        );
+        assert_eq!(
+            registers(Model::Agb, &header),
+            (0x1100, 0x0100, 0x0008, 0x007c)
+        );
+    }
+
+    #[test]
+    fn agb_flags_follow_the_increment() {
+        // 0xff + 0x10 sums to 0x0f: B becomes 0x10, with a half carry.
+        let half_carry = header(&[0xff, 0x10], 0x00, 0x01);
+        assert_eq!(
+            registers(Model::Agb, &half_carry),
+            (0x1120, 0x1000, 0x0008, 0x007c)
+        );
+
+        // 0xff alone becomes 0x00, zero and half carry.
+        let zero = header(&[0xff], 0x00, 0x01);
+        assert_eq!(
+            registers(Model::Agb, &zero),
+            (0x11a0, 0x0000, 0x0008, 0x007c)
+        );
+    }
+}
@@ -0,0 +1,538 @@
+use crate::gameboy::Boot;
+use crate::hash::{self, RomHashes};
+use crate::input::{InputSource, SourceState};
+use crate::model::Model;
+use crate::power_on::{PowerOnPattern, PowerOnSettings};
+use alloc::vec::Vec;
+use core::fmt::{Display, Formatter};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MovieError {
+    UnexpectedEof,
+    BadMagic,
+    UnsupportedVersion(u8),
+    InvalidModel(u8),
+    InvalidPowerOnPattern(u8),
+    InvalidBoot(u8),
+}
+
+impl Display for MovieError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self {
+            MovieError::UnexpectedEof => write!(f, "Unexpected end of movie file"),
+            MovieError::BadMagic => write!(f, "Not a movie file"),
+            MovieError::UnsupportedVersion(version) => {
+                write!(f, "Unsupported movie version {}", version)
+            }
+            MovieError::InvalidModel(model) => write!(f, "Invalid model {:#04x}", model),
+            MovieError::InvalidPowerOnPattern(pattern) => {
+                write!(f, "Invalid power-on pattern {:#04x}", pattern)
+            }
+            MovieError::InvalidBoot(boot) => write!(f, "Invalid boot mode {:#04x}", boot),
+        }
+    }
+}
+
+/// State hash taken at the end of `frame`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Checkpoint {
+    pub frame: u64,
+    pub state_hash: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Desync {
+    pub frame: u64,
+    pub expected: u32,
+    pub actual: u32,
+}
+
+impl Display for Desync {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "Desync at frame {}: state hash {:08x}, expected {:08x}",
+            self.frame, self.actual, self.expected
+        )
+    }
+}
+
+/// How the recording got to the cartridge. A dumped boot ROM isn't stored, only its SHA-1, so
+/// playback needs the same dump.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MovieBoot {
+    #[default]
+    Default,
+    BootRom([u8; 20]),
+    Skip,
+}
+
+impl From<&Boot> for MovieBoot {
+    fn from(boot: &Boot) -> Self {
+        match boot {
+            Boot::Default => MovieBoot::Default,
+            Boot::BootRom(boot_rom) => MovieBoot::BootRom(hash::sha1(boot_rom)),
+            Boot::Skip => MovieBoot::Skip,
+        }
+    }
+}
+
+/// Joypad state of every frame from power on, with everything else needed to replay it exactly:
+/// model, power-on memory contents, boot, buttons held at power on and ROM. Checkpoints of the
+/// state hash every `checkpoint_interval` frames catch desyncs close to where they happen.
+///
+/// Movies always start at power on: there are no save states to start from, and no RTC to seed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Movie {
+    pub model: Model,
+    pub power_on: PowerOnSettings,
+    pub boot: MovieBoot,
+    /// Buttons held down at power on, as a `JoyPadButton` mask.
+    pub held_buttons: u8,
+    pub rom_sha1: [u8; 20],
+    pub checkpoint_interval: u32,
+    frames: Vec<u8>,
+    /// Frames during which the game didn't read the joypad.
+    lag: Vec<bool>,
+    checkpoints: Vec<Checkpoint>,
+}
+
+impl Movie {
+    const MAGIC: &'static [u8; 4] = b"RBMV";
+    const VERSION: u8 = 1;
+    pub const DEFAULT_CHECKPOINT_INTERVAL: u32 = 60;
+
+    pub fn new(model: Model, power_on: PowerOnSettings, rom_hashes: &RomHashes) -> Self {
+        Self {
+            model,
+            power_on,
+            boot: MovieBoot::Default,
+            held_buttons: 0x00,
+            rom_sha1: rom_hashes.sha1,
+            checkpoint_interval: Movie::DEFAULT_CHECKPOINT_INTERVAL,
+            frames: Vec::new(),
+            lag: Vec::new(),
+            checkpoints: Vec::new(),
+        }
+    }
+
+    pub fn matches_rom(&self, rom_hashes: &RomHashes) -> bool {
+        self.rom_sha1 == rom_hashes.sha1
+    }
+
+    pub fn len(&self) -> u64 {
+        self.frames.len() as u64
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    /// Buttons pressed during `frame`, as a `JoyPadButton` mask.
+    pub fn buttons(&self, frame: u64) -> Option<u8> {
+        self.frames.get(frame as usize).copied()
+    }
+
+    pub fn is_lag_frame(&self, frame: u64) -> Option<bool> {
+        self.lag.get(frame as usize).copied()
+    }
+
+    pub fn lag_frames(&self) -> u64 {
+        self.lag.iter().filter(|&&lag| lag).count() as u64
+    }
+
+    pub fn checkpoints(&self) -> &[Checkpoint] {
+        &self.checkpoints
+    }
+
+    /// Appends the next frame. `state_hash` is only called on checkpoint frames, after the
+    /// frame has been emulated.
+    pub fn record_frame(&mut self, buttons: u8, lag: bool, state_hash: impl FnOnce() -> u32) {
+        let frame = self.len();
+        self.frames.push(buttons);
+        self.lag.push(lag);
+
+        if (frame + 1).is_multiple_of(self.checkpoint_interval as u64) {
+            self.checkpoints.push(Checkpoint {
+                frame,
+                state_hash: state_hash(),
+            });
+        }
+    }
+
+    /// Compares the state hash at the end of `frame` with the recorded one, if there is a
+    /// checkpoint on that frame.
+    pub fn verify(&self, frame: u64, state_hash: impl FnOnce() -> u32) -> Result<(), Desync> {
+        let Some(checkpoint) = self
+            .checkpoints
+            .iter()
+            .find(|checkpoint| checkpoint.frame == frame)
+        else {
+            return Ok(());
+        };
+
+        let actual = state_hash();
+        if actual != checkpoint.state_hash {
+            return Err(Desync {
+                frame,
+                expected: checkpoint.state_hash,
+                actual,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Drops everything from `frame` on, to resume recording there during playback.
+    pub fn truncate(&mut self, frame: u64) {
+        self.frames.truncate(frame as usize);
+        self.lag.truncate(frame as usize);
+        self.checkpoints
+            .retain(|checkpoint| checkpoint.frame < frame);
+    }
+
+    /// Input source replaying the recorded frames.
+    pub fn playback(&self) -> MoviePlayback {
+        MoviePlayback {
+            frames: self.frames.clone(),
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut output = Vec::new();
+
+        output.extend_from_slice(Movie::MAGIC);
+        output.push(Movie::VERSION);
+        output.push(match self.model {
+            Model::Dmg => 0x00,
+            Model::Cgb => 0x01,
+            Model::Dmg0 => 0x02,
+            Model::Mgb => 0x03,
+            Model::Sgb => 0x04,
+            Model::Sgb2 => 0x05,
+            Model::Agb => 0x06,
+        });
+        output.extend_from_slice(&self.power_on.seed.to_le_bytes());
+        for pattern in [
+            self.power_on.vram,
+            self.power_on.sram,
+            self.power_on.wram,
+            self.power_on.oam,
+            self.power_on.hram,
+        ] {
+            output.push(pattern as u8);
+        }
+        match self.boot {
+            MovieBoot::Default => output.push(0x00),
+            MovieBoot::BootRom(sha1) => {
+                output.push(0x01);
+                output.extend_from_slice(&sha1);
+            }
+            MovieBoot::Skip => output.push(0x02),
+        }
+        output.push(self.held_buttons);
+        output.extend_from_slice(&self.rom_sha1);
+        output.extend_from_slice(&self.checkpoint_interval.to_le_bytes());
+
+        output.extend_from_slice(&(self.frames.len() as u32).to_le_bytes());
+        output.extend_from_slice(&self.frames);
+        output.extend(self.lag.iter().map(|&lag| lag as u8));
+
+        output.extend_from_slice(&(self.checkpoints.len() as u32).to_le_bytes());
+        for checkpoint in &self.checkpoints {
+            output.extend_from_slice(&checkpoint.frame.to_le_bytes());
+            output.extend_from_slice(&checkpoint.state_hash.to_le_bytes());
+        }
+
+        output
+    }
+
+    pub fn parse(content: &[u8]) -> Result<Self, MovieError> {
+        let mut reader = Reader { content };
+
+        if reader.take(4)? != Movie::MAGIC {
+            return Err(MovieError::BadMagic);
+        }
+
+        let version = reader.u8()?;
+        if version != Movie::VERSION {
+            return Err(MovieError::UnsupportedVersion(version));
+        }
+
+        let model = match reader.u8()? {
+            0x00 => Model::Dmg,
+            0x01 => Model::Cgb,
+            0x02 => Model::Dmg0,
+            0x03 => Model::Mgb,
+            0x04 => Model::Sgb,
+            0x05 => Model::Sgb2,
+            0x06 => Model::Agb,
+            model => return Err(MovieError::InvalidModel(model)),
+        };
+
+        let seed = reader.u64()?;
+        let mut patterns = [PowerOnPattern::default(); 5];
+        for pattern in patterns.iter_mut() {
+            *pattern = match reader.u8()? {
+                0x00 => PowerOnPattern::Zeros,
+                0x01 => PowerOnPattern::Ones,
+                0x02 => PowerOnPattern::Random,
+                pattern => return Err(MovieError::InvalidPowerOnPattern(pattern)),
+            };
+        }
+        let [vram, sram, wram, oam, hram] = patterns;
+        let power_on = PowerOnSettings {
+            seed,
+            vram,
+            sram,
+            wram,
+            oam,
+            hram,
+        };
+
+        let boot = match reader.u8()? {
+            0x00 => MovieBoot::Default,
+            0x01 => MovieBoot::BootRom(reader.sha1()?),
+            0x02 => MovieBoot::Skip,
+            boot => return Err(MovieError::InvalidBoot(boot)),
+        };
+        let held_buttons = reader.u8()?;
+        let rom_sha1 = reader.sha1()?;
+        let checkpoint_interval = reader.u32()?;
+
+        let length = reader.u32()? as usize;
+        let frames = reader.take(length)?.to_vec();
+        let lag = reader.take(length)?.iter().map(|&lag| lag != 0).collect();
+
+        let length = reader.u32()? as usize;
+        let mut checkpoints = Vec::new();
+        for _ in 0..length {
+            checkpoints.push(Checkpoint {
+                frame: reader.u64()?,
+                state_hash: reader.u32()?,
+            });
+        }
+
+        Ok(Self {
+            model,
+            power_on,
+            boot,
+            held_buttons,
+            rom_sha1,
+            checkpoint_interval,
+            frames,
+            lag,
+            checkpoints,
+        })
+    }
+}
+
+struct Reader<'a> {
+    content: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, length: usize) -> Result<&'a [u8], MovieError> {
+        if self.content.len() < length {
+            return Err(MovieError::UnexpectedEof);
+        }
+
+        let (bytes, rest) = self.content.split_at(length);
+        self.content = rest;
+
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, MovieError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn sha1(&mut self) -> Result<[u8; 20], MovieError> {
+        let mut sha1 = [0x00; 20];
+        sha1.copy_from_slice(self.take(20)?);
+
+        Ok(sha1)
+    }
+
+    fn u32(&mut self) -> Result<u32, MovieError> {
+        let mut bytes = [0x00; 4];
+        bytes.copy_from_slice(self.take(4)?);
+
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    fn u64(&mut self) -> Result<u64, MovieError> {
+        let mut bytes = [0x00; 8];
+        bytes.copy_from_slice(self.take(8)?);
+
+        Ok(u64::from_le_bytes(bytes))
+    }
+}
+
+/// Plays a movie back through `Input`. Nothing is pressed past its last frame.
+pub struct MoviePlayback {
+    frames: Vec<u8>,
+}
+
+impl InputSource for MoviePlayback {
+    fn poll(&mut self, frame: u64) -> SourceState {
+        SourceState {
+            pressed: self.frames.get(frame as usize).copied().unwrap_or(0x00),
+            turbo: 0x00,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::power_on::PowerOnPattern;
+
+    fn movie() -> Movie {
+        let mut movie = Movie::new(
+            Model::Cgb,
+            PowerOnSettings {
+                wram: PowerOnPattern::Random,
+                ..PowerOnSettings::uniform(PowerOnPattern::Zeros, 0x0123_4567_89ab_cdef)
+            },
+            &RomHashes::compute(b"rom"),
+        );
+        movie.boot = MovieBoot::BootRom([0x5a; 20]);
+        movie.held_buttons = 0x14;
+        movie.checkpoint_interval = 2;
+
+        for frame in 0..5u8 {
+            movie.record_frame(frame * 3, frame == 1, || 0xdead_0000 | frame as u32);
+        }
+
+        movie
+    }
+
+    #[test]
+    fn record_frames() {
+        let movie = movie();
+
+        assert_eq!(movie.len(), 5);
+        assert_eq!(movie.buttons(4), Some(12));
+        assert_eq!(movie.buttons(5), None);
+        assert_eq!(movie.is_lag_frame(1), Some(true));
+        assert_eq!(movie.lag_frames(), 1);
+        assert_eq!(
+            movie.checkpoints(),
+            &[
+                Checkpoint {
+                    frame: 1,
+                    state_hash: 0xdead_0001,
+                },
+                Checkpoint {
+                    frame: 3,
+                    state_hash: 0xdead_0003,
+                },
+            ]
+        );
+        assert!(movie.matches_rom(&RomHashes::compute(b"rom")));
+        assert!(!movie.matches_rom(&RomHashes::compute(b"other rom")));
+    }
+
+    #[test]
+    fn round_trip() {
+        let movie = movie();
+        let bytes = movie.to_bytes();
+
+        assert_eq!(&bytes[..5], b"RBMV\x01");
+        assert_eq!(Movie::parse(&bytes), Ok(movie.clone()));
+
+        for boot in [MovieBoot::Default, MovieBoot::Skip] {
+            let movie = Movie {
+                boot,
+                ..movie.clone()
+            };
+            assert_eq!(Movie::parse(&movie.to_bytes()), Ok(movie));
+        }
+    }
+
+    #[test]
+    fn boot_from_builder_settings() {
+        assert_eq!(MovieBoot::from(&Boot::Default), MovieBoot::Default);
+        assert_eq!(MovieBoot::from(&Boot::Skip), MovieBoot::Skip);
+        assert_eq!(
+            MovieBoot::from(&Boot::BootRom(b"abc".to_vec())),
+            MovieBoot::BootRom(hash::sha1(b"abc"))
+        );
+    }
+
+    #[test]
+    fn parse_errors() {
+        let bytes = movie().to_bytes();
+
+        assert_eq!(Movie::parse(b"RBMW\x01"), Err(MovieError::BadMagic));
+        assert_eq!(
+            Movie::parse(b"RBMV\x02"),
+            Err(MovieError::UnsupportedVersion(2))
+        );
+
+        let mut invalid_model = bytes.clone();
+        invalid_model[5] = 0x07;
+        assert_eq!(
+            Movie::parse(&invalid_model),
+            Err(MovieError::InvalidModel(0x07))
+        );
+
+        let mut invalid_pattern = bytes.clone();
+        invalid_pattern[14] = 0x03;
+        assert_eq!(
+            Movie::parse(&invalid_pattern),
+            Err(MovieError::InvalidPowerOnPattern(0x03))
+        );
+
+        let mut invalid_boot = bytes.clone();
+        invalid_boot[19] = 0x03;
+        assert_eq!(
+            Movie::parse(&invalid_boot),
+            Err(MovieError::InvalidBoot(0x03))
+        );
+
+        for length in 0..bytes.len() {
+            assert_eq!(
+                Movie::parse(&bytes[..length]),
+                Err(MovieError::UnexpectedEof)
+            );
+        }
+    }
+
+    #[test]
+    fn verify_checkpoints() {
+        let movie = movie();
+
+        assert_eq!(movie.verify(0, || unreachable!()), Ok(()));
+        assert_eq!(movie.verify(3, || 0xdead_0003), Ok(()));
+        assert_eq!(
+            movie.verify(3, || 0xbeef),
+            Err(Desync {
+                frame: 3,
+                expected: 0xdead_0003,
+                actual: 0xbeef,
+            })
+        );
+    }
+
+    #[test]
+    fn truncate_drops_later_checkpoints() {
+        let mut movie = movie();
+        movie.truncate(3);
+
+        assert_eq!(movie.len(), 3);
+        assert_eq!(movie.checkpoints().len(), 1);
+
+        movie.record_frame(0x01, false, || 0xcafe);
+        assert_eq!(movie.checkpoints()[1].state_hash, 0xcafe);
+    }
+
+    #[test]
+    fn playback_releases_everything_past_the_end() {
+        let mut playback = movie().playback();
+
+        assert_eq!(playback.poll(2).pressed, 6);
+        assert_eq!(playback.poll(5).pressed, 0x00);
+    }
+}
@@ -0,0 +1,172 @@
+/// CPU accesses that corrupt OAM on the DMG when they put an address in 0xFE00-0xFEFF on the bus
+/// during OAM scan. Combined accesses, like `POP` or `LD A, [HLI]`, have their own pattern
+/// instead of the sum of the two.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OamBugAccess {
+    Read,
+    Write,
+    /// 16-bit increment or decrement of a register pointing into OAM.
+    IncDec,
+    ReadIncDec,
+}
+
+/// OAM is corrupted 8 bytes (a row) at a time, in the row the PPU is scanning.
+pub const ROW_SIZE: usize = 8;
+pub const ROWS: usize = 20;
+
+fn word(oam: &[u8], row: usize, index: usize) -> u16 {
+    let offset = row * ROW_SIZE + index * 2;
+    u16::from_le_bytes([oam[offset], oam[offset + 1]])
+}
+
+fn set_word(oam: &mut [u8], row: usize, index: usize, value: u16) {
+    let offset = row * ROW_SIZE + index * 2;
+    oam[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+}
+
+fn copy_row(oam: &mut [u8], from: usize, to: usize, start: usize) {
+    oam.copy_within(
+        from * ROW_SIZE + start..(from + 1) * ROW_SIZE,
+        to * ROW_SIZE + start,
+    );
+}
+
+/// Applies the corruption `access` causes while the PPU is scanning `row`. The first row is
+/// never affected.
+pub fn corrupt(oam: &mut [u8], row: usize, access: OamBugAccess) {
+    if row == 0 || row >= ROWS {
+        return;
+    }
+
+    match access {
+        OamBugAccess::Write | OamBugAccess::IncDec => {
+            let a = word(oam, row, 0);
+            let b = word(oam, row - 1, 0);
+            let c = word(oam, row - 1, 2);
+
+            set_word(oam, row, 0, ((a ^ c) & (b ^ c)) ^ c);
+            copy_row(oam, row - 1, row, 2);
+        }
+        OamBugAccess::Read => {
+            let a = word(oam, row, 0);
+            let b = word(oam, row - 1, 0);
+            let c = word(oam, row - 1, 2);
+
+            set_word(oam, row, 0, b | (a & c));
+            copy_row(oam, row - 1, row, 2);
+        }
+        OamBugAccess::ReadIncDec => {
+            // Only rows with two rows before them, and not the last one, get the extra step.
+            if (4..ROWS - 1).contains(&row) {
+                let a = word(oam, row - 2, 0);
+                let b = word(oam, row - 1, 0);
+                let c = word(oam, row, 0);
+                let d = word(oam, row - 1, 2);
+
+                set_word(oam, row - 1, 0, (b & (a | c | d)) | (a & c & d));
+                copy_row(oam, row - 1, row, 0);
+                copy_row(oam, row - 1, row - 2, 0);
+            }
+
+            corrupt(oam, row, OamBugAccess::Read);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn oam() -> [u8; ROWS * ROW_SIZE] {
+        let mut oam = [0x00; ROWS * ROW_SIZE];
+        for (index, byte) in oam.iter_mut().enumerate() {
+            *byte = index as u8;
+        }
+
+        set_word(&mut oam, 0, 0, 0x1234);
+        set_word(&mut oam, 0, 2, 0x00ff);
+        set_word(&mut oam, 1, 0, 0xf0f0);
+        oam
+    }
+
+    #[test]
+    fn write_corruption() {
+        let mut oam = oam();
+        corrupt(&mut oam, 1, OamBugAccess::Write);
+
+        assert_eq!(word(&oam, 1, 0), 0x10f4);
+        assert_eq!(oam[10..16], oam[2..8]);
+        assert_eq!(word(&oam, 0, 0), 0x1234);
+    }
+
+    #[test]
+    fn inc_dec_corrupts_like_write() {
+        let mut write = oam();
+        let mut inc_dec = oam();
+        corrupt(&mut write, 1, OamBugAccess::Write);
+        corrupt(&mut inc_dec, 1, OamBugAccess::IncDec);
+
+        assert_eq!(write, inc_dec);
+    }
+
+    #[test]
+    fn read_corruption() {
+        let mut oam = oam();
+        corrupt(&mut oam, 1, OamBugAccess::Read);
+
+        assert_eq!(word(&oam, 1, 0), 0x12f4);
+        assert_eq!(oam[10..16], oam[2..8]);
+    }
+
+    #[test]
+    fn first_row_and_out_of_range_rows_are_not_corrupted() {
+        for access in [
+            OamBugAccess::Read,
+            OamBugAccess::Write,
+            OamBugAccess::IncDec,
+            OamBugAccess::ReadIncDec,
+        ] {
+            for row in [0, ROWS] {
+                let mut oam = oam();
+                corrupt(&mut oam, row, access);
+                assert_eq!(oam, self::oam());
+            }
+        }
+    }
+
+    #[test]
+    fn read_inc_dec_in_the_first_rows_is_a_read() {
+        let mut read = oam();
+        let mut read_inc_dec = oam();
+        corrupt(&mut read, 1, OamBugAccess::Read);
+        corrupt(&mut read_inc_dec, 1, OamBugAccess::ReadIncDec);
+
+        assert_eq!(read, read_inc_dec);
+    }
+
+    #[test]
+    fn read_inc_dec_corruption() {
+        let mut oam = oam();
+        set_word(&mut oam, 2, 0, 0x00ff);
+        set_word(&mut oam, 3, 0, 0x0f0f);
+        set_word(&mut oam, 3, 2, 0x3333);
+        set_word(&mut oam, 4, 0, 0xf000);
+        corrupt(&mut oam, 4, OamBugAccess::ReadIncDec);
+
+        assert_eq!(word(&oam, 3, 0), 0x030f);
+        assert_eq!(word(&oam, 3, 2), 0x3333);
+        assert_eq!(oam[16..24], oam[24..32]);
+        assert_eq!(oam[32..40], oam[24..32]);
+        assert_eq!(oam[8..16], self::oam()[8..16]);
+    }
+
+    #[test]
+    fn read_inc_dec_in_the_last_row_is_a_read() {
+        let mut read = oam();
+        let mut read_inc_dec = oam();
+        corrupt(&mut read, ROWS - 1, OamBugAccess::Read);
+        corrupt(&mut read_inc_dec, ROWS - 1, OamBugAccess::ReadIncDec);
+
+        assert_eq!(read, read_inc_dec);
+    }
+}
@@ -0,0 +1,58 @@
+/// Memory regions backed by plain buffers, which the page table can point into directly.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Region {
+    BootRom,
+    RomBank0,
+    RomBankN,
+    ExternalRam,
+    Wram0,
+    WramN,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Page {
+    /// The access needs the full address decoding: I/O and MBC registers, VRAM and OAM, which
+    /// the PPU can block, peripherals or anything with side effects.
+    Handler,
+    /// The last page: 0xFF80-0xFFFE are HRAM accessed directly, the I/O registers and IE go
+    /// through the address decoding.
+    HighRam,
+    /// The 256 bytes of the page live at `offset` in `bank` of `region`.
+    Direct {
+        region: Region,
+        bank: u16,
+        offset: u16,
+    },
+}
+
+/// One entry per 256-byte page of the address space, separately for reads and writes since ROM
+/// pages are only readable directly. It must be rebuilt whenever the mapping changes: bank
+/// switches, boot ROM unmapping, DMA transfers and peripheral registration.
+pub struct PageTable {
+    read: [Page; 0x100],
+    write: [Page; 0x100],
+}
+
+impl Default for PageTable {
+    fn default() -> Self {
+        Self {
+            read: [Page::Handler; 0x100],
+            write: [Page::Handler; 0x100],
+        }
+    }
+}
+
+impl PageTable {
+    pub fn read_page(&self, address: u16) -> Page {
+        self.read[(address >> 8) as usize]
+    }
+
+    pub fn write_page(&self, address: u16) -> Page {
+        self.write[(address >> 8) as usize]
+    }
+
+    pub fn map(&mut self, page: u8, read: Page, write: Page) {
+        self.read[page as usize] = read;
+        self.write[page as usize] = write;
+    }
+}
@@ -0,0 +1,645 @@
+use crate::hash::crc32;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt::{Display, Formatter};
+
+/// The largest ROM a cartridge header can describe: 512 banks of 16 KiB.
+const MAX_TARGET_SIZE: usize = 0x80_0000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PatchFormat {
+    Ips,
+    Ups,
+    Bps,
+}
+
+impl PatchFormat {
+    const IPS_MAGIC: &'static [u8] = b"PATCH";
+    const UPS_MAGIC: &'static [u8] = b"UPS1";
+    const BPS_MAGIC: &'static [u8] = b"BPS1";
+
+    pub fn detect(patch: &[u8]) -> Option<Self> {
+        if patch.starts_with(PatchFormat::IPS_MAGIC) {
+            Some(PatchFormat::Ips)
+        } else if patch.starts_with(PatchFormat::UPS_MAGIC) {
+            Some(PatchFormat::Ups)
+        } else if patch.starts_with(PatchFormat::BPS_MAGIC) {
+            Some(PatchFormat::Bps)
+        } else {
+            None
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            PatchFormat::Ips => "ips",
+            PatchFormat::Ups => "ups",
+            PatchFormat::Bps => "bps",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PatchError {
+    UnknownFormat,
+    UnexpectedEof,
+    SourceSizeMismatch,
+    SourceChecksumMismatch,
+    TargetChecksumMismatch,
+    PatchChecksumMismatch,
+    /// A copy reads outside of the source or target buffer.
+    OutOfBounds,
+    /// IPS offsets are 24 bits wide, so it cannot describe ROMs bigger than 16 MiB. A UPS target
+    /// can't be bigger than the largest cartridge.
+    TooLarge,
+    /// A UPS or BPS number or offset overflows.
+    InvalidNumber,
+}
+
+impl Display for PatchError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        let message = match self {
+            PatchError::UnknownFormat => "Unknown patch format",
+            PatchError::UnexpectedEof => "Unexpected end of patch",
+            PatchError::SourceSizeMismatch => "Patch is not meant for this ROM size",
+            PatchError::SourceChecksumMismatch => "Patch is not meant for this ROM",
+            PatchError::TargetChecksumMismatch => "Patched ROM checksum mismatch",
+            PatchError::PatchChecksumMismatch => "Patch file is corrupted",
+            PatchError::OutOfBounds => "Patch accesses data out of bounds",
+            PatchError::TooLarge => "ROM is too large for the patch format",
+            PatchError::InvalidNumber => "Patch contains an out of range number",
+        };
+
+        write!(f, "{}", message)
+    }
+}
+
+/// Detects the patch format from its magic number and applies it to `rom`.
+pub fn apply(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError> {
+    match PatchFormat::detect(patch) {
+        Some(PatchFormat::Ips) => apply_ips(rom, patch),
+        Some(PatchFormat::Ups) => apply_ups(rom, patch),
+        Some(PatchFormat::Bps) => apply_bps(rom, patch),
+        None => Err(PatchError::UnknownFormat),
+    }
+}
+
+struct PatchReader<'a> {
+    patch: &'a [u8],
+    position: usize,
+}
+
+impl<'a> PatchReader<'a> {
+    fn new(patch: &'a [u8], position: usize) -> Self {
+        Self { patch, position }
+    }
+
+    fn is_at(&self, end: usize) -> bool {
+        self.position >= end
+    }
+
+    fn bytes(&mut self, length: usize) -> Result<&'a [u8], PatchError> {
+        let end = self
+            .position
+            .checked_add(length)
+            .ok_or(PatchError::UnexpectedEof)?;
+        let bytes = self
+            .patch
+            .get(self.position..end)
+            .ok_or(PatchError::UnexpectedEof)?;
+        self.position += length;
+
+        Ok(bytes)
+    }
+
+    fn byte(&mut self) -> Result<u8, PatchError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn big_endian(&mut self, length: usize) -> Result<usize, PatchError> {
+        Ok(self
+            .bytes(length)?
+            .iter()
+            .fold(0, |value, &byte| (value << 8) | byte as usize))
+    }
+
+    /// Variable length integer shared by UPS and BPS.
+    fn number(&mut self) -> Result<usize, PatchError> {
+        let mut value = 0usize;
+        let mut shift = 1usize;
+
+        loop {
+            let byte = self.byte()?;
+            value = ((byte & 0x7f) as usize)
+                .checked_mul(shift)
+                .and_then(|digit| value.checked_add(digit))
+                .ok_or(PatchError::InvalidNumber)?;
+
+            if byte & 0x80 != 0 {
+                return Ok(value);
+            }
+
+            shift = shift.checked_mul(0x80).ok_or(PatchError::InvalidNumber)?;
+            value = value.checked_add(shift).ok_or(PatchError::InvalidNumber)?;
+        }
+    }
+}
+
+fn encode_number(output: &mut Vec<u8>, mut value: usize) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+
+        if value == 0 {
+            output.push(byte | 0x80);
+            return;
+        }
+
+        output.push(byte);
+        value -= 1;
+    }
+}
+
+fn read_footer_crc(patch: &[u8], index: usize) -> u32 {
+    let start = patch.len() - 12 + index * 4;
+    u32::from_le_bytes(patch[start..start + 4].try_into().unwrap())
+}
+
+fn verify_footer(patch: &[u8], source: &[u8]) -> Result<(), PatchError> {
+    if crc32(&patch[..patch.len() - 4]) != read_footer_crc(patch, 2) {
+        return Err(PatchError::PatchChecksumMismatch);
+    }
+
+    if crc32(source) != read_footer_crc(patch, 0) {
+        return Err(PatchError::SourceChecksumMismatch);
+    }
+
+    Ok(())
+}
+
+pub fn apply_ips(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError> {
+    const EOF: &[u8] = b"EOF";
+
+    if !patch.starts_with(PatchFormat::IPS_MAGIC) {
+        return Err(PatchError::UnknownFormat);
+    }
+
+    let mut output = rom.to_vec();
+    let mut reader = PatchReader::new(patch, PatchFormat::IPS_MAGIC.len());
+
+    loop {
+        let offset = reader.bytes(3)?;
+        if offset == EOF {
+            break;
+        }
+
+        let offset = offset
+            .iter()
+            .fold(0, |value, &byte| (value << 8) | byte as usize);
+        let length = reader.big_endian(2)?;
+
+        let (length, data) = if length == 0 {
+            let length = reader.big_endian(2)?;
+            (length, vec![reader.byte()?; length])
+        } else {
+            (length, reader.bytes(length)?.to_vec())
+        };
+
+        if output.len() < offset + length {
+            output.resize(offset + length, 0x00);
+        }
+        output[offset..offset + length].copy_from_slice(&data);
+    }
+
+    if let Ok(truncate) = reader.big_endian(3) {
+        output.truncate(truncate);
+    }
+
+    Ok(output)
+}
+
+pub fn apply_ups(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError> {
+    if !patch.starts_with(PatchFormat::UPS_MAGIC) {
+        return Err(PatchError::UnknownFormat);
+    }
+
+    if patch.len() < PatchFormat::UPS_MAGIC.len() + 12 {
+        return Err(PatchError::UnexpectedEof);
+    }
+
+    verify_footer(patch, rom)?;
+
+    let mut reader = PatchReader::new(patch, PatchFormat::UPS_MAGIC.len());
+    let source_size = reader.number()?;
+    let target_size = reader.number()?;
+    if source_size != rom.len() {
+        return Err(PatchError::SourceSizeMismatch);
+    }
+    if target_size > MAX_TARGET_SIZE {
+        return Err(PatchError::TooLarge);
+    }
+
+    // As with BPS, the size comes from the patch, so the output only grows as the patch writes to
+    // it. Bytes past the source read as zero.
+    let mut output = rom[..source_size.min(target_size)].to_vec();
+
+    let mut offset = 0usize;
+    while !reader.is_at(patch.len() - 12) {
+        offset = offset
+            .checked_add(reader.number()?)
+            .filter(|&offset| offset <= source_size.max(target_size))
+            .ok_or(PatchError::OutOfBounds)?;
+
+        loop {
+            let xor = reader.byte()?;
+            if xor == 0x00 {
+                offset += 1;
+                break;
+            }
+
+            if offset < output.len() {
+                output[offset] ^= xor;
+            } else if offset < target_size {
+                output.resize(offset, 0x00);
+                output.push(xor);
+            }
+            offset += 1;
+        }
+    }
+
+    // Trailing zeros aren't in the patch, so they are the only bytes filled from the size alone.
+    output.resize(target_size, 0x00);
+
+    if crc32(&output) != read_footer_crc(patch, 1) {
+        return Err(PatchError::TargetChecksumMismatch);
+    }
+
+    Ok(output)
+}
+
+pub fn apply_bps(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError> {
+    if !patch.starts_with(PatchFormat::BPS_MAGIC) {
+        return Err(PatchError::UnknownFormat);
+    }
+
+    if patch.len() < PatchFormat::BPS_MAGIC.len() + 12 {
+        return Err(PatchError::UnexpectedEof);
+    }
+
+    verify_footer(patch, rom)?;
+
+    let mut reader = PatchReader::new(patch, PatchFormat::BPS_MAGIC.len());
+    let source_size = reader.number()?;
+    let target_size = reader.number()?;
+    let metadata_size = reader.number()?;
+    reader.bytes(metadata_size)?;
+
+    if source_size != rom.len() {
+        return Err(PatchError::SourceSizeMismatch);
+    }
+
+    // The size comes from the patch, so it's only checked at the end rather than allocated.
+    let mut output = Vec::new();
+    let mut source_offset = 0isize;
+    let mut target_offset = 0isize;
+
+    let relative = |reader: &mut PatchReader, offset: &mut isize| -> Result<usize, PatchError> {
+        let data = reader.number()?;
+        let delta = isize::try_from(data >> 1).map_err(|_| PatchError::InvalidNumber)?;
+        *offset = if data & 1 != 0 {
+            offset.checked_sub(delta)
+        } else {
+            offset.checked_add(delta)
+        }
+        .ok_or(PatchError::InvalidNumber)?;
+
+        usize::try_from(*offset).map_err(|_| PatchError::OutOfBounds)
+    };
+
+    while !reader.is_at(patch.len() - 12) {
+        let data = reader.number()?;
+        let length = (data >> 2) + 1;
+
+        match data & 0x03 {
+            0 => {
+                let start = output.len();
+                let source = start
+                    .checked_add(length)
+                    .and_then(|end| rom.get(start..end))
+                    .ok_or(PatchError::OutOfBounds)?;
+                output.extend_from_slice(source);
+            }
+            1 => output.extend_from_slice(reader.bytes(length)?),
+            2 => {
+                let start = relative(&mut reader, &mut source_offset)?;
+                let source = start
+                    .checked_add(length)
+                    .and_then(|end| rom.get(start..end))
+                    .ok_or(PatchError::OutOfBounds)?;
+                output.extend_from_slice(source);
+                source_offset += length as isize;
+            }
+            _ => {
+                let start = relative(&mut reader, &mut target_offset)?;
+
+                // The copy can overlap with the bytes it produces, so it must go byte by byte.
+                let end = start.checked_add(length).ok_or(PatchError::OutOfBounds)?;
+                for i in start..end {
+                    let byte = *output.get(i).ok_or(PatchError::OutOfBounds)?;
+                    output.push(byte);
+                }
+                target_offset += length as isize;
+            }
+        }
+    }
+
+    if output.len() != target_size || crc32(&output) != read_footer_crc(patch, 1) {
+        return Err(PatchError::TargetChecksumMismatch);
+    }
+
+    Ok(output)
+}
+
+pub fn create_ips(original: &[u8], modified: &[u8]) -> Result<Vec<u8>, PatchError> {
+    const MAX_OFFSET: usize = 0xFF_FFFF;
+    const MAX_RECORD: usize = 0xFFFF;
+    const EOF_OFFSET: usize = 0x45_4F46;
+
+    if modified.len() > MAX_OFFSET {
+        return Err(PatchError::TooLarge);
+    }
+
+    let differs = |offset: usize| original.get(offset) != modified.get(offset);
+    let mut patch = PatchFormat::IPS_MAGIC.to_vec();
+    let mut offset = 0;
+
+    while offset < modified.len() {
+        if !differs(offset) {
+            offset += 1;
+            continue;
+        }
+
+        let mut end = offset + 1;
+
+        // A record at 0x454F46 would read as the "EOF" marker, so start one byte earlier.
+        if offset == EOF_OFFSET {
+            offset -= 1;
+        }
+
+        while end < modified.len() && end - offset < MAX_RECORD && differs(end) {
+            end += 1;
+        }
+
+        patch.extend_from_slice(&(offset as u32).to_be_bytes()[1..]);
+        patch.extend_from_slice(&((end - offset) as u16).to_be_bytes());
+        patch.extend_from_slice(&modified[offset..end]);
+        offset = end;
+    }
+
+    patch.extend_from_slice(b"EOF");
+
+    if modified.len() < original.len() {
+        patch.extend_from_slice(&(modified.len() as u32).to_be_bytes()[1..]);
+    }
+
+    Ok(patch)
+}
+
+pub fn create_bps(original: &[u8], modified: &[u8]) -> Vec<u8> {
+    const SOURCE_READ: usize = 0;
+    const TARGET_READ: usize = 1;
+
+    let mut patch = PatchFormat::BPS_MAGIC.to_vec();
+    encode_number(&mut patch, original.len());
+    encode_number(&mut patch, modified.len());
+    encode_number(&mut patch, 0);
+
+    let same = |offset: usize| original.get(offset) == modified.get(offset);
+    let mut offset = 0;
+
+    while offset < modified.len() {
+        let start = offset;
+        let matching = same(start);
+
+        while offset < modified.len() && same(offset) == matching {
+            offset += 1;
+        }
+
+        let command = if matching { SOURCE_READ } else { TARGET_READ };
+        encode_number(&mut patch, ((offset - start - 1) << 2) | command);
+
+        if !matching {
+            patch.extend_from_slice(&modified[start..offset]);
+        }
+    }
+
+    patch.extend_from_slice(&crc32(original).to_le_bytes());
+    patch.extend_from_slice(&crc32(modified).to_le_bytes());
+    let patch_crc = crc32(&patch);
+    patch.extend_from_slice(&patch_crc.to_le_bytes());
+
+    patch
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rom() -> Vec<u8> {
+        (0..0x400).map(|i| (i * 7 + i / 0x100) as u8).collect()
+    }
+
+    fn modified() -> Vec<u8> {
+        let mut modified = rom();
+        modified[0x10..0x20].fill(0xaa);
+        modified[0x200] ^= 0xff;
+        modified.extend_from_slice(&[0x55; 0x30]);
+        modified
+    }
+
+    fn ups_patch(original: &[u8], modified: &[u8]) -> Vec<u8> {
+        let mut patch = PatchFormat::UPS_MAGIC.to_vec();
+        encode_number(&mut patch, original.len());
+        encode_number(&mut patch, modified.len());
+
+        let xor = |offset: usize| original.get(offset).unwrap_or(&0) ^ modified[offset];
+        let mut offset = 0;
+        let mut last = 0;
+        while offset < modified.len() {
+            if xor(offset) == 0 {
+                offset += 1;
+                continue;
+            }
+
+            encode_number(&mut patch, offset - last);
+            while offset < modified.len() && xor(offset) != 0 {
+                patch.push(xor(offset));
+                offset += 1;
+            }
+            patch.push(0x00);
+            offset += 1;
+            last = offset;
+        }
+
+        patch.extend_from_slice(&crc32(original).to_le_bytes());
+        patch.extend_from_slice(&crc32(modified).to_le_bytes());
+        let patch_crc = crc32(&patch);
+        patch.extend_from_slice(&patch_crc.to_le_bytes());
+        patch
+    }
+
+    #[test]
+    fn number_round_trip() {
+        for value in [0, 1, 0x7f, 0x80, 0x407f, 0x4080, 0x12_3456, usize::MAX >> 1] {
+            let mut encoded = Vec::new();
+            encode_number(&mut encoded, value);
+
+            assert_eq!(PatchReader::new(&encoded, 0).number(), Ok(value));
+        }
+    }
+
+    #[test]
+    fn overflowing_number() {
+        let patch = [0x7f; 16];
+
+        assert_eq!(
+            PatchReader::new(&patch, 0).number(),
+            Err(PatchError::InvalidNumber)
+        );
+    }
+
+    #[test]
+    fn ips_round_trip() {
+        let patch = create_ips(&rom(), &modified()).unwrap();
+
+        assert_eq!(PatchFormat::detect(&patch), Some(PatchFormat::Ips));
+        assert_eq!(apply(&rom(), &patch), Ok(modified()));
+    }
+
+    #[test]
+    fn ips_truncates() {
+        let mut truncated = rom();
+        truncated.truncate(0x300);
+        let patch = create_ips(&rom(), &truncated).unwrap();
+
+        assert_eq!(apply_ips(&rom(), &patch), Ok(truncated));
+    }
+
+    #[test]
+    fn ips_rle_record() {
+        let mut patch = PatchFormat::IPS_MAGIC.to_vec();
+        patch.extend_from_slice(&[0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x04, 0xee]);
+        patch.extend_from_slice(b"EOF");
+
+        assert_eq!(
+            apply_ips(&[0x00; 8], &patch),
+            Ok(vec![0x00, 0x00, 0xee, 0xee, 0xee, 0xee, 0x00, 0x00])
+        );
+    }
+
+    #[test]
+    fn ups_apply() {
+        let patch = ups_patch(&rom(), &modified());
+
+        assert_eq!(apply(&rom(), &patch), Ok(modified()));
+        assert_eq!(
+            apply_ups(&modified(), &patch),
+            Err(PatchError::SourceChecksumMismatch)
+        );
+    }
+
+    #[test]
+    fn ups_pads_and_truncates() {
+        let mut padded = rom();
+        padded.extend_from_slice(&[0x00; 0x20]);
+        padded[0x410] = 0x33;
+        let patch = ups_patch(&rom(), &padded);
+        assert_eq!(apply_ups(&rom(), &patch), Ok(padded));
+
+        let truncated = rom()[..0x300].to_vec();
+        let mut patch = PatchFormat::UPS_MAGIC.to_vec();
+        encode_number(&mut patch, 0x400);
+        encode_number(&mut patch, 0x300);
+        encode_number(&mut patch, 0x380);
+        patch.extend_from_slice(&[0xff, 0x00]);
+        patch.extend_from_slice(&crc32(&rom()).to_le_bytes());
+        patch.extend_from_slice(&crc32(&truncated).to_le_bytes());
+        let patch_crc = crc32(&patch);
+        patch.extend_from_slice(&patch_crc.to_le_bytes());
+        assert_eq!(apply_ups(&rom(), &patch), Ok(truncated));
+    }
+
+    #[test]
+    fn ups_target_size_is_not_trusted() {
+        let mut patch = PatchFormat::UPS_MAGIC.to_vec();
+        encode_number(&mut patch, 0x400);
+        encode_number(&mut patch, usize::MAX >> 1);
+        patch.extend_from_slice(&crc32(&rom()).to_le_bytes());
+        patch.extend_from_slice(&0u32.to_le_bytes());
+        let patch_crc = crc32(&patch);
+        patch.extend_from_slice(&patch_crc.to_le_bytes());
+        assert_eq!(apply_ups(&rom(), &patch), Err(PatchError::TooLarge));
+
+        let mut patch = PatchFormat::UPS_MAGIC.to_vec();
+        encode_number(&mut patch, 0x400);
+        encode_number(&mut patch, 0x500);
+        encode_number(&mut patch, 0x501);
+        patch.extend_from_slice(&[0x01, 0x00]);
+        patch.extend_from_slice(&crc32(&rom()).to_le_bytes());
+        patch.extend_from_slice(&0u32.to_le_bytes());
+        let patch_crc = crc32(&patch);
+        patch.extend_from_slice(&patch_crc.to_le_bytes());
+        assert_eq!(apply_ups(&rom(), &patch), Err(PatchError::OutOfBounds));
+    }
+
+    #[test]
+    fn bps_round_trip() {
+        let patch = create_bps(&rom(), &modified());
+
+        assert_eq!(PatchFormat::detect(&patch), Some(PatchFormat::Bps));
+        assert_eq!(apply(&rom(), &patch), Ok(modified()));
+    }
+
+    #[test]
+    fn bps_target_copy_overlaps() {
+        // TargetRead of one byte, then a TargetCopy of 7 bytes starting at that byte.
+        let mut patch = PatchFormat::BPS_MAGIC.to_vec();
+        encode_number(&mut patch, 0);
+        encode_number(&mut patch, 8);
+        encode_number(&mut patch, 0);
+        encode_number(&mut patch, 1);
+        patch.push(0x42);
+        encode_number(&mut patch, (6 << 2) | 3);
+        encode_number(&mut patch, 0);
+        patch.extend_from_slice(&crc32(&[]).to_le_bytes());
+        patch.extend_from_slice(&crc32(&[0x42; 8]).to_le_bytes());
+        let patch_crc = crc32(&patch);
+        patch.extend_from_slice(&patch_crc.to_le_bytes());
+
+        assert_eq!(apply_bps(&[], &patch), Ok(vec![0x42; 8]));
+    }
+
+    #[test]
+    fn corrupted_patch() {
+        let mut patch = create_bps(&rom(), &modified());
+        let middle = patch.len() / 2;
+        patch[middle] ^= 0x01;
+
+        assert_eq!(
+            apply_bps(&rom(), &patch),
+            Err(PatchError::PatchChecksumMismatch)
+        );
+    }
+
+    #[test]
+    fn malformed_bps_number() {
+        let mut patch = PatchFormat::BPS_MAGIC.to_vec();
+        patch.extend_from_slice(&[0x7f; 16]);
+        patch.extend_from_slice(&crc32(&rom()).to_le_bytes());
+        patch.extend_from_slice(&[0x00; 4]);
+        let patch_crc = crc32(&patch);
+        patch.extend_from_slice(&patch_crc.to_le_bytes());
+
+        assert_eq!(apply_bps(&rom(), &patch), Err(PatchError::InvalidNumber));
+    }
+}
@@ -0,0 +1,219 @@
+use crate::virtual_memory::MemoryMappedPeripheral;
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::fmt::{Display, Formatter};
+use core::ops::RangeInclusive;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PeripheralId(usize);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PeripheralError {
+    EmptyRange,
+    /// The range overlaps with the one of an already registered peripheral.
+    Overlaps(PeripheralId),
+}
+
+impl Display for PeripheralError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self {
+            PeripheralError::EmptyRange => write!(f, "The peripheral range is empty"),
+            PeripheralError::Overlaps(_) => {
+                write!(f, "The peripheral range overlaps with another peripheral")
+            }
+        }
+    }
+}
+
+struct Entry {
+    id: PeripheralId,
+    range: RangeInclusive<u16>,
+    peripheral: Box<dyn MemoryMappedPeripheral>,
+}
+
+/// Peripherals attached to address ranges on top of the standard memory map. They receive
+/// addresses relative to the start of their range, and ranges can't overlap.
+pub struct PeripheralRegistry {
+    entries: Vec<Entry>,
+    claimed_pages: [bool; 0x100],
+    next_id: usize,
+}
+
+impl Default for PeripheralRegistry {
+    fn default() -> Self {
+        Self {
+            entries: Vec::new(),
+            claimed_pages: [false; 0x100],
+            next_id: 0,
+        }
+    }
+}
+
+impl PeripheralRegistry {
+    pub fn register(
+        &mut self,
+        range: RangeInclusive<u16>,
+        peripheral: Box<dyn MemoryMappedPeripheral>,
+    ) -> Result<PeripheralId, PeripheralError> {
+        if range.is_empty() {
+            return Err(PeripheralError::EmptyRange);
+        }
+        if let Some(entry) = self
+            .entries
+            .iter()
+            .find(|entry| entry.range.start() <= range.end() && range.start() <= entry.range.end())
+        {
+            return Err(PeripheralError::Overlaps(entry.id));
+        }
+
+        let id = PeripheralId(self.next_id);
+        self.next_id += 1;
+
+        self.entries.push(Entry {
+            id,
+            range,
+            peripheral,
+        });
+        self.update_claimed_pages();
+
+        Ok(id)
+    }
+
+    pub fn unregister(&mut self, id: PeripheralId) -> Option<Box<dyn MemoryMappedPeripheral>> {
+        let index = self.entries.iter().position(|entry| entry.id == id)?;
+        let entry = self.entries.remove(index);
+        self.update_claimed_pages();
+
+        Some(entry.peripheral)
+    }
+
+    pub fn get(&self, id: PeripheralId) -> Option<&dyn MemoryMappedPeripheral> {
+        self.entries
+            .iter()
+            .find(|entry| entry.id == id)
+            .map(|entry| entry.peripheral.as_ref())
+    }
+
+    pub fn get_mut(&mut self, id: PeripheralId) -> Option<&mut dyn MemoryMappedPeripheral> {
+        match self.entries.iter_mut().find(|entry| entry.id == id) {
+            Some(entry) => Some(entry.peripheral.as_mut()),
+            None => None,
+        }
+    }
+
+    fn update_claimed_pages(&mut self) {
+        self.claimed_pages = [false; 0x100];
+
+        for entry in &self.entries {
+            for page in (entry.range.start() >> 8)..=(entry.range.end() >> 8) {
+                self.claimed_pages[page as usize] = true;
+            }
+        }
+    }
+
+    /// Cheap check done on every access, so the standard memory map stays on the fast path.
+    pub fn claims(&self, address: u16) -> bool {
+        self.claimed_pages[(address >> 8) as usize]
+    }
+
+    fn find_index(&self, address: u16) -> Option<usize> {
+        if !self.claims(address) {
+            return None;
+        }
+
+        self.entries
+            .iter()
+            .position(|entry| entry.range.contains(&address))
+    }
+
+    pub fn write(&mut self, address: u16, data: u8) -> bool {
+        let Some(index) = self.find_index(address) else {
+            return false;
+        };
+
+        let entry = &mut self.entries[index];
+        entry.peripheral.write(address - entry.range.start(), data);
+
+        true
+    }
+
+    pub fn read(&self, address: u16) -> Option<u8> {
+        let entry = &self.entries[self.find_index(address)?];
+
+        Some(entry.peripheral.read(address - entry.range.start()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::rc::Rc;
+    use alloc::vec;
+    use core::cell::RefCell;
+
+    /// Logs the addresses it is accessed with and reads back their low byte.
+    struct Probe(Rc<RefCell<Vec<u16>>>);
+
+    impl MemoryMappedPeripheral for Probe {
+        fn write(&mut self, address: u16, _data: u8) {
+            self.0.borrow_mut().push(address);
+        }
+
+        fn read(&self, address: u16) -> u8 {
+            self.0.borrow_mut().push(address);
+            address as u8
+        }
+    }
+
+    fn probe() -> (Box<Probe>, Rc<RefCell<Vec<u16>>>) {
+        let log = Rc::new(RefCell::new(Vec::new()));
+        (Box::new(Probe(log.clone())), log)
+    }
+
+    #[test]
+    fn overlapping_ranges_are_rejected() {
+        let mut registry = PeripheralRegistry::default();
+        let first = registry.register(0xa000..=0xa0ff, probe().0).unwrap();
+
+        for range in [
+            0xa0ff..=0xa1ff,
+            0x9f00..=0xa000,
+            0xa010..=0xa020,
+            0x9000..=0xbfff,
+        ] {
+            assert_eq!(
+                registry.register(range, probe().0).err(),
+                Some(PeripheralError::Overlaps(first))
+            );
+        }
+        assert_eq!(
+            registry
+                .register(RangeInclusive::new(0xa200, 0xa100), probe().0)
+                .err(),
+            Some(PeripheralError::EmptyRange)
+        );
+
+        let second = registry.register(0xa100..=0xa1ff, probe().0).unwrap();
+        assert_ne!(first, second);
+        assert!(registry.unregister(first).is_some());
+        assert!(registry.register(0xa080..=0xa08f, probe().0).is_ok());
+    }
+
+    #[test]
+    fn addresses_are_relative_to_the_range() {
+        let mut registry = PeripheralRegistry::default();
+        let (peripheral, log) = probe();
+        registry.register(0xff40..=0xff4b, peripheral).unwrap();
+
+        assert_eq!(registry.read(0xff44), Some(0x04));
+        assert!(registry.write(0xff4b, 0x00));
+        assert!(registry.write(0xff40, 0x00));
+        assert_eq!(*log.borrow(), vec![0x0004, 0x000b, 0x0000]);
+
+        // The rest of the claimed page falls through to the standard map.
+        assert!(registry.claims(0xff3f));
+        assert_eq!(registry.read(0xff3f), None);
+        assert!(!registry.write(0xff4c, 0x00));
+        assert!(!registry.claims(0xfe00));
+    }
+}
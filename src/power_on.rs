@@ -0,0 +1,167 @@
+/// Contents of a memory region at power on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PowerOnPattern {
+    Zeros,
+    #[default]
+    Ones,
+    /// Pseudo-random bytes derived from the power-on seed, like the noise found in real RAM.
+    Random,
+}
+
+/// Memory regions whose power-on contents can be configured.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PowerOnRegion {
+    Vram,
+    Sram,
+    Wram,
+    Oam,
+    Hram,
+}
+
+/// Power-on pattern of every region and the seed of the random ones. Keeping the whole settings
+/// is enough to reproduce the power-on memory contents byte for byte.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PowerOnSettings {
+    pub seed: u64,
+    pub vram: PowerOnPattern,
+    pub sram: PowerOnPattern,
+    pub wram: PowerOnPattern,
+    pub oam: PowerOnPattern,
+    pub hram: PowerOnPattern,
+}
+
+impl PowerOnSettings {
+    /// Same pattern everywhere.
+    pub fn uniform(pattern: PowerOnPattern, seed: u64) -> Self {
+        Self {
+            seed,
+            vram: pattern,
+            sram: pattern,
+            wram: pattern,
+            oam: pattern,
+            hram: pattern,
+        }
+    }
+
+    pub fn pattern(&self, region: PowerOnRegion) -> PowerOnPattern {
+        match region {
+            PowerOnRegion::Vram => self.vram,
+            PowerOnRegion::Sram => self.sram,
+            PowerOnRegion::Wram => self.wram,
+            PowerOnRegion::Oam => self.oam,
+            PowerOnRegion::Hram => self.hram,
+        }
+    }
+
+    /// Byte source for `region`. Each region draws from its own stream, so changing the pattern
+    /// of one region doesn't change the contents of the others.
+    pub fn bytes(&self, region: PowerOnRegion) -> impl FnMut() -> u8 {
+        let pattern = self.pattern(region);
+        let mut rng = Rng::new(self.seed ^ (region as u64 + 1).wrapping_mul(0xA076_1D64_78BD_642F));
+
+        move || match pattern {
+            PowerOnPattern::Zeros => 0x00,
+            PowerOnPattern::Ones => 0xff,
+            PowerOnPattern::Random => rng.next_u8(),
+        }
+    }
+}
+
+/// SplitMix64: small, fast and identical on every platform, which is all reproducible runs need.
+#[derive(Debug, Clone)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    pub fn next_u8(&mut self) -> u8 {
+        (self.next_u64() >> 56) as u8
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+
+    fn take(settings: &PowerOnSettings, region: PowerOnRegion, count: usize) -> Vec<u8> {
+        let mut bytes = settings.bytes(region);
+        (0..count).map(|_| bytes()).collect()
+    }
+
+    #[test]
+    fn splitmix64_reference_values() {
+        let mut rng = Rng::new(0);
+
+        assert_eq!(rng.next_u64(), 0xe220_a839_7b1d_cdaf);
+        assert_eq!(rng.next_u64(), 0x6e78_9e6a_a1b9_65f4);
+        assert_eq!(rng.next_u64(), 0x06c4_5d18_8009_454f);
+    }
+
+    #[test]
+    fn same_seed_same_bytes() {
+        let settings = PowerOnSettings::uniform(PowerOnPattern::Random, 0x1234);
+
+        for region in [
+            PowerOnRegion::Vram,
+            PowerOnRegion::Wram,
+            PowerOnRegion::Hram,
+        ] {
+            assert_eq!(
+                take(&settings, region, 0x100),
+                take(&settings, region, 0x100)
+            );
+        }
+    }
+
+    #[test]
+    fn different_seeds_differ() {
+        let first = PowerOnSettings::uniform(PowerOnPattern::Random, 1);
+        let second = PowerOnSettings::uniform(PowerOnPattern::Random, 2);
+
+        assert_ne!(
+            take(&first, PowerOnRegion::Wram, 0x100),
+            take(&second, PowerOnRegion::Wram, 0x100)
+        );
+        // Each region has its own stream, even with the same seed.
+        assert_ne!(
+            take(&first, PowerOnRegion::Wram, 0x100),
+            take(&first, PowerOnRegion::Vram, 0x100)
+        );
+    }
+
+    #[test]
+    fn patterns_per_region() {
+        let settings = PowerOnSettings {
+            seed: 7,
+            vram: PowerOnPattern::Zeros,
+            wram: PowerOnPattern::Random,
+            ..PowerOnSettings::default()
+        };
+
+        assert_eq!(settings.pattern(PowerOnRegion::Hram), PowerOnPattern::Ones);
+        assert_eq!(take(&settings, PowerOnRegion::Vram, 0x10), [0x00; 0x10]);
+        assert_eq!(take(&settings, PowerOnRegion::Oam, 0x10), [0xff; 0x10]);
+        assert_eq!(
+            take(&settings, PowerOnRegion::Wram, 0x10),
+            take(
+                &PowerOnSettings::uniform(PowerOnPattern::Random, 7),
+                PowerOnRegion::Wram,
+                0x10
+            )
+        );
+    }
+}
@@ -11,7 +11,7 @@ pub struct Ram<const S: usize> {
 impl<const S: usize> Ram<S> {
     pub fn new(banks: usize) -> Self {
         Self {
-            buffer: (0..S).map(|_| [0xff; S].to_vec()).collect(),
+            buffer: (0..banks).map(|_| [0xff; S].to_vec()).collect(),
             banks,
             actual_bank: 0,
         }
@@ -24,6 +24,29 @@ impl<const S: usize> Ram<S> {
 
         self.actual_bank = bank;
     }
+
+    pub fn banks(&self) -> usize {
+        self.banks
+    }
+
+    pub fn actual_bank(&self) -> usize {
+        self.actual_bank
+    }
+
+    /// Overwrites every bank, in order, with bytes from `byte`.
+    pub fn fill(&mut self, mut byte: impl FnMut() -> u8) {
+        for data in self.buffer.iter_mut().flatten() {
+            *data = byte();
+        }
+    }
+
+    pub(crate) fn bank(&self, bank: usize) -> &[u8] {
+        &self.buffer[bank]
+    }
+
+    pub(crate) fn bank_mut(&mut self, bank: usize) -> &mut [u8] {
+        &mut self.buffer[bank]
+    }
 }
 
 impl<const S: usize> Default for Ram<S> {
@@ -58,12 +81,45 @@ impl<const S: usize> WriteBlock for Ram<S> {
     fn write_block(&mut self, base_address: u16, block: &[u8]) {
         let base_address = base_address as usize;
 
-        for i in 0..block.len() {
+        for (i, &data) in block.iter().enumerate() {
             if base_address + i >= S {
                 return;
             }
 
-            self.buffer[self.actual_bank][base_address + i] = block[i];
+            self.buffer[self.actual_bank][base_address + i] = data;
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn new_allocates_every_bank() {
+        let mut ram = Ram::<0x1000>::new(7);
+
+        assert_eq!(ram.banks(), 7);
+        for bank in 0..7 {
+            assert_eq!(ram.bank(bank).len(), 0x1000);
+        }
+
+        ram.sel_bank(6);
+        ram.write(0x0fff, 0x12);
+        assert_eq!(ram.bank(6)[0x0fff], 0x12);
+
+        ram.sel_bank(7);
+        assert_eq!(ram.actual_bank(), 6);
+    }
+
+    #[test]
+    fn accesses_past_the_bank_are_ignored() {
+        let mut ram = Ram::<0x100>::default();
+
+        ram.write(0x0100, 0x12);
+        ram.write_block(0x00fe, &[0x01, 0x02, 0x03]);
+
+        assert_eq!(ram.read(0x0100), 0xff);
+        assert_eq!(ram.bank(0)[0xfe..], [0x01, 0x02]);
+    }
+}
@@ -0,0 +1,67 @@
+use crate::virtual_memory::MemoryMappedPeripheral;
+use alloc::vec::Vec;
+
+/// SB and SC with nothing plugged into the link port: bytes sent with the internal clock are
+/// kept in `output` and 0xFF is shifted in. Transfers waiting for an external clock never end.
+#[derive(Default)]
+pub struct Serial {
+    sb: u8,
+    sc: u8,
+    /// M-cycles left in the current transfer.
+    remaining: u16,
+    output: Vec<u8>,
+}
+
+impl Serial {
+    /// 8 bits at 8192 Hz.
+    const TRANSFER_CYCLES: u16 = 8 * 128;
+
+    /// Advances one M-cycle and returns true when the serial interrupt is requested.
+    pub fn tick(&mut self) -> bool {
+        if self.remaining == 0 {
+            return false;
+        }
+
+        self.remaining -= 1;
+        if self.remaining != 0 {
+            return false;
+        }
+
+        self.output.push(self.sb);
+        self.sb = 0xff;
+        self.sc &= 0x7f;
+
+        true
+    }
+
+    /// Bytes sent since the last call. Test ROMs print their results this way.
+    pub fn take_output(&mut self) -> Vec<u8> {
+        core::mem::take(&mut self.output)
+    }
+}
+
+/// Addresses are the offsets of the registers from 0xFF00.
+impl MemoryMappedPeripheral for Serial {
+    fn write(&mut self, address: u16, data: u8) {
+        match address {
+            0x01 => self.sb = data,
+            0x02 => {
+                self.sc = data;
+                self.remaining = if data & 0x81 == 0x81 {
+                    Serial::TRANSFER_CYCLES
+                } else {
+                    0
+                };
+            }
+            _ => {}
+        }
+    }
+
+    fn read(&self, address: u16) -> u8 {
+        match address {
+            0x01 => self.sb,
+            0x02 => self.sc,
+            _ => 0xff,
+        }
+    }
+}
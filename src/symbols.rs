@@ -0,0 +1,62 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Symbol {
+    pub bank: usize,
+    pub address: u16,
+    pub name: String,
+}
+
+/// Symbols from a `.sym` file, as written by RGBDS and understood by most debuggers: one
+/// `BB:AAAA Name` entry per line, `;` starting a comment. Lines in any other format are skipped.
+#[derive(Debug, Clone, Default)]
+pub struct SymbolTable {
+    symbols: Vec<Symbol>,
+}
+
+impl SymbolTable {
+    pub fn parse(input: &str) -> Self {
+        let mut symbols: Vec<Symbol> = input.lines().filter_map(SymbolTable::parse_line).collect();
+        symbols.sort_by_key(|symbol| (symbol.bank, symbol.address));
+
+        Self { symbols }
+    }
+
+    fn parse_line(line: &str) -> Option<Symbol> {
+        let line = line.split(';').next()?;
+        let mut fields = line.split_whitespace();
+        let (bank, address) = fields.next()?.split_once(':')?;
+        let name = fields.next()?;
+
+        Some(Symbol {
+            bank: usize::from_str_radix(bank, 16).ok()?,
+            address: u16::from_str_radix(address, 16).ok()?,
+            name: String::from(name),
+        })
+    }
+
+    pub fn symbols(&self) -> &[Symbol] {
+        &self.symbols
+    }
+
+    /// Closest symbol at or before `address` in `bank`, with the distance from it.
+    pub fn lookup(&self, bank: usize, address: u16) -> Option<(&Symbol, u16)> {
+        let end = self
+            .symbols
+            .partition_point(|symbol| (symbol.bank, symbol.address) <= (bank, address));
+        let symbol = self.symbols[..end].last()?;
+
+        (symbol.bank == bank).then(|| (symbol, address - symbol.address))
+    }
+
+    /// `Name` or `Name+offset` for `address`, the way debuggers display it.
+    pub fn describe(&self, bank: usize, address: u16) -> Option<String> {
+        let (symbol, offset) = self.lookup(bank, address)?;
+
+        Some(match offset {
+            0 => symbol.name.clone(),
+            offset => alloc::format!("{}+{:#x}", symbol.name, offset),
+        })
+    }
+}
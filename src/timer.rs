@@ -0,0 +1,105 @@
+use crate::virtual_memory::MemoryMappedPeripheral;
+use alloc::vec::Vec;
+
+/// DIV, TIMA, TMA and TAC. DIV is the upper byte of a 16-bit counter running at the CPU clock,
+/// and TIMA is incremented on the falling edges of the counter bit selected by TAC.
+#[derive(Default)]
+pub struct Timer {
+    counter: u16,
+    tima: u8,
+    tma: u8,
+    tac: u8,
+    /// TIMA overflowed during the last M-cycle: it reads 0 until it is reloaded from TMA.
+    reloading: bool,
+}
+
+impl Timer {
+    /// Appends the whole timer state, including the bits of the counter below DIV.
+    pub fn hash_state(&self, state: &mut Vec<u8>) {
+        state.extend_from_slice(&self.counter.to_le_bytes());
+        state.extend_from_slice(&[self.tima, self.tma, self.tac, self.reloading as u8]);
+    }
+
+    fn signal(&self) -> bool {
+        let bit = match self.tac & 0x03 {
+            0x00 => 9,
+            0x01 => 3,
+            0x02 => 5,
+            _ => 7,
+        };
+
+        self.tac & 0x04 != 0 && self.counter & (1 << bit) != 0
+    }
+
+    /// The counter is also reset by `STOP`.
+    pub fn reset_counter(&mut self) {
+        let signal = self.signal();
+        self.counter = 0;
+        self.update(signal);
+    }
+
+    fn update(&mut self, old_signal: bool) {
+        if !old_signal || self.signal() {
+            return;
+        }
+
+        let (tima, overflow) = self.tima.overflowing_add(1);
+        self.tima = tima;
+        self.reloading = overflow;
+    }
+
+    /// Advances one M-cycle and returns true when the timer interrupt is requested.
+    pub fn tick(&mut self) -> bool {
+        let interrupt = self.reloading;
+        if self.reloading {
+            self.tima = self.tma;
+            self.reloading = false;
+        }
+
+        let signal = self.signal();
+        self.counter = self.counter.wrapping_add(4);
+        self.update(signal);
+
+        interrupt
+    }
+
+    /// Sets DIV, with the lower bits of the counter cleared.
+    pub fn set_div(&mut self, div: u8) {
+        self.counter = (div as u16) << 8;
+    }
+
+    pub fn div(&self) -> u8 {
+        (self.counter >> 8) as u8
+    }
+}
+
+/// Addresses are the offsets of the registers from 0xFF00.
+impl MemoryMappedPeripheral for Timer {
+    fn write(&mut self, address: u16, data: u8) {
+        match address {
+            0x04 => self.reset_counter(),
+            0x05 => {
+                // Writing during the overflow cycle cancels the reload.
+                self.tima = data;
+                self.reloading = false;
+            }
+            0x06 => self.tma = data,
+            0x07 => {
+                let signal = self.signal();
+                self.tac = data & 0x07;
+                self.update(signal);
+            }
+            _ => {}
+        }
+    }
+
+    fn read(&self, address: u16) -> u8 {
+        match address {
+            0x04 => self.div(),
+            0x05 => self.tima,
+            0x06 => self.tma,
+            0x07 => self.tac,
+            _ => 0xff,
+        }
+    }
+}
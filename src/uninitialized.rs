@@ -0,0 +1,206 @@
+use crate::symbols::SymbolTable;
+use crate::virtual_memory::MemoryDomain;
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UninitializedRead {
+    pub address: u16,
+    pub bank: usize,
+    /// Address of the instruction that did the read.
+    pub pc: u16,
+    /// Symbol covering `address`, when a symbol table is loaded.
+    pub symbol: Option<String>,
+}
+
+/// One bit per byte, grown on demand so banks never written take no space.
+#[derive(Default)]
+struct WrittenBytes {
+    bits: Vec<u64>,
+}
+
+impl WrittenBytes {
+    fn mark(&mut self, index: usize) {
+        let word = index / 64;
+        if word >= self.bits.len() {
+            self.bits.resize(word + 1, 0);
+        }
+
+        self.bits[word] |= 1 << (index % 64);
+    }
+
+    fn is_marked(&self, index: usize) -> bool {
+        self.bits
+            .get(index / 64)
+            .is_some_and(|word| word & (1 << (index % 64)) != 0)
+    }
+}
+
+/// Reports reads of WRAM, HRAM and SRAM bytes that were never written since power on, which
+/// only hold garbage on real hardware. It has to be attached before the program starts, since
+/// writes done before are not known.
+pub struct UninitializedReadDetector {
+    /// WRAM, HRAM and SRAM, in that order.
+    written: [WrittenBytes; 3],
+    symbols: Option<SymbolTable>,
+    hook: Box<dyn Fn(UninitializedRead)>,
+}
+
+impl UninitializedReadDetector {
+    const WRAM_BANK_SIZE: usize = 0x1000;
+    const SRAM_BANK_SIZE: usize = 0x2000;
+
+    pub fn new(hook: Box<dyn Fn(UninitializedRead)>) -> Self {
+        Self {
+            written: Default::default(),
+            symbols: None,
+            hook,
+        }
+    }
+
+    pub fn with_symbols(mut self, symbols: SymbolTable) -> Self {
+        self.symbols = Some(symbols);
+        self
+    }
+
+    /// Tracked area, byte index in it and bank of `offset` in `domain`.
+    fn locate(domain: MemoryDomain, offset: usize) -> Option<(usize, usize, usize)> {
+        match domain {
+            MemoryDomain::Wram(bank) => Some((
+                0,
+                bank * UninitializedReadDetector::WRAM_BANK_SIZE + offset,
+                bank,
+            )),
+            MemoryDomain::Hram => Some((1, offset, 0)),
+            MemoryDomain::Sram(bank) => Some((
+                2,
+                bank * UninitializedReadDetector::SRAM_BANK_SIZE + offset,
+                bank,
+            )),
+            _ => None,
+        }
+    }
+
+    pub fn write(&mut self, domain: MemoryDomain, offset: usize) {
+        if let Some((area, index, _)) = UninitializedReadDetector::locate(domain, offset) {
+            self.written[area].mark(index);
+        }
+    }
+
+    pub fn read(&self, domain: MemoryDomain, offset: usize, address: u16, pc: u16) {
+        let Some((area, index, bank)) = UninitializedReadDetector::locate(domain, offset) else {
+            return;
+        };
+
+        if self.written[area].is_marked(index) {
+            return;
+        }
+
+        // Symbols are defined at the WRAM address, not at its echo.
+        let symbol_address = match address {
+            0xe000..=0xfdff => address - 0x2000,
+            _ => address,
+        };
+
+        (self.hook)(UninitializedRead {
+            address,
+            bank,
+            pc,
+            symbol: self
+                .symbols
+                .as_ref()
+                .and_then(|symbols| symbols.describe(bank, symbol_address)),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::rc::Rc;
+    use alloc::vec;
+    use core::cell::RefCell;
+
+    fn detector() -> (
+        UninitializedReadDetector,
+        Rc<RefCell<Vec<UninitializedRead>>>,
+    ) {
+        let reads = Rc::new(RefCell::new(Vec::new()));
+        let log = reads.clone();
+        let symbols = SymbolTable::parse("00:c000 wVariables\n03:d100 wBank3\n00:ff80 hCounter\n");
+        let detector =
+            UninitializedReadDetector::new(Box::new(move |read| log.borrow_mut().push(read)))
+                .with_symbols(symbols);
+
+        (detector, reads)
+    }
+
+    #[test]
+    fn only_unwritten_bytes_are_reported() {
+        let (mut detector, reads) = detector();
+        detector.write(MemoryDomain::Wram(0), 0x0010);
+        detector.write(MemoryDomain::Hram, 0x00);
+        detector.write(MemoryDomain::Sram(2), 0x1fff);
+
+        detector.read(MemoryDomain::Wram(0), 0x0010, 0xc010, 0x0150);
+        detector.read(MemoryDomain::Hram, 0x00, 0xff80, 0x0150);
+        detector.read(MemoryDomain::Sram(2), 0x1fff, 0xbfff, 0x0150);
+        detector.read(MemoryDomain::Vram(0), 0x0000, 0x8000, 0x0150);
+        assert!(reads.borrow().is_empty());
+
+        detector.read(MemoryDomain::Wram(0), 0x0011, 0xc011, 0x0150);
+        detector.read(MemoryDomain::Hram, 0x01, 0xff81, 0x0151);
+        detector.read(MemoryDomain::Sram(1), 0x1fff, 0xbfff, 0x0152);
+        assert_eq!(
+            *reads.borrow(),
+            vec![
+                UninitializedRead {
+                    address: 0xc011,
+                    bank: 0,
+                    pc: 0x0150,
+                    symbol: Some("wVariables+0x11".into()),
+                },
+                UninitializedRead {
+                    address: 0xff81,
+                    bank: 0,
+                    pc: 0x0151,
+                    symbol: Some("hCounter+0x1".into()),
+                },
+                UninitializedRead {
+                    address: 0xbfff,
+                    bank: 1,
+                    pc: 0x0152,
+                    symbol: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn banks_are_tracked_separately() {
+        let (mut detector, reads) = detector();
+        detector.write(MemoryDomain::Wram(2), 0x0100);
+
+        detector.read(MemoryDomain::Wram(2), 0x0100, 0xd100, 0x0000);
+        detector.read(MemoryDomain::Wram(3), 0x0100, 0xd100, 0x0000);
+
+        let reads = reads.borrow();
+        assert_eq!(reads.len(), 1);
+        assert_eq!(reads[0].bank, 3);
+        assert_eq!(reads[0].symbol.as_deref(), Some("wBank3"));
+    }
+
+    #[test]
+    fn echo_reads_use_the_wram_symbols() {
+        let (detector, reads) = detector();
+
+        detector.read(MemoryDomain::Wram(0), 0x0004, 0xe004, 0x0000);
+        detector.read(MemoryDomain::Wram(3), 0x0102, 0xf102, 0x0000);
+
+        let reads = reads.borrow();
+        assert_eq!(reads[0].address, 0xe004);
+        assert_eq!(reads[0].symbol.as_deref(), Some("wVariables+0x4"));
+        assert_eq!(reads[1].symbol.as_deref(), Some("wBank3+0x2"));
+    }
+}
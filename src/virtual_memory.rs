@@ -1,6 +1,25 @@
-use crate::cartridge::{Cartridge, Rom};
+use crate::audio::Apu;
+use crate::boot_rom::{self, BootRomError};
+use crate::cartridge::{Cartridge, CgbSupport, Rom};
+use crate::colorization::CompatibilityPalettes;
+use crate::dma::{OamDma, VramDma};
+use crate::graphics::{Ppu, PpuMode};
+use crate::io_registers::IoRegisters;
 use crate::joypad::JoyPad;
+use crate::mbc::Mbc;
+use crate::model::Model;
+use crate::oam_bug::{self, OamBugAccess};
+use crate::page_table::{Page, PageTable, Region};
+use crate::peripheral_registry::{PeripheralError, PeripheralId, PeripheralRegistry};
+use crate::power_on::{PowerOnRegion, PowerOnSettings};
 use crate::ram::Ram;
+use crate::serial_data::Serial;
+use crate::timer::Timer;
+use crate::uninitialized::UninitializedReadDetector;
+use crate::watchpoint::{Access, WatchHit, Watchpoint, WatchpointId, Watchpoints};
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::ops::RangeInclusive;
 
 pub trait MemoryMappedPeripheral {
     fn write(&mut self, address: u16, data: u8);
@@ -12,145 +31,1756 @@ pub trait WriteBlock {
 }
 
 pub trait ReadBlock {
-    fn read_block<const S: usize>(&self, base_address: u16) -> [u8; S];
+    fn read_block(&self, base_address: u16, block: &mut [u8]);
+}
+
+/// Memory areas as debuggers and tools see them: each one is addressed from its own start,
+/// independently of the CPU memory map and of the currently selected banks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryDomain {
+    BootRom,
+    /// The whole cartridge ROM, all banks back to back.
+    Rom,
+    Vram(usize),
+    Wram(usize),
+    Sram(usize),
+    Oam,
+    Hram,
+    /// I/O registers at 0xFF00-0xFF7F.
+    Io,
+}
+
+/// Accesses that real hardware tolerates but usually point to a bug in the running program.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BusDiagnostic {
+    ProhibitedRead(u16),
+    ProhibitedWrite(u16, u8),
+    UnmappedIoRead(u16),
+    UnmappedIoWrite(u16, u8),
+}
+
+/// The CPU and the DMA units share the external bus (cartridge and WRAM) and the video bus (VRAM).
+/// On the CGB, WRAM has a bus of its own.
+#[derive(PartialEq, Eq)]
+enum MemoryBus {
+    External,
+    Video,
+    Wram,
+}
+
+impl MemoryBus {
+    fn of(address: u16, model: Model) -> Option<Self> {
+        match address {
+            0xc000..=0xfdff if model.is_cgb() => Some(MemoryBus::Wram),
+            0x0000..=0x7fff | 0xa000..=0xfdff => Some(MemoryBus::External),
+            0x8000..=0x9fff => Some(MemoryBus::Video),
+            _ => None,
+        }
+    }
 }
 
 pub struct VirtualMemory {
+    model: Model,
+    power_on: PowerOnSettings,
+    page_table: PageTable,
+    mbc: Mbc,
     boot_rom: Rom<0x100>,
     rom_bank0: Rom<0x4000>,
     rom_bank1: Rom<0x4000>,
     vram: Ram<0x2000>,
     external_ram: Option<Ram<0x2000>>,
+    /// Palettes the CGB boot ROM picks from the title when no buttons are held.
+    compatibility_palettes: CompatibilityPalettes,
     wram0: Ram<0x1000>,
     wram1: Ram<0x1000>,
+    svbk: u8,
+    /// KEY1: speed switch requested, then performed by the next `STOP`.
+    speed_switch_armed: bool,
+    double_speed: bool,
+    /// In double speed, whether the PPU and APU sit out the current M-cycle.
+    odd_cycle: bool,
+    /// M-cycles the CPU has to wait for VRAM DMA or a speed switch.
+    cpu_stall: u32,
     oam: Ram<0xA0>,
+    ppu: Ppu,
+    frame_ready: bool,
+    apu: Apu,
+    timer: Timer,
+    serial: Serial,
     joypad: JoyPad,
+    /// Input polls of the last frame, `None` until the first one ends.
+    frame_polls: Option<u32>,
+    lag_frames: u64,
+    io_registers: IoRegisters,
     boot_rom_en: u8,
-    oam_dma: u8,
+    oam_dma: OamDma,
+    vram_dma: VramDma,
     hram: Ram<0x7F>,
-    ie: bool,
+    ie: u8,
+    peripherals: PeripheralRegistry,
+    diagnostics_hook: Option<Box<dyn Fn(BusDiagnostic)>>,
+    watchpoints: Watchpoints,
+    pc: u16,
+    uninitialized_reads: Option<UninitializedReadDetector>,
 }
 
 impl VirtualMemory {
-    const BOOT_ROM: &'static [u8] = include_bytes!("DMG_ROM.bin");
+    pub const VBLANK_INTERRUPT: u8 = 0;
+    pub const STAT_INTERRUPT: u8 = 1;
+    pub const TIMER_INTERRUPT: u8 = 2;
+    pub const SERIAL_INTERRUPT: u8 = 3;
+    pub const JOYPAD_INTERRUPT: u8 = 4;
+    /// M-cycles the CPU stays stopped during a speed switch.
+    const SPEED_SWITCH_CYCLES: u32 = 2050;
+
+    /// Uses the model the cartridge header asks for.
+    pub fn new(cartridge: Cartridge) -> Self {
+        let model = Model::for_header(cartridge.header());
+        VirtualMemory::with_model(cartridge, model)
+    }
+
+    pub fn with_model(cartridge: Cartridge, model: Model) -> Self {
+        VirtualMemory::with_power_on(cartridge, model, PowerOnSettings::default())
+    }
 
-    pub fn new(mut cartridge: Cartridge) -> Self {
-        Self {
-            boot_rom: Rom::new(VirtualMemory::BOOT_ROM.to_vec(), 1),
+    pub fn with_power_on(
+        mut cartridge: Cartridge,
+        model: Model,
+        power_on: PowerOnSettings,
+    ) -> Self {
+        let mut virtual_memory = Self {
+            model,
+            power_on,
+            page_table: PageTable::default(),
+            mbc: Mbc::new(cartridge.mapper(), cartridge.rom_banks()),
+            boot_rom: Rom::new(boot_rom::DMG_BOOT_ROM.to_vec(), 1),
             rom_bank0: cartridge.take_bank0(),
             rom_bank1: cartridge.take_bank1(),
-            vram: Ram::default(),
+            vram: Ram::new(if model.is_cgb() { 2 } else { 1 }),
             external_ram: cartridge.take_ram(),
+            compatibility_palettes: CompatibilityPalettes::for_header(cartridge.header()),
             wram0: Ram::default(),
-            wram1: Ram::default(),
+            wram1: Ram::new(if model.is_cgb() { 7 } else { 1 }),
+            svbk: 0x01,
+            speed_switch_armed: false,
+            double_speed: false,
+            odd_cycle: false,
+            cpu_stall: 0,
             oam: Ram::default(),
+            ppu: Ppu::new(model),
+            frame_ready: false,
+            apu: Apu::new(model, Apu::DEFAULT_SAMPLE_RATE),
+            timer: Timer::default(),
+            serial: Serial::default(),
             joypad: JoyPad::default(),
+            frame_polls: None,
+            lag_frames: 0,
+            io_registers: IoRegisters::new(model),
             boot_rom_en: 0x01,
-            oam_dma: 0x00,
+            oam_dma: OamDma::default(),
+            vram_dma: VramDma::default(),
             hram: Ram::default(),
-            ie: true,
+            ie: 0x00,
+            peripherals: PeripheralRegistry::default(),
+            diagnostics_hook: None,
+            watchpoints: Watchpoints::default(),
+            pc: 0x0000,
+            uninitialized_reads: None,
+        };
+
+        virtual_memory.fill_power_on();
+        virtual_memory.rebuild_page_table();
+        virtual_memory
+    }
+
+    fn fill_power_on(&mut self) {
+        let power_on = self.power_on;
+
+        self.vram.fill(power_on.bytes(PowerOnRegion::Vram));
+        if let Some(external_ram) = self.external_ram.as_mut() {
+            external_ram.fill(power_on.bytes(PowerOnRegion::Sram));
+        }
+
+        // WRAM is a single chip, so both halves come from the same stream.
+        let mut wram = power_on.bytes(PowerOnRegion::Wram);
+        self.wram0.fill(&mut wram);
+        self.wram1.fill(&mut wram);
+
+        self.oam.fill(power_on.bytes(PowerOnRegion::Oam));
+        self.hram.fill(power_on.bytes(PowerOnRegion::Hram));
+    }
+
+    /// Replaces the boot ROM and maps it over the cartridge, as at power on.
+    pub fn set_boot_rom(&mut self, boot_rom: Vec<u8>) -> Result<(), BootRomError> {
+        boot_rom::validate(self.model, &boot_rom)?;
+        self.map_boot_rom(boot_rom);
+
+        Ok(())
+    }
+
+    /// Maps a boot ROM whose size was already validated for the model.
+    pub(crate) fn map_boot_rom(&mut self, boot_rom: Vec<u8>) {
+        let banks = boot_rom.len() / 0x100;
+        self.boot_rom = Rom::new(boot_rom, banks);
+        self.boot_rom_en = 0x00;
+        self.rebuild_page_table();
+    }
+
+    /// Leaves the I/O registers, DIV and VRAM the way the boot ROM does, to start the cartridge
+    /// at 0x0100 without running it.
+    pub fn skip_boot(&mut self) {
+        self.boot_rom_en = 0x01;
+
+        for (address, data) in boot_rom::post_boot_io(self.model) {
+            self.write_io_regs(address, data);
+        }
+        self.timer.set_div(self.model.post_boot_div());
+
+        let mut logo = [0x00; 48];
+        logo.copy_from_slice(&self.rom_bank0.bank(0)[0x0104..0x0134]);
+
+        if self.model.is_cgb() {
+            self.skip_cgb_boot();
+        }
+
+        self.vram.fill(|| 0x00);
+        let vram = self.vram.bank_mut(0);
+        let tiles = boot_rom::logo_tiles(&logo);
+        vram[0x0010..0x0010 + tiles.len()].copy_from_slice(&tiles);
+        for (address, tile) in boot_rom::logo_map() {
+            vram[(address - 0x8000) as usize] = tile;
+        }
+
+        self.rebuild_page_table();
+    }
+
+    /// The CGB boot ROM picks CGB mode or DMG compatibility mode from the header, and sets up
+    /// the palettes of the latter from the title or the buttons held down.
+    fn skip_cgb_boot(&mut self) {
+        let cgb_flag = self.rom_bank0.bank(0)[0x0143];
+        if CgbSupport::from(cgb_flag) != CgbSupport::None {
+            return;
+        }
+
+        self.ppu.set_cgb_mode(false);
+        self.ppu.write(0x6c, 0x01);
+        let palettes = CompatibilityPalettes::for_buttons(self.joypad.pressed())
+            .unwrap_or(self.compatibility_palettes);
+        self.ppu.set_bg_palette(0, palettes.bg);
+        self.ppu.set_obj_palette(0, palettes.obj0);
+        self.ppu.set_obj_palette(1, palettes.obj1);
+    }
+
+    /// Boot ROM overlay at `address`, 0x0000-0x00FF and, with a CGB boot ROM, 0x0200-0x08FF.
+    fn boot_rom_mapped(&self, address: u16) -> bool {
+        self.boot_rom_en == 0x00
+            && (address < 0x0100
+                || (0x0200..0x0900).contains(&address) && self.boot_rom.banks() > 1)
+    }
+
+    pub fn model(&self) -> Model {
+        self.model
+    }
+
+    /// Settings the memory was filled with, to be recorded with movies.
+    pub fn power_on_settings(&self) -> PowerOnSettings {
+        self.power_on
+    }
+
+    pub fn peripherals(&self) -> &PeripheralRegistry {
+        &self.peripherals
+    }
+
+    /// Registered peripherals take priority over the standard memory map in their range.
+    pub fn register_peripheral(
+        &mut self,
+        range: RangeInclusive<u16>,
+        peripheral: Box<dyn MemoryMappedPeripheral>,
+    ) -> Result<PeripheralId, PeripheralError> {
+        let id = self.peripherals.register(range, peripheral)?;
+        self.rebuild_page_table();
+
+        Ok(id)
+    }
+
+    pub fn unregister_peripheral(
+        &mut self,
+        id: PeripheralId,
+    ) -> Option<Box<dyn MemoryMappedPeripheral>> {
+        let peripheral = self.peripherals.unregister(id);
+        self.rebuild_page_table();
+
+        peripheral
+    }
+
+    pub fn peripheral_mut(&mut self, id: PeripheralId) -> Option<&mut dyn MemoryMappedPeripheral> {
+        self.peripherals.get_mut(id)
+    }
+
+    /// Points every plain memory page straight at its current bank, and HRAM at itself.
+    /// Everything else goes through the address decoding, and so does the rest of the map while
+    /// OAM DMA causes bus conflicts, since HRAM is the only memory it leaves alone.
+    fn rebuild_page_table(&mut self) {
+        self.page_table = PageTable::default();
+
+        if !self.peripherals.claims(0xff80) && self.uninitialized_reads.is_none() {
+            let page = |access| {
+                if self.watchpoints.watches(access, 0xff80) {
+                    Page::Handler
+                } else {
+                    Page::HighRam
+                }
+            };
+            self.page_table
+                .map(0xff, page(Access::Read), page(Access::Write));
+        }
+
+        if self.oam_dma.is_active() {
+            return;
+        }
+
+        for page in 0x00..=0xfd {
+            let address = (page as u16) << 8;
+
+            if self.peripherals.claims(address) {
+                continue;
+            }
+
+            let (region, bank, base) = match address {
+                _ if self.boot_rom_mapped(address) => (Region::BootRom, page as usize, address),
+                0x0000..=0x3fff => (Region::RomBank0, self.rom_bank0.actual_bank(), 0x0000),
+                // MBC5 can map bank 0 at 0x4000, which the switchable area doesn't hold.
+                0x4000..=0x7fff if self.mbc.rom_bank() == 0 => (Region::RomBank0, 0, 0x4000),
+                0x4000..=0x7fff => (Region::RomBankN, self.rom_bank1.actual_bank(), 0x4000),
+                // The PPU blocks VRAM during mode 3.
+                0x8000..=0x9fff => continue,
+                0xa000..=0xbfff => match self.external_ram() {
+                    Some(external_ram) => (Region::ExternalRam, external_ram.actual_bank(), 0xa000),
+                    None => continue,
+                },
+                0xc000..=0xcfff => (Region::Wram0, 0, 0xc000),
+                0xd000..=0xdfff => (Region::WramN, self.wram1.actual_bank(), 0xd000),
+                0xe000..=0xefff => (Region::Wram0, 0, 0xe000),
+                _ => (Region::WramN, self.wram1.actual_bank(), 0xf000),
+            };
+
+            // The detector needs to see every RAM access.
+            if self.uninitialized_reads.is_some()
+                && matches!(region, Region::ExternalRam | Region::Wram0 | Region::WramN)
+            {
+                continue;
+            }
+
+            let direct = Page::Direct {
+                region,
+                bank: bank as u16,
+                offset: address - base,
+            };
+            let writable = !matches!(
+                region,
+                Region::BootRom | Region::RomBank0 | Region::RomBankN
+            );
+
+            let read = if self.watchpoints.watches(Access::Read, address) {
+                Page::Handler
+            } else {
+                direct
+            };
+            let write = if writable && !self.watchpoints.watches(Access::Write, address) {
+                direct
+            } else {
+                Page::Handler
+            };
+
+            self.page_table.map(page, read, write);
+        }
+    }
+
+    fn region(&self, region: Region, bank: u16) -> &[u8] {
+        let bank = bank as usize;
+
+        match region {
+            Region::BootRom => self.boot_rom.bank(bank),
+            Region::RomBank0 => self.rom_bank0.bank(bank),
+            Region::RomBankN => self.rom_bank1.bank(bank),
+            Region::ExternalRam => self.external_ram.as_ref().map_or(&[], |ram| ram.bank(bank)),
+            Region::Wram0 => self.wram0.bank(bank),
+            Region::WramN => self.wram1.bank(bank),
+        }
+    }
+
+    fn region_mut(&mut self, region: Region, bank: u16) -> &mut [u8] {
+        let bank = bank as usize;
+
+        match region {
+            Region::ExternalRam => self
+                .external_ram
+                .as_mut()
+                .map_or(&mut [], |ram| ram.bank_mut(bank)),
+            Region::Wram0 => self.wram0.bank_mut(bank),
+            Region::WramN => self.wram1.bank_mut(bank),
+            Region::BootRom | Region::RomBank0 | Region::RomBankN => &mut [],
+        }
+    }
+
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) -> WatchpointId {
+        let id = self.watchpoints.add(watchpoint);
+        self.rebuild_page_table();
+
+        id
+    }
+
+    pub fn remove_watchpoint(&mut self, id: WatchpointId) -> bool {
+        let removed = self.watchpoints.remove(id);
+        self.rebuild_page_table();
+
+        removed
+    }
+
+    pub fn clear_watchpoints(&mut self) {
+        self.watchpoints.clear();
+        self.rebuild_page_table();
+    }
+
+    /// Hit of a pausing watchpoint since the last call. The emulation loop stops at the end of
+    /// the instruction when there is one.
+    pub fn take_watch_hit(&self) -> Option<WatchHit> {
+        self.watchpoints.take_pause()
+    }
+
+    /// Address of the instruction being executed, reported with watchpoint hits.
+    pub fn set_pc(&mut self, pc: u16) {
+        self.pc = pc;
+    }
+
+    /// Opcode fetch by the CPU: it records the PC and checks execute watchpoints instead of read
+    /// ones.
+    pub fn fetch_opcode(&mut self, address: u16) -> u8 {
+        self.pc = address;
+        let data = match self.page_table.read_page(address) {
+            Page::Direct {
+                region,
+                bank,
+                offset,
+            } => self.region(region, bank)[(offset | (address & 0xff)) as usize],
+            Page::HighRam if (0xff80..=0xfffe).contains(&address) => {
+                self.hram.read(address - 0xff80)
+            }
+            _ => self.read_unwatched(address),
+        };
+        self.check_watchpoints(Access::Execute, address, data);
+
+        data
+    }
+
+    fn check_watchpoints(&self, access: Access, address: u16, data: u8) {
+        if !self.watchpoints.watches(access, address) {
+            return;
+        }
+
+        let bank = self
+            .domain_at(address)
+            .and_then(|(domain, offset)| match domain {
+                MemoryDomain::Rom => Some(offset / VirtualMemory::ROM_BANK_SIZE),
+                MemoryDomain::Vram(bank) | MemoryDomain::Wram(bank) | MemoryDomain::Sram(bank) => {
+                    Some(bank)
+                }
+                _ => None,
+            });
+
+        self.watchpoints.check(access, address, bank, data, self.pc);
+    }
+
+    /// Opt-in, since it takes RAM accesses off the page table fast path.
+    pub fn set_uninitialized_read_detector(&mut self, detector: Option<UninitializedReadDetector>) {
+        self.uninitialized_reads = detector;
+        self.rebuild_page_table();
+    }
+
+    fn track_write(&mut self, address: u16) {
+        if self.uninitialized_reads.is_none() {
+            return;
+        }
+
+        if let Some((domain, offset)) = self.domain_at(address) {
+            if let Some(detector) = self.uninitialized_reads.as_mut() {
+                detector.write(domain, offset);
+            }
+        }
+    }
+
+    fn track_read(&self, address: u16) {
+        let Some(detector) = self.uninitialized_reads.as_ref() else {
+            return;
+        };
+
+        if let Some((domain, offset)) = self.domain_at(address) {
+            detector.read(domain, offset, address, self.pc);
+        }
+    }
+
+    /// Strict mode: prohibited and unmapped accesses are reported to `hook` before being handled
+    /// the way the hardware does.
+    pub fn set_diagnostics_hook(&mut self, hook: Option<Box<dyn Fn(BusDiagnostic)>>) {
+        self.diagnostics_hook = hook;
+    }
+
+    fn report(&self, diagnostic: BusDiagnostic) {
+        if let Some(hook) = self.diagnostics_hook.as_ref() {
+            hook(diagnostic);
+        }
+    }
+
+    /// OAM, and the prohibited area after it, can't be reached during OAM scan and drawing.
+    fn oam_blocked(&self) -> bool {
+        self.ppu.lcd_enabled() && matches!(self.ppu.mode(), PpuMode::OamScan | PpuMode::Drawing)
+    }
+
+    /// The PPU owns VRAM during drawing and OAM during OAM scan and drawing: CPU reads return
+    /// 0xFF and writes are dropped.
+    fn ppu_conflict(&self, address: u16) -> bool {
+        match address {
+            0x8000..=0x9fff => self.ppu.lcd_enabled() && self.ppu.mode() == PpuMode::Drawing,
+            0xfe00..=0xfe9f => self.oam_blocked(),
+            _ => false,
+        }
+    }
+
+    fn read_prohibited_area(&self, address: u16) -> u8 {
+        self.report(BusDiagnostic::ProhibitedRead(address));
+        self.prohibited_area_value(address)
+    }
+
+    fn prohibited_area_value(&self, address: u16) -> u8 {
+        if self.oam_blocked() {
+            return 0xFF;
         }
+
+        if self.model.is_cgb() {
+            // CGB revision E repeats the high nibble of the lower address byte.
+            ((address as u8) >> 4) * 0x11
+        } else {
+            0x00
+        }
+    }
+
+    /// DMG OAM corruption bug. The CPU reports accesses whose address is on the bus, including
+    /// the 16-bit increments and decrements that don't access memory, and OAM gets corrupted
+    /// when they hit 0xFE00-0xFEFF during OAM scan. It isn't applied by `read` and `write`
+    /// because combined accesses have a pattern of their own.
+    pub fn oam_bug(&mut self, address: u16, access: OamBugAccess) {
+        if self.model.is_cgb() || !(0xfe00..=0xfeff).contains(&address) {
+            return;
+        }
+
+        let Some(row) = self.ppu.oam_scan_row() else {
+            return;
+        };
+
+        oam_bug::corrupt(self.oam.bank_mut(0), row, access);
     }
 
     pub fn joypad_ref(&self) -> &JoyPad {
         &self.joypad
     }
 
+    /// Advances the peripherals on the bus by one M-cycle.
+    pub fn tick(&mut self) {
+        let dma_was_active = self.oam_dma.is_active();
+        let dma_transfer = self.oam_dma.tick();
+
+        if dma_was_active != self.oam_dma.is_active() {
+            self.rebuild_page_table();
+        }
+
+        if let Some((source, index)) = dma_transfer {
+            // Sources from 0xE000 up read WRAM through its echo, even above 0xFE00.
+            let source = if source >= 0xe000 {
+                source - 0x2000
+            } else {
+                source
+            };
+
+            let data = self.read_bus(source);
+            self.oam.write(index, data);
+            self.oam_dma.set_last_byte(data);
+        }
+
+        if self.timer.tick() {
+            self.request_interrupt(VirtualMemory::TIMER_INTERRUPT);
+        }
+
+        if self.serial.tick() {
+            self.request_interrupt(VirtualMemory::SERIAL_INTERRUPT);
+        }
+
+        // Double speed only applies to the CPU, the timer, serial and OAM DMA.
+        if self.double_speed {
+            self.odd_cycle = !self.odd_cycle;
+        }
+        if !self.odd_cycle {
+            self.tick_video_and_audio();
+        }
+
+        if self.joypad.check_interrupt() {
+            self.request_interrupt(VirtualMemory::JOYPAD_INTERRUPT);
+        }
+    }
+
+    fn tick_video_and_audio(&mut self) {
+        let vram = [self.vram.bank(0), self.vram.bank(self.vram.banks() - 1)];
+        let events = self.ppu.tick(vram, self.oam.bank(0));
+        if events.stat {
+            self.request_interrupt(VirtualMemory::STAT_INTERRUPT);
+        }
+        if events.hblank && self.vram_dma.hblank_active() {
+            self.copy_vram_dma_block();
+        }
+        if events.vblank {
+            self.request_interrupt(VirtualMemory::VBLANK_INTERRUPT);
+            self.frame_ready = true;
+            self.end_frame();
+        }
+
+        self.apu.tick();
+    }
+
+    /// Copies 16 bytes to the selected VRAM bank, which stops the CPU for 8 M-cycles of normal
+    /// speed.
+    fn copy_vram_dma_block(&mut self) {
+        let (source, destination) = self.vram_dma.next_block();
+
+        for index in 0..VramDma::BLOCK_SIZE {
+            let data = self.read_bus(source.wrapping_add(index));
+            self.vram.write(destination + index, data);
+        }
+
+        self.cpu_stall += if self.double_speed { 16 } else { 8 };
+    }
+
+    /// M-cycles the CPU has to spend stopped before the next instruction.
+    pub fn take_cpu_stall(&mut self) -> u32 {
+        core::mem::take(&mut self.cpu_stall)
+    }
+
+    /// Interrupts both requested and enabled.
+    pub fn pending_interrupts(&self) -> u8 {
+        self.ie & self.io_registers.raw(0x000f) & 0x1f
+    }
+
+    /// Clears the `interrupt` bit of IF when the CPU services it.
+    pub fn acknowledge_interrupt(&mut self, interrupt: u8) {
+        let interrupt_flag = self.io_registers.raw(0x000f);
+        self.io_registers
+            .write(0x000f, interrupt_flag & !(1 << interrupt));
+    }
+
+    /// `STOP` resets the divider. On the CGB it switches speed when KEY1 asked for it, and
+    /// returns true in that case as the CPU carries on instead of stopping.
+    pub fn stop(&mut self) -> bool {
+        self.timer.reset_counter();
+
+        if !self.speed_switch_armed {
+            return false;
+        }
+
+        self.speed_switch_armed = false;
+        self.double_speed = !self.double_speed;
+        self.odd_cycle = false;
+        self.cpu_stall += VirtualMemory::SPEED_SWITCH_CYCLES;
+        true
+    }
+
+    pub fn double_speed(&self) -> bool {
+        self.double_speed
+    }
+
+    /// Whether the PPU entered VBlank since the last call.
+    pub fn take_frame_ready(&mut self) -> bool {
+        core::mem::take(&mut self.frame_ready)
+    }
+
+    pub fn ppu(&self) -> &Ppu {
+        &self.ppu
+    }
+
+    pub fn ppu_mut(&mut self) -> &mut Ppu {
+        &mut self.ppu
+    }
+
+    pub fn apu_mut(&mut self) -> &mut Apu {
+        &mut self.apu
+    }
+
+    pub fn serial_mut(&mut self) -> &mut Serial {
+        &mut self.serial
+    }
+
+    fn write_mbc(&mut self, address: u16, data: u8) {
+        if !self.mbc.write(address, data) {
+            return;
+        }
+
+        // Bank 0 at 0x4000 is read from `rom_bank0` instead.
+        if let Some(bank) = self.mbc.rom_bank().checked_sub(1) {
+            self.rom_bank1.sel_bank(bank);
+        }
+        if let Some(external_ram) = self.external_ram.as_mut() {
+            external_ram.sel_bank(self.mbc.ram_bank());
+        }
+        self.rebuild_page_table();
+    }
+
+    fn external_ram(&self) -> Option<&Ram<0x2000>> {
+        self.external_ram
+            .as_ref()
+            .filter(|_| self.mbc.ram_enabled())
+    }
+
+    /// Frame boundary, signaled by the PPU when it enters VBlank. Returns whether the frame that
+    /// ended was a lag frame: one where the game never read the joypad.
+    pub fn end_frame(&mut self) -> bool {
+        let polls = self.joypad.take_polls();
+        self.frame_polls = Some(polls);
+
+        let lag = polls == 0;
+        if lag {
+            self.lag_frames += 1;
+        }
+
+        lag
+    }
+
+    /// False until a frame has ended.
+    pub fn was_lag_frame(&self) -> bool {
+        self.frame_polls == Some(0)
+    }
+
+    /// Reads of P1 with a button group selected during the last frame, once one has ended.
+    pub fn input_polls(&self) -> Option<u32> {
+        self.frame_polls
+    }
+
+    pub fn lag_frames(&self) -> u64 {
+        self.lag_frames
+    }
+
+    /// Sets the `interrupt` bit of IF.
+    pub fn request_interrupt(&mut self, interrupt: u8) {
+        let interrupt_flag = self.io_registers.raw(0x000f);
+        self.io_registers
+            .write(0x000f, interrupt_flag | (1 << interrupt));
+    }
+
+    /// While OAM DMA runs, the CPU can't reach OAM nor the bus the transfer reads from. HRAM and
+    /// the I/O registers stay accessible.
+    fn dma_conflict(&self, address: u16) -> bool {
+        let Some(source) = self.oam_dma.source() else {
+            return false;
+        };
+
+        match address {
+            0xfe00..=0xfeff => true,
+            0xff00..=0xffff => false,
+            _ => {
+                MemoryBus::of(address, self.model) == MemoryBus::of(source.min(0xdfff), self.model)
+            }
+        }
+    }
+
     fn write_io_regs(&mut self, address: u16, data: u8) {
         match address {
             0x0000 => self.joypad.write(address, data),
-            0x0001..=0x0002 => todo!("serial transfer write"),
-            0x0004..=0x0007 => todo!("timer and driver write"),
-            0x0010..=0x0026 => todo!("audio write"),
-            0x0030..=0x003F => todo!("wave pattern write"),
-            0x0040..=0x0045 | 0x0047..=0x004B => todo!("graphics write"),
-            0x0046 => {
-                self.oam_dma = data;
-
-                let base_address = (data as u16) << 8;
-                let source = self.read_block::<0xA0>(base_address);
-                self.oam.write_block(0x0000, &source);
+            0x0001..=0x0002 => self.serial.write(address, data),
+            0x0004..=0x0007 => self.timer.write(address, data),
+            0x0010..=0x003f if self.io_registers.is_mapped(address) => {
+                self.apu.write(address, data)
+            }
+            0x0040..=0x004b if address != 0x0046 => self.ppu.write(address, data),
+            0x0046 => self.oam_dma.start(data),
+            // Once unmapped, the boot ROM stays unmapped until the next reset.
+            0x0050 => {
+                let boot_rom_en = self.boot_rom_en | data & 0x01;
+                if boot_rom_en != self.boot_rom_en {
+                    self.boot_rom_en = boot_rom_en;
+                    self.rebuild_page_table();
+                }
             }
-            0x0050 => self.boot_rom_en = data,
-            _ => panic!("IO register address cannot be written"),
+            // KEY0 can only be written by the boot ROM, to enter DMG compatibility mode.
+            0x004c if self.model.is_cgb() && self.boot_rom_en == 0x00 => {
+                self.ppu.set_cgb_mode(data & 0x04 == 0)
+            }
+            0x004d if self.model.is_cgb() => self.speed_switch_armed = data & 0x01 != 0,
+            0x004f if self.model.is_cgb() => {
+                self.vram.sel_bank(data as usize & 0x01);
+                self.rebuild_page_table();
+            }
+            0x0051..=0x0055 if self.model.is_cgb() => {
+                if let Some(blocks) = self.vram_dma.write(address, data) {
+                    for _ in 0..blocks {
+                        self.copy_vram_dma_block();
+                    }
+                }
+            }
+            0x0068..=0x006c if self.model.is_cgb() => self.ppu.write(address, data),
+            0x0070 if self.model.is_cgb() => {
+                // Bank 0 can't be mapped at 0xD000, selecting it maps bank 1 instead.
+                self.svbk = data & 0x07;
+                self.wram1.sel_bank(self.svbk.max(1) as usize - 1);
+                self.rebuild_page_table();
+            }
+            _ if self.io_registers.is_mapped(address) => self.io_registers.write(address, data),
+            _ => self.report(BusDiagnostic::UnmappedIoWrite(0xff00 + address, data)),
         }
     }
 
     fn read_io_regs(&self, address: u16) -> u8 {
         match address {
-            0x0000 => self.joypad.read(address),
-            0x0001..=0x0002 => todo!("serial transfer read"),
-            0x0004..=0x0007 => todo!("timer and driver read"),
-            0x0010..=0x0026 => todo!("audio read"),
-            0x0030..=0x003F => todo!("wave pattern read"),
-            0x0040..=0x0045 | 0x0047..=0x004B => todo!("graphics read"),
-            0x0046 => self.oam_dma,
-            0x0050 => self.boot_rom_en,
-            _ => panic!("IO register address cannot be read"),
+            0x0000 => self.joypad.read(address) | self.io_registers.read_mask(address),
+            0x0001..=0x0002 => self.serial.read(address) | self.io_registers.read_mask(address),
+            0x0004..=0x0007 => self.timer.read(address) | self.io_registers.read_mask(address),
+            0x0010..=0x003f if self.io_registers.is_mapped(address) => {
+                self.apu.read(address) | self.io_registers.read_mask(address)
+            }
+            0x0040..=0x004b if address != 0x0046 => {
+                self.ppu.read(address) | self.io_registers.read_mask(address)
+            }
+            0x0046 => self.oam_dma.register(),
+            0x0050 => self.boot_rom_en | self.io_registers.read_mask(address),
+            0x004f if self.model.is_cgb() => {
+                self.vram.actual_bank() as u8 | self.io_registers.read_mask(address)
+            }
+            0x0055 if self.model.is_cgb() => self.vram_dma.status(),
+            0x0068..=0x006c if self.model.is_cgb() => {
+                self.ppu.read(address) | self.io_registers.read_mask(address)
+            }
+            0x004d if self.model.is_cgb() => {
+                (self.double_speed as u8) << 7
+                    | self.speed_switch_armed as u8
+                    | self.io_registers.read_mask(address)
+            }
+            0x0070 if self.model.is_cgb() => self.svbk | self.io_registers.read_mask(address),
+            _ if self.io_registers.is_mapped(address) => self.io_registers.read(address),
+            _ => {
+                self.report(BusDiagnostic::UnmappedIoRead(0xff00 + address));
+                0xff
+            }
         }
     }
 }
 
-impl MemoryMappedPeripheral for VirtualMemory {
-    fn write(&mut self, address: u16, data: u8) {
-        let boot_rom_en = self.boot_rom_en == 0x00;
+impl VirtualMemory {
+    /// CPU accesses the page table can't serve. Kept out of line so that the fast path of
+    /// `read` and `write` stays small.
+    #[inline(never)]
+    fn write_decoded(&mut self, address: u16, data: u8) {
+        self.check_watchpoints(Access::Write, address, data);
+
+        if self.dma_conflict(address) || self.ppu_conflict(address) {
+            return;
+        }
+
+        self.track_write(address);
+        self.write_bus(address, data)
+    }
+
+    #[inline(never)]
+    fn read_decoded(&self, address: u16) -> u8 {
+        let data = self.read_unwatched(address);
+        self.check_watchpoints(Access::Read, address, data);
+
+        data
+    }
+
+    /// CPU read with the DMA and PPU blocking applied, but no read watchpoint check.
+    fn read_unwatched(&self, address: u16) -> u8 {
+        if self.dma_conflict(address) {
+            match address {
+                0xfe00..=0xfeff => 0xff,
+                _ => self.oam_dma.last_byte(),
+            }
+        } else if self.ppu_conflict(address) {
+            0xff
+        } else {
+            self.track_read(address);
+            self.read_bus(address)
+        }
+    }
+
+    fn write_bus(&mut self, address: u16, data: u8) {
+        if self.peripherals.write(address, data) {
+            return;
+        }
 
         match address {
-            0x0000..=0x00ff if boot_rom_en => self.boot_rom.write(address, data),
-            0x0000..=0x3fff => self.rom_bank0.write(address, data),
-            0x4000..=0x7fff => self.rom_bank1.write(address - 0x4000, data),
+            0x0000..=0x7fff => self.write_mbc(address, data),
             0x8000..=0x9fff => self.vram.write(address - 0x8000, data),
             0xa000..=0xbfff => {
-                if let Some(external_ram) = self.external_ram.as_mut() {
+                if let Some(external_ram) = self
+                    .external_ram
+                    .as_mut()
+                    .filter(|_| self.mbc.ram_enabled())
+                {
                     external_ram.write(address - 0xa000, data)
                 }
             }
             0xc000..=0xcfff => self.wram0.write(address - 0xc000, data),
             0xd000..=0xdfff => self.wram1.write(address - 0xd000, data),
-            0xe000..=0xfdff => self.wram0.write(address - 0xe000, data),
+            0xe000..=0xefff => self.wram0.write(address - 0xe000, data),
+            0xf000..=0xfdff => self.wram1.write(address - 0xf000, data),
             0xfe00..=0xfe9f => self.oam.write(address - 0xfe00, data),
-            0xfea0..=0xfeff => panic!("Write at prohibited memory area [{address}:{data}]"),
+            0xfea0..=0xfeff => self.report(BusDiagnostic::ProhibitedWrite(address, data)),
             0xff00..=0xff7f => self.write_io_regs(address - 0xff00, data),
             0xff80..=0xfffe => self.hram.write(address - 0xff80, data),
-            0xffff => self.ie = data != 0,
+            0xffff => self.ie = data,
         }
     }
 
-    fn read(&self, address: u16) -> u8 {
-        let boot_rom_en = self.boot_rom_en == 0x00;
+    fn read_bus(&self, address: u16) -> u8 {
+        if let Some(data) = self.peripherals.read(address) {
+            return data;
+        }
 
         match address {
-            0x0000..=0x00ff if boot_rom_en => self.boot_rom.read(address),
+            _ if self.boot_rom_mapped(address) => {
+                self.boot_rom.bank(address as usize >> 8)[address as usize & 0xff]
+            }
             0x0000..=0x3fff => self.rom_bank0.read(address),
+            0x4000..=0x7fff if self.mbc.rom_bank() == 0 => self.rom_bank0.read(address - 0x4000),
             0x4000..=0x7fff => self.rom_bank1.read(address - 0x4000),
             0x8000..=0x9fff => self.vram.read(address - 0x8000),
-            0xa000..=0xbfff => {
-                if let Some(external_ram) = self.external_ram.as_ref() {
-                    external_ram.read(address - 0xa000)
-                } else {
-                    0xff
-                }
-            }
+            0xa000..=0xbfff => self
+                .external_ram()
+                .map_or(0xff, |external_ram| external_ram.read(address - 0xa000)),
             0xc000..=0xcfff => self.wram0.read(address - 0xc000),
             0xd000..=0xdfff => self.wram1.read(address - 0xd000),
-            0xe000..=0xfdff => self.wram0.read(address - 0xe000),
+            0xe000..=0xefff => self.wram0.read(address - 0xe000),
+            0xf000..=0xfdff => self.wram1.read(address - 0xf000),
             0xfe00..=0xfe9f => self.oam.read(address - 0xfe00),
-            0xfea0..=0xfeff => panic!("Read at prohibited memory area [{address}]"),
+            0xfea0..=0xfeff => self.read_prohibited_area(address),
             0xff00..=0xff7f => self.read_io_regs(address - 0xff00),
             0xff80..=0xfffe => self.hram.read(address - 0xff80),
-            0xffff => self.ie as u8,
+            0xffff => self.ie,
+        }
+    }
+}
+
+impl MemoryMappedPeripheral for VirtualMemory {
+    fn write(&mut self, address: u16, data: u8) {
+        let page = self.page_table.write_page(address);
+
+        if let Page::Direct {
+            region,
+            bank,
+            offset,
+        } = page
+        {
+            self.region_mut(region, bank)[(offset | (address & 0xff)) as usize] = data;
+        } else if page == Page::HighRam && (0xff80..=0xfffe).contains(&address) {
+            self.hram.write(address - 0xff80, data);
+        } else {
+            self.write_decoded(address, data);
+        }
+    }
+
+    fn read(&self, address: u16) -> u8 {
+        let page = self.page_table.read_page(address);
+
+        if let Page::Direct {
+            region,
+            bank,
+            offset,
+        } = page
+        {
+            self.region(region, bank)[(offset | (address & 0xff)) as usize]
+        } else if page == Page::HighRam && (0xff80..=0xfffe).contains(&address) {
+            self.hram.read(address - 0xff80)
+        } else {
+            self.read_decoded(address)
         }
     }
 }
 
+/// Block reads are meant for tools, so they use the side-effect-free view of the bus and wrap
+/// around at the end of the address space.
 impl ReadBlock for VirtualMemory {
-    fn read_block<const S: usize>(&self, base_address: u16) -> [u8; S] {
-        let mut output = [0xFF; S];
+    fn read_block(&self, base_address: u16, block: &mut [u8]) {
+        for (i, data) in block.iter_mut().enumerate() {
+            *data = self.peek_bus(base_address.wrapping_add(i as u16));
+        }
+    }
+}
+
+/// Debug access: no read side effects, no diagnostics and no blocking by the PPU or OAM DMA.
+impl VirtualMemory {
+    const ROM_BANK_SIZE: usize = 0x4000;
+
+    fn domain_bank(&self, domain: MemoryDomain, offset: usize) -> Option<(&[u8], usize)> {
+        let (bank, offset) = match domain {
+            MemoryDomain::BootRom if offset < self.boot_rom.banks() * 0x100 => {
+                (self.boot_rom.bank(offset >> 8), offset & 0xff)
+            }
+            MemoryDomain::Rom if offset < VirtualMemory::ROM_BANK_SIZE => {
+                (self.rom_bank0.bank(0), offset)
+            }
+            MemoryDomain::Rom => {
+                let bank = offset / VirtualMemory::ROM_BANK_SIZE - 1;
+                if bank >= self.rom_bank1.banks() {
+                    return None;
+                }
+
+                (
+                    self.rom_bank1.bank(bank),
+                    offset % VirtualMemory::ROM_BANK_SIZE,
+                )
+            }
+            MemoryDomain::Vram(bank) if bank < self.vram.banks() => (self.vram.bank(bank), offset),
+            MemoryDomain::Wram(0) => (self.wram0.bank(0), offset),
+            MemoryDomain::Wram(bank) if bank <= self.wram1.banks() => {
+                (self.wram1.bank(bank - 1), offset)
+            }
+            MemoryDomain::Sram(bank) => {
+                let external_ram = self.external_ram.as_ref()?;
+                if bank >= external_ram.banks() {
+                    return None;
+                }
+
+                (external_ram.bank(bank), offset)
+            }
+            MemoryDomain::Oam => (self.oam.bank(0), offset),
+            MemoryDomain::Hram => (self.hram.bank(0), offset),
+            _ => return None,
+        };
+
+        (offset < bank.len()).then_some((bank, offset))
+    }
+
+    fn domain_bank_mut(
+        &mut self,
+        domain: MemoryDomain,
+        offset: usize,
+    ) -> Option<(&mut [u8], usize)> {
+        // Bounds are the same for reads and writes, so checking them immutably first keeps the
+        // mapping logic in one place.
+        self.domain_bank(domain, offset)?;
+
+        let (bank, offset) = match domain {
+            MemoryDomain::BootRom => (self.boot_rom.bank_mut(offset >> 8), offset & 0xff),
+            MemoryDomain::Rom if offset < VirtualMemory::ROM_BANK_SIZE => {
+                (self.rom_bank0.bank_mut(0), offset)
+            }
+            MemoryDomain::Rom => (
+                self.rom_bank1
+                    .bank_mut(offset / VirtualMemory::ROM_BANK_SIZE - 1),
+                offset % VirtualMemory::ROM_BANK_SIZE,
+            ),
+            MemoryDomain::Vram(bank) => (self.vram.bank_mut(bank), offset),
+            MemoryDomain::Wram(0) => (self.wram0.bank_mut(0), offset),
+            MemoryDomain::Wram(bank) => (self.wram1.bank_mut(bank - 1), offset),
+            MemoryDomain::Sram(bank) => (self.external_ram.as_mut()?.bank_mut(bank), offset),
+            MemoryDomain::Oam => (self.oam.bank_mut(0), offset),
+            MemoryDomain::Hram => (self.hram.bank_mut(0), offset),
+            MemoryDomain::Io => return None,
+        };
+
+        Some((bank, offset))
+    }
+
+    pub fn domain_size(&self, domain: MemoryDomain) -> usize {
+        match domain {
+            MemoryDomain::BootRom => self.boot_rom.banks() * 0x100,
+            MemoryDomain::Rom => (self.rom_bank1.banks() + 1) * VirtualMemory::ROM_BANK_SIZE,
+            MemoryDomain::Io => 0x80,
+            domain => self
+                .domain_bank(domain, 0)
+                .map_or(0, |(bank, _)| bank.len()),
+        }
+    }
+
+    pub fn peek(&self, domain: MemoryDomain, offset: usize) -> Option<u8> {
+        if domain == MemoryDomain::Io && offset == 0x00 {
+            // `JoyPad::read` would count this as an input poll.
+            return Some(self.joypad.peek() | self.io_registers.read_mask(0x0000));
+        }
+
+        if domain == MemoryDomain::Io {
+            return match offset {
+                0x00..=0x7f if self.io_registers.is_mapped(offset as u16) => {
+                    Some(self.read_io_regs(offset as u16))
+                }
+                0x00..=0x7f => Some(0xff),
+                _ => None,
+            };
+        }
+
+        self.domain_bank(domain, offset)
+            .map(|(bank, offset)| bank[offset])
+    }
+
+    /// Writes straight into the domain, ROM included. I/O registers are written the way the CPU
+    /// would, since their state can't be separated from their behavior.
+    pub fn poke(&mut self, domain: MemoryDomain, offset: usize, data: u8) -> bool {
+        if domain == MemoryDomain::Io {
+            if offset >= 0x80 {
+                return false;
+            }
+
+            self.write_io_regs(offset as u16, data);
+            return true;
+        }
+
+        let Some((bank, bank_offset)) = self.domain_bank_mut(domain, offset) else {
+            return false;
+        };
+        bank[bank_offset] = data;
+
+        if let Some(detector) = self.uninitialized_reads.as_mut() {
+            detector.write(domain, offset);
+        }
+
+        true
+    }
+
+    pub fn export_domain(&self, domain: MemoryDomain) -> Vec<u8> {
+        (0..self.domain_size(domain))
+            .filter_map(|offset| self.peek(domain, offset))
+            .collect()
+    }
+
+    /// Domain and offset currently mapped at `address` of the CPU memory map.
+    pub fn domain_at(&self, address: u16) -> Option<(MemoryDomain, usize)> {
+        let address_usize = address as usize;
+
+        Some(match address {
+            _ if self.boot_rom_mapped(address) => (MemoryDomain::BootRom, address_usize),
+            0x0000..=0x3fff => (MemoryDomain::Rom, address_usize),
+            0x4000..=0x7fff => (
+                MemoryDomain::Rom,
+                self.mbc.rom_bank() * VirtualMemory::ROM_BANK_SIZE + address_usize - 0x4000,
+            ),
+            0x8000..=0x9fff => (
+                MemoryDomain::Vram(self.vram.actual_bank()),
+                address_usize - 0x8000,
+            ),
+            0xa000..=0xbfff => (
+                MemoryDomain::Sram(self.external_ram.as_ref()?.actual_bank()),
+                address_usize - 0xa000,
+            ),
+            0xc000..=0xcfff => (MemoryDomain::Wram(0), address_usize - 0xc000),
+            0xd000..=0xdfff => (
+                MemoryDomain::Wram(self.wram1.actual_bank() + 1),
+                address_usize - 0xd000,
+            ),
+            0xe000..=0xefff => (MemoryDomain::Wram(0), address_usize - 0xe000),
+            0xf000..=0xfdff => (
+                MemoryDomain::Wram(self.wram1.actual_bank() + 1),
+                address_usize - 0xf000,
+            ),
+            0xfe00..=0xfe9f => (MemoryDomain::Oam, address_usize - 0xfe00),
+            0xff00..=0xff7f => (MemoryDomain::Io, address_usize - 0xff00),
+            0xff80..=0xfffe => (MemoryDomain::Hram, address_usize - 0xff80),
+            _ => return None,
+        })
+    }
+
+    /// Appends all the RAM, the registers and the state of the PPU, APU and timer, for the state
+    /// hash of movies.
+    pub fn hash_state(&self, state: &mut Vec<u8>) {
+        let mut domains = Vec::from([MemoryDomain::Oam, MemoryDomain::Hram, MemoryDomain::Io]);
+        domains.extend((0..self.vram.banks()).map(MemoryDomain::Vram));
+        domains.extend((0..=self.wram1.banks()).map(MemoryDomain::Wram));
+        if let Some(external_ram) = self.external_ram.as_ref() {
+            domains.extend((0..external_ram.banks()).map(MemoryDomain::Sram));
+        }
+
+        for domain in domains {
+            state.extend(self.export_domain(domain));
+        }
+        state.push(self.ie);
+        state.push(self.boot_rom_en);
+        state.push(self.double_speed as u8);
+        state.extend_from_slice(&(self.mbc.rom_bank() as u16).to_le_bytes());
+
+        self.ppu.hash_state(state);
+        self.apu.hash_state(state);
+        self.timer.hash_state(state);
+    }
+
+    /// Reads the CPU memory map the way it is currently banked, without side effects.
+    pub fn peek_bus(&self, address: u16) -> u8 {
+        if let Some(data) = self.peripherals.read(address) {
+            return data;
+        }
+
+        match address {
+            0xfea0..=0xfeff => self.prohibited_area_value(address),
+            0xffff => self.ie,
+            _ => self
+                .domain_at(address)
+                .and_then(|(domain, offset)| self.peek(domain, offset))
+                .unwrap_or(0xff),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::power_on::PowerOnPattern;
+    use crate::watchpoint::{ValueCondition, WatchAction};
+    use alloc::rc::Rc;
+    use alloc::vec;
+    use core::cell::Cell;
+
+    /// 64 KiB MBC5 ROM with 32 KiB of SRAM, each ROM bank starting with its number.
+    fn cartridge() -> Cartridge {
+        let mut rom = vec![0x00; 0x10000];
+        for bank in 0..4 {
+            rom[bank * 0x4000] = bank as u8;
+        }
+        rom[0x0147] = 0x1b;
+        rom[0x0148] = 0x01;
+        rom[0x0149] = 0x03;
+        rom[0x014d] = rom[0x0134..0x014d]
+            .iter()
+            .fold(0u8, |sum, &byte| sum.wrapping_sub(byte).wrapping_sub(1));
+        Cartridge::load(&rom)
+    }
+
+    fn memory(model: Model) -> VirtualMemory {
+        VirtualMemory::with_model(cartridge(), model)
+    }
+
+    #[test]
+    fn echo_ram_follows_svbk() {
+        let mut memory = memory(Model::Cgb);
+        assert_eq!(memory.wram1.banks(), 7);
+
+        for bank in 1..=7 {
+            memory.write(0xff70, bank);
+            memory.write(0xd123, bank * 0x11);
+        }
+        memory.write(0xc456, 0xab);
 
-        for i in 0..S {
-            output[i] = self.read(base_address + i as u16);
+        assert_eq!(memory.read(0xe456), 0xab);
+        for bank in 1..=7 {
+            memory.write(0xff70, bank);
+            assert_eq!(memory.read(0xf123), bank * 0x11);
+            assert_eq!(memory.peek_bus(0xf123), bank * 0x11);
         }
 
-        output
+        memory.write(0xff70, 0x03);
+        memory.write(0xfdff, 0x5a);
+        assert_eq!(memory.read(0xddff), 0x5a);
+        assert_eq!(memory.peek(MemoryDomain::Wram(3), 0x0dff), Some(0x5a));
+    }
+
+    #[test]
+    fn svbk_0_selects_bank_1() {
+        let mut memory = memory(Model::Cgb);
+        memory.write(0xff70, 0x01);
+        memory.write(0xd000, 0x11);
+        memory.write(0xff70, 0x02);
+        memory.write(0xd000, 0x22);
+
+        memory.write(0xff70, 0x00);
+        assert_eq!(memory.read(0xd000), 0x11);
+        assert_eq!(memory.read(0xf000), 0x11);
+        assert_eq!(memory.read(0xff70), 0xf8);
+
+        memory.write(0xff70, 0x0a);
+        assert_eq!(memory.read(0xd000), 0x22);
+    }
+
+    #[test]
+    fn oam_dma_start_up_delay() {
+        let mut memory = memory(Model::Dmg);
+        memory.poke(MemoryDomain::Wram(0), 0x0000, 0x12);
+        memory.poke(MemoryDomain::Oam, 0x0000, 0x34);
+
+        memory.write(0xff46, 0xc0);
+        assert_eq!(memory.read(0xfe00), 0x34);
+        memory.tick();
+        assert_eq!(memory.read(0xfe00), 0x34);
+        assert_eq!(memory.read(0xc000), 0x12);
+
+        memory.tick();
+        assert_eq!(memory.read(0xfe00), 0xff);
+        assert_eq!(memory.peek(MemoryDomain::Oam, 0x0000), Some(0x12));
+
+        for _ in 2..OamDma::LENGTH {
+            memory.tick();
+        }
+        assert_eq!(memory.read(0xfe00), 0xff);
+        memory.tick();
+        assert_eq!(memory.read(0xfe00), 0x12);
+    }
+
+    #[test]
+    fn oam_dma_restarted_mid_transfer() {
+        let mut memory = memory(Model::Dmg);
+        for offset in 0..OamDma::LENGTH as usize {
+            memory.poke(MemoryDomain::Wram(0), offset, 0x11);
+            memory.poke(MemoryDomain::Wram(0), 0x0100 + offset, 0x22);
+        }
+
+        memory.write(0xff46, 0xc0);
+        for _ in 0..11 {
+            memory.tick();
+        }
+        memory.write(0xff46, 0xc1);
+
+        // The first transfer keeps going during the start-up delay of the second one.
+        memory.tick();
+        assert_eq!(memory.read(0xfe00), 0xff);
+        assert_eq!(memory.peek(MemoryDomain::Oam, 10), Some(0x11));
+        assert_eq!(memory.peek(MemoryDomain::Oam, 11), Some(0xff));
+
+        memory.tick();
+        assert_eq!(memory.peek(MemoryDomain::Oam, 0), Some(0x22));
+        assert_eq!(memory.peek(MemoryDomain::Oam, 1), Some(0x11));
+
+        for _ in 0..OamDma::LENGTH {
+            memory.tick();
+        }
+        assert_eq!(memory.export_domain(MemoryDomain::Oam), vec![0x22; 0xa0]);
+    }
+
+    #[test]
+    fn oam_dma_bus_conflicts() {
+        let mut dmg = memory(Model::Dmg);
+        dmg.poke(MemoryDomain::Wram(0), 0x0005, 0x5a);
+        dmg.poke(MemoryDomain::Vram(0), 0x0000, 0x77);
+
+        dmg.write(0xff46, 0xc0);
+        for _ in 0..7 {
+            dmg.tick();
+        }
+
+        // The external bus returns the byte the DMA just read, whatever the CPU asks for.
+        assert_eq!(dmg.read(0x0000), 0x5a);
+        assert_eq!(dmg.read(0xa000), 0x5a);
+        assert_eq!(dmg.read(0xd800), 0x5a);
+        dmg.write(0xc005, 0x00);
+        assert_eq!(dmg.peek(MemoryDomain::Wram(0), 0x0005), Some(0x5a));
+        assert_eq!(dmg.read(0x8000), 0x77);
+
+        // On the CGB, WRAM is on a bus of its own.
+        let mut cgb = memory(Model::Cgb);
+        cgb.poke(MemoryDomain::Wram(0), 0x0000, 0x12);
+        cgb.write(0xff46, 0x00);
+        cgb.tick();
+        cgb.tick();
+
+        assert_eq!(cgb.read(0xc000), 0x12);
+        assert_eq!(cgb.read(0xe000), 0x12);
+        assert_eq!(cgb.read(0x4000), cgb.oam_dma.last_byte());
+        assert_eq!(cgb.read(0x4000), 0x00);
+    }
+
+    #[test]
+    fn hram_is_reachable_during_oam_dma() {
+        let mut memory = memory(Model::Dmg);
+        memory.write(0xff46, 0xc0);
+        memory.tick();
+        memory.tick();
+
+        memory.write(0xff80, 0x42);
+        memory.write(0xfffe, 0x43);
+        memory.write(0xff42, 0x44);
+        memory.write(0xffff, 0x05);
+        assert_eq!(memory.read(0xff80), 0x42);
+        assert_eq!(memory.read(0xfffe), 0x43);
+        assert_eq!(memory.read(0xff42), 0x44);
+        assert_eq!(memory.read(0xffff), 0x05);
+        assert_eq!(memory.read(0xff46), 0xc0);
+    }
+
+    #[test]
+    fn page_table_follows_bank_switches() {
+        let mut memory = memory(Model::Cgb);
+        for bank in 0..4 {
+            memory.poke(MemoryDomain::Sram(bank), 0x0000, 0x10 + bank as u8);
+        }
+        for bank in 0..2 {
+            memory.poke(MemoryDomain::Vram(bank), 0x0000, 0x20 + bank as u8);
+        }
+        for bank in 1..8 {
+            memory.poke(MemoryDomain::Wram(bank), 0x0000, 0x30 + bank as u8);
+        }
+        memory.write(0x0000, 0x0a);
+
+        for bank in [2, 3, 0, 1] {
+            memory.write(0x2000, bank);
+            memory.write(0x4000, bank);
+            memory.write(0xff4f, bank);
+            memory.write(0xff70, bank + 4);
+
+            assert_eq!(memory.read(0x4000), bank);
+            assert_eq!(memory.read(0xa000), 0x10 + bank);
+            assert_eq!(memory.read(0x8000), 0x20 + (bank & 0x01));
+            assert_eq!(memory.read(0xd000), 0x30 + bank + 4);
+            assert_eq!(memory.read(0xf000), 0x30 + bank + 4);
+        }
+
+        // The reads above went through the page table, not the address decoding.
+        for address in [0x4000, 0xa000, 0xd000, 0xf000] {
+            assert!(matches!(
+                memory.page_table.read_page(address),
+                Page::Direct { .. }
+            ));
+        }
+        assert_eq!(memory.page_table.read_page(0x8000), Page::Handler);
+    }
+
+    #[test]
+    fn peek_and_poke_domains() {
+        let mut memory = memory(Model::Cgb);
+
+        assert!(memory.poke(MemoryDomain::Rom, 0xc001, 0x42));
+        assert!(memory.poke(MemoryDomain::Vram(1), 0x1fff, 0x43));
+        assert!(memory.poke(MemoryDomain::Wram(7), 0x0000, 0x44));
+        assert!(memory.poke(MemoryDomain::Sram(3), 0x1fff, 0x45));
+        assert!(memory.poke(MemoryDomain::Hram, 0x7e, 0x46));
+        assert!(!memory.poke(MemoryDomain::Rom, 0x10000, 0x00));
+        assert!(!memory.poke(MemoryDomain::Vram(2), 0x0000, 0x00));
+        assert!(!memory.poke(MemoryDomain::Wram(8), 0x0000, 0x00));
+        assert!(!memory.poke(MemoryDomain::Io, 0x80, 0x00));
+
+        assert_eq!(memory.peek(MemoryDomain::Rom, 0xc001), Some(0x42));
+        assert_eq!(memory.peek(MemoryDomain::Vram(1), 0x1fff), Some(0x43));
+        assert_eq!(memory.peek(MemoryDomain::Wram(7), 0x0000), Some(0x44));
+        assert_eq!(memory.peek(MemoryDomain::Sram(3), 0x1fff), Some(0x45));
+        assert_eq!(memory.peek(MemoryDomain::Hram, 0x7e), Some(0x46));
+        assert_eq!(memory.peek(MemoryDomain::Oam, 0xa0), None);
+
+        // The CPU sees the poked bytes once their banks are mapped.
+        memory.write(0x2000, 0x03);
+        memory.write(0x0000, 0x0a);
+        memory.write(0x4000, 0x03);
+        memory.write(0xff4f, 0x01);
+        memory.write(0xff70, 0x07);
+        assert_eq!(memory.read(0x4001), 0x42);
+        assert_eq!(memory.read(0x9fff), 0x43);
+        assert_eq!(memory.read(0xd000), 0x44);
+        assert_eq!(memory.read(0xbfff), 0x45);
+        assert_eq!(memory.read(0xfffe), 0x46);
+
+        // I/O registers are written the way the CPU would.
+        assert!(memory.poke(MemoryDomain::Io, 0x70, 0x02));
+        assert_eq!(memory.read(0xd000), 0xff);
+        assert_eq!(memory.peek(MemoryDomain::Io, 0x70), Some(0xfa));
+    }
+
+    #[test]
+    fn export_domain_sizes() {
+        let mut memory = memory(Model::Cgb);
+        memory.poke(MemoryDomain::Oam, 0x9f, 0x12);
+
+        let oam = memory.export_domain(MemoryDomain::Oam);
+        assert_eq!(oam.len(), 0xa0);
+        assert_eq!(oam[0x9f], 0x12);
+        assert_eq!(memory.export_domain(MemoryDomain::Rom).len(), 0x10000);
+        assert_eq!(memory.export_domain(MemoryDomain::Rom)[0xc000], 0x03);
+        assert_eq!(memory.export_domain(MemoryDomain::Vram(1)).len(), 0x2000);
+        assert_eq!(memory.export_domain(MemoryDomain::Wram(7)).len(), 0x1000);
+        assert_eq!(memory.export_domain(MemoryDomain::Sram(3)).len(), 0x2000);
+        assert_eq!(memory.export_domain(MemoryDomain::Io).len(), 0x80);
+        assert!(memory.export_domain(MemoryDomain::Wram(8)).is_empty());
+    }
+
+    #[test]
+    fn peeks_ignore_blocking() {
+        let mut memory = memory(Model::Dmg);
+        memory.poke(MemoryDomain::Oam, 0x00, 0x12);
+        memory.write(0xff40, 0x80);
+        memory.tick();
+
+        assert_eq!(memory.read(0xfe00), 0xff);
+        assert_eq!(memory.peek(MemoryDomain::Oam, 0x00), Some(0x12));
+        assert_eq!(memory.peek_bus(0xfe00), 0x12);
+    }
+
+    #[test]
+    fn read_block_wraps() {
+        let mut memory = memory(Model::Dmg);
+        memory.write(0xfffe, 0x12);
+        memory.write(0xffff, 0x1f);
+
+        let mut block = [0x00; 5];
+        memory.read_block(0xfffd, &mut block);
+        assert_eq!(block, [0xff, 0x12, 0x1f, 0x00, 0x00]);
+
+        memory.read_block(0x3fff, &mut block);
+        assert_eq!(block, [0x00, 0x01, 0x00, 0x00, 0x00]);
+    }
+
+    #[test]
+    fn peeks_have_no_side_effects() {
+        let mut memory = memory(Model::Dmg);
+        memory.add_watchpoint(Watchpoint::new(Access::Read, 0xc000..=0xc0ff));
+        memory.add_watchpoint(Watchpoint::new(Access::Read, 0xff00..=0xff00));
+        memory.write(0xff00, 0x10);
+
+        let mut block = [0x00; 0x100];
+        memory.read_block(0xc000, &mut block);
+        memory.read_block(0xff00, &mut block);
+        memory.peek_bus(0xff00);
+        memory.peek(MemoryDomain::Io, 0x00);
+        memory.export_domain(MemoryDomain::Io);
+        memory.export_domain(MemoryDomain::Wram(0));
+
+        assert_eq!(memory.take_watch_hit(), None);
+        assert!(memory.end_frame());
+        assert_eq!(memory.input_polls(), Some(0));
+
+        memory.read(0xff00);
+        assert!(memory.take_watch_hit().is_some());
+        assert!(!memory.end_frame());
+        assert_eq!(memory.input_polls(), Some(1));
+    }
+
+    #[test]
+    fn opcode_fetches_only_check_execute_watchpoints() {
+        let mut memory = memory(Model::Dmg);
+        memory.add_watchpoint(Watchpoint::new(Access::Read, 0x0100..=0x01ff));
+
+        assert_eq!(memory.fetch_opcode(0x0150), 0x00);
+        assert_eq!(memory.take_watch_hit(), None);
+
+        let execute = memory.add_watchpoint(Watchpoint::new(Access::Execute, 0x0150..=0x0150));
+        memory.fetch_opcode(0x0150);
+        let hit = memory.take_watch_hit().unwrap();
+        assert_eq!(
+            (hit.id, hit.access, hit.pc),
+            (execute, Access::Execute, 0x0150)
+        );
+
+        memory.read(0x0151);
+        assert_eq!(memory.take_watch_hit().unwrap().access, Access::Read);
+    }
+
+    #[test]
+    fn bank_qualified_watchpoints() {
+        let mut memory = memory(Model::Cgb);
+        let rom =
+            memory.add_watchpoint(Watchpoint::new(Access::Read, 0x4000..=0x7fff).with_bank(2));
+        let wram =
+            memory.add_watchpoint(Watchpoint::new(Access::Write, 0xd000..=0xdfff).with_bank(3));
+
+        memory.read(0x4000);
+        memory.write(0xd000, 0x00);
+        assert_eq!(memory.take_watch_hit(), None);
+
+        memory.write(0x2000, 0x02);
+        memory.write(0xff70, 0x03);
+        assert_eq!(memory.take_watch_hit(), None);
+
+        memory.set_pc(0x1234);
+        assert_eq!(memory.read(0x4000), 0x02);
+        assert_eq!(
+            memory.take_watch_hit(),
+            Some(WatchHit {
+                id: rom,
+                access: Access::Read,
+                address: 0x4000,
+                bank: Some(2),
+                data: 0x02,
+                pc: 0x1234,
+            })
+        );
+
+        memory.write(0xd123, 0x56);
+        let hit = memory.take_watch_hit().unwrap();
+        assert_eq!((hit.id, hit.address, hit.bank), (wram, 0xd123, Some(3)));
+    }
+
+    #[test]
+    fn conditional_watchpoints() {
+        let mut memory = memory(Model::Dmg);
+        memory.add_watchpoint(
+            Watchpoint::new(Access::Write, 0xc000..=0xc000)
+                .with_condition(ValueCondition::Equal(0x42)),
+        );
+        memory.add_watchpoint(
+            Watchpoint::new(Access::Write, 0xc001..=0xc001).with_condition(
+                ValueCondition::Masked {
+                    mask: 0xf0,
+                    value: 0x80,
+                },
+            ),
+        );
+
+        memory.write(0xc000, 0x41);
+        memory.write(0xc001, 0x7f);
+        memory.write(0xc001, 0x08);
+        assert_eq!(memory.take_watch_hit(), None);
+
+        memory.write(0xc000, 0x42);
+        assert_eq!(memory.take_watch_hit().unwrap().data, 0x42);
+        memory.write(0xc001, 0x8c);
+        assert_eq!(memory.take_watch_hit().unwrap().data, 0x8c);
+    }
+
+    #[test]
+    fn watchpoint_callbacks_dont_pause() {
+        let hits = Rc::new(Cell::new(0));
+        let mut memory = memory(Model::Dmg);
+        let counter = hits.clone();
+        let id =
+            memory.add_watchpoint(Watchpoint::new(Access::Write, 0xff80..=0xfffe).with_action(
+                WatchAction::Callback(Box::new(move |_| counter.set(counter.get() + 1))),
+            ));
+
+        memory.write(0xff80, 0x01);
+        memory.write(0xfffe, 0x01);
+        memory.write(0xc000, 0x01);
+        assert_eq!(hits.get(), 2);
+        assert_eq!(memory.take_watch_hit(), None);
+
+        assert!(memory.remove_watchpoint(id));
+        memory.write(0xff80, 0x01);
+        assert_eq!(hits.get(), 2);
+        assert_eq!(memory.read(0xff80), 0x01);
+    }
+
+    #[test]
+    fn power_on_fills_every_bank() {
+        let settings = PowerOnSettings {
+            seed: 42,
+            vram: PowerOnPattern::Random,
+            wram: PowerOnPattern::Random,
+            hram: PowerOnPattern::Zeros,
+            ..PowerOnSettings::default()
+        };
+
+        for (model, vram_banks, wram_banks) in [(Model::Dmg, 1, 2), (Model::Cgb, 2, 8)] {
+            let memory = VirtualMemory::with_power_on(cartridge(), model, settings);
+            let again = VirtualMemory::with_power_on(cartridge(), model, settings);
+            assert_eq!(memory.power_on_settings(), settings);
+
+            let mut expected = settings.bytes(PowerOnRegion::Wram);
+            for bank in 0..wram_banks {
+                let wram = memory.export_domain(MemoryDomain::Wram(bank));
+                assert!(wram.iter().all(|&data| data == expected()));
+                assert_eq!(wram, again.export_domain(MemoryDomain::Wram(bank)));
+            }
+
+            let mut expected = settings.bytes(PowerOnRegion::Vram);
+            for bank in 0..vram_banks {
+                let vram = memory.export_domain(MemoryDomain::Vram(bank));
+                assert!(vram.iter().all(|&data| data == expected()));
+            }
+
+            assert_eq!(memory.export_domain(MemoryDomain::Hram), vec![0x00; 0x7f]);
+            assert_eq!(memory.export_domain(MemoryDomain::Oam), vec![0xff; 0xa0]);
+            assert_eq!(
+                memory.export_domain(MemoryDomain::Sram(3)),
+                vec![0xff; 0x2000]
+            );
+        }
+
+        let other_seed = PowerOnSettings {
+            seed: 43,
+            ..settings
+        };
+        assert_ne!(
+            VirtualMemory::with_power_on(cartridge(), Model::Dmg, settings)
+                .export_domain(MemoryDomain::Wram(0)),
+            VirtualMemory::with_power_on(cartridge(), Model::Dmg, other_seed)
+                .export_domain(MemoryDomain::Wram(0))
+        );
+    }
+
+    #[test]
+    fn uninitialized_reads_through_the_bus() {
+        let reads = Rc::new(Cell::new(0));
+        let counter = reads.clone();
+        let mut memory = memory(Model::Cgb);
+        memory.set_uninitialized_read_detector(Some(UninitializedReadDetector::new(Box::new(
+            move |_| counter.set(counter.get() + 1),
+        ))));
+
+        memory.write(0xc000, 0x01);
+        memory.write(0xff70, 0x02);
+        memory.write(0xd000, 0x01);
+        memory.write(0xff90, 0x01);
+        memory.read(0xc000);
+        memory.read(0xe000);
+        memory.read(0xf000);
+        memory.read(0xff90);
+        memory.peek_bus(0xc001);
+        assert_eq!(reads.get(), 0);
+
+        memory.read(0xc001);
+        memory.read(0xe001);
+        memory.write(0xff70, 0x03);
+        memory.read(0xd000);
+        memory.read(0xff91);
+        assert_eq!(reads.get(), 4);
+    }
+
+    #[test]
+    fn end_frame_counts_lag_frames() {
+        let mut memory = memory(Model::Dmg);
+        assert!(!memory.was_lag_frame());
+        assert_eq!(memory.input_polls(), None);
+
+        // Reads without a button row selected don't count as polls.
+        memory.write(0xff00, 0x30);
+        memory.read(0xff00);
+        assert!(memory.end_frame());
+        assert!(memory.was_lag_frame());
+
+        memory.write(0xff00, 0x20);
+        memory.read(0xff00);
+        memory.read(0xff00);
+        assert!(!memory.end_frame());
+        assert!(!memory.was_lag_frame());
+        assert_eq!(memory.input_polls(), Some(2));
+
+        assert!(memory.end_frame());
+        assert_eq!(memory.lag_frames(), 2);
+
+        // VBlank ends the frame.
+        memory.write(0xff40, 0x80);
+        memory.read(0xff00);
+        while !memory.take_frame_ready() {
+            memory.tick();
+        }
+        assert!(!memory.was_lag_frame());
+        while !memory.take_frame_ready() {
+            memory.tick();
+        }
+        assert!(memory.was_lag_frame());
+        assert_eq!(memory.lag_frames(), 3);
+    }
+
+    #[test]
+    fn prohibited_area_reads() {
+        let dmg = memory(Model::Dmg);
+        let cgb = memory(Model::Cgb);
+
+        for address in 0xfea0..=0xfeff {
+            let nibble = ((address & 0xf0) >> 4) as u8;
+
+            assert_eq!(dmg.read(address), 0x00);
+            assert_eq!(cgb.read(address), nibble << 4 | nibble);
+            assert_eq!(cgb.peek_bus(address), nibble << 4 | nibble);
+        }
+        assert_eq!(cgb.read(0xfeb7), 0xbb);
+    }
+
+    #[test]
+    fn prohibited_area_is_blocked_during_oam_scan() {
+        for model in [Model::Dmg, Model::Cgb] {
+            let mut memory = memory(model);
+            memory.write(0xff40, 0x80);
+            memory.tick();
+
+            assert_eq!(memory.ppu.mode(), PpuMode::OamScan);
+            assert_eq!(memory.read(0xfea0), 0xff);
+            assert_eq!(memory.read(0xfef0), 0xff);
+        }
     }
 }
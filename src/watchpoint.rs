@@ -0,0 +1,215 @@
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::cell::Cell;
+use core::ops::RangeInclusive;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+    /// Opcode fetch by the CPU.
+    Execute,
+}
+
+/// Condition on the byte read, written or fetched.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValueCondition {
+    Equal(u8),
+    NotEqual(u8),
+    /// Matches when `data & mask == value`.
+    Masked {
+        mask: u8,
+        value: u8,
+    },
+}
+
+impl ValueCondition {
+    pub fn matches(&self, data: u8) -> bool {
+        match *self {
+            ValueCondition::Equal(value) => data == value,
+            ValueCondition::NotEqual(value) => data != value,
+            ValueCondition::Masked { mask, value } => data & mask == value,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WatchpointId(usize);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WatchHit {
+    pub id: WatchpointId,
+    pub access: Access,
+    pub address: u16,
+    /// Bank mapped at `address` when it was accessed, for banked areas.
+    pub bank: Option<usize>,
+    pub data: u8,
+    /// Address of the instruction that caused the access.
+    pub pc: u16,
+}
+
+pub enum WatchAction {
+    /// Stop emulation after the current instruction.
+    Pause,
+    Callback(Box<dyn Fn(WatchHit)>),
+}
+
+pub struct Watchpoint {
+    access: Access,
+    range: RangeInclusive<u16>,
+    bank: Option<usize>,
+    condition: Option<ValueCondition>,
+    action: WatchAction,
+}
+
+impl Watchpoint {
+    /// Pauses on any `access` to `range`, whatever bank is mapped there.
+    pub fn new(access: Access, range: RangeInclusive<u16>) -> Self {
+        Self {
+            access,
+            range,
+            bank: None,
+            condition: None,
+            action: WatchAction::Pause,
+        }
+    }
+
+    /// Only triggers while `bank` is mapped, for the switchable ROM, VRAM, SRAM and WRAM areas.
+    /// ROM banks count from the start of the cartridge, so bank 0 is the fixed one.
+    pub fn with_bank(mut self, bank: usize) -> Self {
+        self.bank = Some(bank);
+        self
+    }
+
+    pub fn with_condition(mut self, condition: ValueCondition) -> Self {
+        self.condition = Some(condition);
+        self
+    }
+
+    pub fn with_action(mut self, action: WatchAction) -> Self {
+        self.action = action;
+        self
+    }
+
+    fn matches(&self, access: Access, address: u16, bank: Option<usize>, data: u8) -> bool {
+        self.access == access
+            && self.range.contains(&address)
+            && self.bank.is_none_or(|watched| bank == Some(watched))
+            && self
+                .condition
+                .is_none_or(|condition| condition.matches(data))
+    }
+}
+
+/// Watchpoints attached to the memory map. Pages holding a read or write watchpoint are taken
+/// off the page table fast path, so the checks cost nothing elsewhere.
+pub struct Watchpoints {
+    entries: Vec<(WatchpointId, Watchpoint)>,
+    read_pages: [bool; 0x100],
+    write_pages: [bool; 0x100],
+    execute_pages: [bool; 0x100],
+    next_id: usize,
+    pending_pause: Cell<Option<WatchHit>>,
+}
+
+impl Default for Watchpoints {
+    fn default() -> Self {
+        Self {
+            entries: Vec::new(),
+            read_pages: [false; 0x100],
+            write_pages: [false; 0x100],
+            execute_pages: [false; 0x100],
+            next_id: 0,
+            pending_pause: Cell::new(None),
+        }
+    }
+}
+
+impl Watchpoints {
+    pub fn add(&mut self, watchpoint: Watchpoint) -> WatchpointId {
+        let id = WatchpointId(self.next_id);
+        self.next_id += 1;
+
+        self.entries.push((id, watchpoint));
+        self.update_pages();
+
+        id
+    }
+
+    pub fn remove(&mut self, id: WatchpointId) -> bool {
+        let Some(index) = self.entries.iter().position(|(entry, _)| *entry == id) else {
+            return false;
+        };
+
+        self.entries.remove(index);
+        self.update_pages();
+
+        true
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.update_pages();
+    }
+
+    fn update_pages(&mut self) {
+        self.read_pages = [false; 0x100];
+        self.write_pages = [false; 0x100];
+        self.execute_pages = [false; 0x100];
+
+        for (_, watchpoint) in &self.entries {
+            let pages = match watchpoint.access {
+                Access::Read => &mut self.read_pages,
+                Access::Write => &mut self.write_pages,
+                Access::Execute => &mut self.execute_pages,
+            };
+
+            for page in (watchpoint.range.start() >> 8)..=(watchpoint.range.end() >> 8) {
+                pages[page as usize] = true;
+            }
+        }
+    }
+
+    /// Cheap check done before resolving the bank and running the full match.
+    pub fn watches(&self, access: Access, address: u16) -> bool {
+        let pages = match access {
+            Access::Read => &self.read_pages,
+            Access::Write => &self.write_pages,
+            Access::Execute => &self.execute_pages,
+        };
+
+        pages[(address >> 8) as usize]
+    }
+
+    pub fn check(&self, access: Access, address: u16, bank: Option<usize>, data: u8, pc: u16) {
+        for (id, watchpoint) in &self.entries {
+            if !watchpoint.matches(access, address, bank, data) {
+                continue;
+            }
+
+            let hit = WatchHit {
+                id: *id,
+                access,
+                address,
+                bank,
+                data,
+                pc,
+            };
+
+            match &watchpoint.action {
+                WatchAction::Pause => {
+                    // Keep the first hit, which is the one that stopped emulation.
+                    if self.pending_pause.get().is_none() {
+                        self.pending_pause.set(Some(hit));
+                    }
+                }
+                WatchAction::Callback(callback) => callback(hit),
+            }
+        }
+    }
+
+    /// Hit that asked emulation to pause, if any. Taking it resumes.
+    pub fn take_pause(&self) -> Option<WatchHit> {
+        self.pending_pause.take()
+    }
+}